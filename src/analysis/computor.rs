@@ -1,74 +1,18 @@
 use crate::{
+    analysis::rolling::{average_price, calculate_slope, scoped_tickers, MovingAggregator},
     vo::{
-        biz::{Protfolio, SlopeLine, Ticker, TimeUnit},
-        core::LockTradeInfo,
+        biz::{BarType, Protfolio, SlopeLine, Ticker, TimeUnit},
+        core::{LockTradeInfo, Retention},
     },
     Result,
 };
 use chrono::{TimeZone, Utc};
 use log::{debug, log_enabled, trace};
-use rayon::prelude::*;
 use std::{
     collections::{BTreeMap, LinkedList},
-    f64::NAN,
+    sync::Mutex,
 };
 
-// Calculate slope for nearest line
-// Reference to doc/trend.md
-fn calculate_slope(samples: &Vec<(f64, f64)>) -> (f64, f64) {
-    match samples.len() {
-        0 => (NAN, NAN),
-        1 => {
-            let (_, y) = samples.first().unwrap();
-            (0.0, *y)
-        }
-        2 => {
-            let (x_1, y_1) = samples.first().unwrap();
-            let (x_2, y_2) = samples.last().unwrap();
-
-            // same timestamp
-            if x_1 == x_2 {
-                if y_1 == y_2 {
-                    return (0.0, *y_1);
-                } else {
-                    return (0.0, (y_1 + y_2) / 2.0);
-                }
-            }
-
-            // y = Ax + B
-            // -> y_1 = Ax_1 + B, y_2 = Ax_2 + B
-            let a = (y_1 - y_2) / (x_1 - x_2);
-            let b = y_1 - a * x_1;
-
-            (a, b)
-        }
-        _ => {
-            let count = samples.len() as f64;
-            let x_avg: f64 = samples.iter().map(|(x, _)| *x / count).sum();
-            let y_avg: f64 = samples.iter().map(|(_, y)| *y / count).sum();
-
-            let xy: f64 = samples
-                .iter()
-                .map(|(x, y)| (*x - x_avg) * (*y - y_avg))
-                .sum();
-
-            let x_x: f64 = samples
-                .iter()
-                .map(|(x, _)| (*x - x_avg) * (*x - x_avg))
-                .sum();
-
-            let a = xy / x_x;
-            let b = y_avg - a * x_avg;
-
-            // A = 2, y = Ax + B
-            // 2 = 2 * 1 + B, (x = 1, y = 2)
-            // 4 = 2 * 2 + B, (x = 2, y = 4)
-            // A = slope = delta-Y / delta-X = (4 - 2) / (2 - 1)
-            (a, b)
-        }
-    }
-}
-
 fn group_by(
     mut map: BTreeMap<i64, Vec<Protfolio>>,
     protfolio: Protfolio,
@@ -103,12 +47,8 @@ fn calculate(values: &Vec<Protfolio>) -> Protfolio {
         .reduce(f32::min)
         .unwrap();
 
-    // Calculate average price
-    let price_sum: f64 = values
-        .par_iter()
-        .map(|protfolio| protfolio.price as f64)
-        .sum();
-    let price_avg: f32 = (price_sum / values.len() as f64) as f32;
+    // Calculate average price, same as rolling aggregation
+    let price_avg: f32 = average_price(values.iter().map(|protfolio| protfolio.price));
 
     let volume = first.volume - last.volume; // FIXME : lack of the volume of first item
 
@@ -161,12 +101,11 @@ fn aggregate_fixed_unit(
     unit: &TimeUnit,
     tickers: &LinkedList<Ticker>,
     protfolios: &mut LinkedList<Protfolio>,
+    retention: Option<Retention>,
     _trade: LockTradeInfo,
 ) -> Result<()> {
-    // Take source data in 3x time range
-    let scope = unit.duration as i64 * 1000 * 3;
     // Use latest ticker time to restrict time
-    let min_time = tickers.front().unwrap().time - scope;
+    let min_time = tickers.front().unwrap().time - unit.window();
 
     if log_enabled!(log::Level::Debug) {
         let count = tickers.iter().take_while(|t| t.time >= min_time).count();
@@ -207,6 +146,14 @@ fn aggregate_fixed_unit(
         update(target, protfolios)?;
     }
 
+    // drop protfolios out of retention, oldest at back
+    if let Some(retention) = retention {
+        let min_time = tickers.front().unwrap().time - retention.window;
+        while matches!(protfolios.back(), Some(p) if p.unit_time < min_time) {
+            protfolios.pop_back();
+        }
+    }
+
     // No update for trade info, only moving data is used
 
     Ok(())
@@ -217,32 +164,25 @@ fn aggregate_moving_unit(
     unit: &TimeUnit,
     tickers: &LinkedList<Ticker>,
     protfolios: &mut LinkedList<Protfolio>,
+    aggregator: Option<&Mutex<MovingAggregator>>,
     trade: LockTradeInfo,
 ) -> Result<()> {
-    let last_timestamp = tickers.front().unwrap().time;
-    let scope = last_timestamp - (unit.duration as i64 * unit.period as i64) * 1000;
-
-    if log_enabled!(log::Level::Debug) {
-        let count = tickers.iter().take_while(|t| t.time > scope).count();
-        debug!(
-            "Aggreate moving protoflio for {} of {}, period: {}, data size: {}, last_timestamp: {}, scope: {}",
-            symbol, unit.duration,unit.period, count, Utc.timestamp_millis(last_timestamp).to_rfc3339(), Utc.timestamp_millis(scope).to_rfc3339()
-        );
-    }
-
-    // calculate
-    let results = tickers
-        .iter()
-        .take_while(|t| t.time > scope) // only take items in range
-        .map(|ticker| Protfolio::moving(ticker, unit, last_timestamp))
-        .fold(
-            BTreeMap::new(),
-            |map: BTreeMap<i64, Vec<Protfolio>>, protfolios| group_by(map, protfolios),
-        )
-        .values()
-        .map(|values| calculate(values))
-        .rev() // sort by time desc
-        .collect::<Vec<Protfolio>>();
+    // incremental aggregation, full recomputation when not available
+    let results = match aggregator {
+        Some(lock) => {
+            let mut aggregator = lock.lock().unwrap();
+            if aggregator.sync(tickers) {
+                aggregator.protfolios()
+            } else {
+                debug!(
+                    "Tickers of {} out of order, recompute {} in full",
+                    symbol, unit.name
+                );
+                calculate_moving_unit(symbol, unit, tickers)
+            }
+        }
+        None => calculate_moving_unit(symbol, unit, tickers),
+    };
 
     // update protfolio, renew all records
     let result_size = results.len();
@@ -271,6 +211,58 @@ fn aggregate_moving_unit(
     Ok(())
 }
 
+fn calculate_moving_unit(
+    symbol: &str,
+    unit: &TimeUnit,
+    tickers: &LinkedList<Ticker>,
+) -> Vec<Protfolio> {
+    let last_timestamp = tickers.front().unwrap().time;
+    let scoped = scoped_tickers(unit, tickers);
+
+    debug!(
+        "Aggreate moving protoflio for {} of {}, period: {}, data size: {}, last_timestamp: {}",
+        symbol,
+        unit.name,
+        unit.period,
+        scoped.len(),
+        Utc.timestamp_millis_opt(last_timestamp)
+            .unwrap()
+            .to_rfc3339()
+    );
+
+    // calculate
+    match unit.bar {
+        BarType::Time => scoped
+            .into_iter()
+            .map(|ticker| Protfolio::moving(ticker, unit, last_timestamp))
+            .fold(
+                BTreeMap::new(),
+                |map: BTreeMap<i64, Vec<Protfolio>>, protfolios| group_by(map, protfolios),
+            )
+            .values()
+            .map(|values| calculate(values))
+            .rev() // sort by time desc
+            .collect::<Vec<Protfolio>>(),
+        _ => {
+            // bucket by tick count or traded volume after the ticker, ends at the latest ticker
+            let size = unit.bar_size().max(1);
+            let mut groups: BTreeMap<i64, Vec<Protfolio>> = BTreeMap::new();
+            let mut distance = 0;
+            for ticker in scoped {
+                groups
+                    .entry(distance / size)
+                    .or_default()
+                    .push(Protfolio::moving(ticker, unit, ticker.time));
+                distance += match unit.bar {
+                    BarType::Volume => ticker.volume.unwrap_or(0),
+                    _ => 1,
+                };
+            }
+            groups.values().map(calculate).collect::<Vec<Protfolio>>()
+        }
+    }
+}
+
 pub fn draw_slop_lines(protfolios: &Vec<Protfolio>) -> Vec<SlopeLine> {
     let unit = &protfolios.first().unwrap().unit;
 
@@ -387,22 +379,30 @@ impl TimeUnit {
     pub fn rebalance(
         &self,
         symbol: &str,
-        message_id: i64,
         tickers: &LinkedList<Ticker>,
         protfolios: &mut LinkedList<Protfolio>,
+        aggregator: Option<&Mutex<MovingAggregator>>,
+        retention: Option<Retention>,
         trade: LockTradeInfo,
     ) -> Result<()> {
         debug!(
             "Rebalance {} of {}, message_id: {}, ticker count: {}",
             symbol,
             self.duration,
-            &message_id,
+            trade.read().unwrap().message_id,
             &tickers.len()
         );
         if self.period == 0 {
-            aggregate_fixed_unit(symbol, self, tickers, protfolios, trade)?;
+            if self.bar != BarType::Time {
+                return Err(format!(
+                    "{:?} bar of unit {} must be moving, period is required",
+                    self.bar, self.name
+                )
+                .into());
+            }
+            aggregate_fixed_unit(symbol, self, tickers, protfolios, retention, trade)?;
         } else {
-            aggregate_moving_unit(symbol, self, tickers, protfolios, trade)?;
+            aggregate_moving_unit(symbol, self, tickers, protfolios, aggregator, trade)?;
         }
         Ok(())
     }
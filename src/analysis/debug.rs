@@ -1,10 +1,14 @@
-use super::trade::{
-    find_max_price, find_min_price, get_min_duration, rebound, rebound_all, validate_audit_rule,
+use super::{
+    metrics::{equity_curve, evaluate_risk},
+    trade::{find_max_price, find_min_price, rebound, rebound_all, validate_audit_rule},
 };
 use crate::{
     vo::{
-        biz::{AuditState, MarketHoursType, Order, OrderStatus, TradeInfo, Trend},
-        core::{AppConfig, AssetContext, AuditMode, KEY_EXTRA_CONFIG_FILE_PATH},
+        biz::{
+            AuditState, BacktestReport, EquityPoint, MarketHoursType, Order, OrderRow, OrderStatus,
+            PairSummary, RiskMetrics, RuleSummary, Ticker, TradeInfo, Trend,
+        },
+        core::{AppConfig, AssetContext, AuditMode},
     },
     Result,
 };
@@ -23,46 +27,107 @@ use std::{
     sync::Arc,
 };
 
-pub fn profit_evaluate(asset: Arc<AssetContext>, config: Arc<AppConfig>) -> Result<bool> {
+// Evaluate profit of placed orders, return None until all markets of ordered symbols closed
+pub fn profit_evaluate(
+    asset: Arc<AssetContext>,
+    config: Arc<AppConfig>,
+) -> Result<Option<BacktestReport>> {
     // find all orders
     let lock = asset.orders();
     let readers = lock.read().unwrap();
-    let symbols: HashSet<String> = readers.iter().map(|o| o.symbol.to_string()).collect();
+    let shadow_lock = asset.shadow_orders();
+    let shadow_readers = shadow_lock.read().unwrap();
+    let symbols: HashSet<String> = readers
+        .iter()
+        .chain(shadow_readers.iter())
+        .map(|o| o.symbol.to_string())
+        .collect();
 
     // check all regular market closed
     for symbol in &symbols {
         if let Some(market) = asset.get_current_market(&symbol) {
             match market {
                 MarketHoursType::PostMarket => {}
-                _ => return Ok(false),
+                _ => return Ok(None),
             }
         }
     }
 
-    let close_prices: HashMap<String, f32> = symbols
+    let close_tickers: Vec<Ticker> = symbols
         .iter()
         .map(|symbol| asset.get_first_post_ticker(symbol).unwrap())
+        .collect();
+    let close_prices: HashMap<String, f32> = close_tickers
+        .iter()
         .map(|ticker| (ticker.id.clone(), ticker.price))
         .collect();
+    let close_time = close_tickers.iter().map(|t| t.time).max().unwrap_or(0);
+    let start_time = asset.get_regular_start_time();
 
     info!("####################################################################################################");
     info!("####################################################################################################");
 
     // estimate profit
-    let mut total_amount = 0.0;
-    let mut total_profit = 0.0;
-    let mut loss_order = 0;
+    let mut rows: Vec<OrderRow> = Vec::new();
     let mut pairs: BTreeMap<String, Vec<Order>> = BTreeMap::new();
     let mut formula: Vec<String> = Vec::new();
+    let config_sha = get_config_sha(Arc::clone(&config));
+
+    // settle hypothetical orders of evaluation-only rules
+    let shadow_orders: Vec<OrderRow> = shadow_readers
+        .iter()
+        .rev()
+        .map(|order| {
+            let date = Utc
+                .timestamp_millis_opt(order.created_time)
+                .unwrap()
+                .format("%Y-%m-%d")
+                .to_string();
+            order_row(
+                &config,
+                order,
+                &date,
+                *close_prices.get(&order.symbol).unwrap(),
+            )
+        })
+        .collect();
+    let shadows: Vec<RuleSummary> = RuleSummary::attribute(&shadow_orders)
+        .into_iter()
+        .map(|summary| RuleSummary {
+            evaluation: true,
+            ..summary
+        })
+        .collect();
 
     let lock = asset.orders();
     let readers = lock.read().unwrap();
 
     if readers.is_empty() {
+        print_rules("shadow", &shadows);
         info!("No available order listed");
-        return Ok(true);
+        return Ok(Some(BacktestReport {
+            source: String::new(),
+            config_sha: config_sha.clone(),
+            config: config.as_ref().clone(),
+            orders: Vec::new(),
+            pairs: Vec::new(),
+            days: vec![PairSummary {
+                config_sha,
+                ..Default::default()
+            }],
+            equity: Vec::new(),
+            metrics: Vec::new(),
+            rules: Vec::new(),
+            shadow_orders,
+            shadows,
+        }));
     }
 
+    let time = Utc
+        .timestamp_millis_opt(readers.front().unwrap().created_time)
+        .unwrap();
+    let date = time.format("%Y-%m-%d").to_string();
+
     let print_orders = false;
     for order in readers.iter().rev() {
         let post_market_price = *close_prices.get(&order.symbol).unwrap();
@@ -84,12 +149,7 @@ pub fn profit_evaluate(asset: Arc<AssetContext>, config: Arc<AppConfig>) -> Resu
             order.symbol, order.created_price, order.created_volume
         ));
 
-        // FIXME: use accepted
-        total_amount += order.created_price * order.created_volume as f32;
-        total_profit += profit;
-        if matches!(order.audit, AuditState::LossClear) {
-            loss_order += 1;
-        }
+        rows.push(order_row(&config, order, &date, post_market_price));
 
         if let Some(constraint) = &order.constraint_id {
             let constraint_id = constraint.to_string();
@@ -148,38 +208,39 @@ pub fn profit_evaluate(asset: Arc<AssetContext>, config: Arc<AppConfig>) -> Resu
 
     info!("formula = {}", formula.join(" + "));
 
-    let time = Utc.timestamp_millis(readers.front().unwrap().created_time);
-
     info!("closed prices {:?}", close_prices,);
     info!(
         "total profit: {}",
         calculate_total_profit(&formula.join(" + "), &close_prices)
     );
-    info!(
-        "| {symbol:<10} | {date:<10} | {order_count:>11} | {loss_order_count:>11} | {loss_order_rate:<16} | {total_amount:<12} | {total_profit:<12} | {profit_rate:<10} | {sha:<40} |",
-        symbol = "Symbols",
-        date = "Date",
-        order_count = "Order count",
-        loss_order_count = "Loss orders",
-        loss_order_rate = "Loss orders (%s)",
-        total_amount = "Total amount",
-        total_profit = "PnL",
-        profit_rate = "PnL (%s)",
-        sha = "Config SHA"
-    );
-    info!("|------------|------------|-------------|-------------|------------------|--------------|--------------|------------|------------------------------------------|");
-    info!(
-        "| {pair:<10} | {date:<10} | {order_count:>11} | {loss_order_count:>11} | {loss_order_rate:<16.5} | {total_amount:<12} | {total_profit:<12} | {profit_rate:<10.5} | {sha} |",
-        pair = generate_pair_key(&close_prices),
-        date = time.format("%Y-%m-%d"),
-        order_count = readers.len(),
-        loss_order_count = loss_order,
-        loss_order_rate = 100.0 * loss_order as f64 / readers.len() as f64,
-        total_profit = total_profit,
-        total_amount = total_amount,
-        profit_rate = total_profit / total_amount * 100.0,
-        sha = get_config_sha(Arc::clone(&config))
-    );
+
+    let mut pair_rows: BTreeMap<String, Vec<&OrderRow>> = BTreeMap::new();
+    for row in &rows {
+        pair_rows.entry(row.pair.clone()).or_default().push(row);
+    }
+    let mut pair_summaries: Vec<PairSummary> = Vec::new();
+    let mut equity: Vec<EquityPoint> = Vec::new();
+    for (pair, list) in &pair_rows {
+        let curve = equity_curve(&asset, pair, &date, list, close_time);
+        let mut summary = summarize(pair, &date, list, &config_sha);
+        evaluate_risk(&mut summary, list, &curve, start_time, close_time);
+        pair_summaries.push(summary);
+        equity.extend(curve);
+    }
+
+    let day_key = generate_pair_key(&close_prices);
+    let day_rows: Vec<&OrderRow> = rows.iter().collect();
+    let curve = equity_curve(&asset, &day_key, &date, &day_rows, close_time);
+    let mut summary = summarize(&day_key, &date, &day_rows, &config_sha);
+    evaluate_risk(&mut summary, &day_rows, &curve, start_time, close_time);
+    equity.extend(curve);
+
+    print_summary_header();
+    print_summary_row(&summary);
+    print_metrics(&RiskMetrics::from(&summary));
+    let rules = RuleSummary::attribute(&rows);
+    print_rules("live", &rules);
+    print_rules("shadow", &shadows);
     info!("####################################################################################################");
 
     // output config
@@ -198,11 +259,7 @@ pub fn profit_evaluate(asset: Arc<AssetContext>, config: Arc<AppConfig>) -> Resu
         info!("earlyClearRate: {}", option.early_clear_rate);
         info!("------------------------------------------------------------------------");
     }
-    for (name, mode) in [
-        ("flash", &config.trade.flash),
-        ("slug", &config.trade.slug),
-        ("revert", &config.trade.revert),
-    ] {
+    for (name, mode) in config.trade.modes() {
         print_config(name, mode);
 
         info!("------------------------------------------------------------------------");
@@ -211,7 +268,187 @@ pub fn profit_evaluate(asset: Arc<AssetContext>, config: Arc<AppConfig>) -> Resu
     info!("####################################################################################################");
     info!("####################################################################################################");
 
-    Ok(true) //FIXME:
+    Ok(Some(BacktestReport {
+        source: String::new(),
+        config_sha,
+        config: config.as_ref().clone(),
+        orders: rows,
+        metrics: pair_summaries
+            .iter()
+            .chain([&summary])
+            .map(RiskMetrics::from)
+            .collect(),
+        pairs: pair_summaries,
+        days: vec![summary],
+        equity,
+        rules,
+        shadow_orders,
+        shadows,
+    }))
+}
+
+// Summarize settled orders
+fn summarize(pair: &str, date: &str, rows: &[&OrderRow], config_sha: &str) -> PairSummary {
+    let mut summary = PairSummary {
+        pair: pair.to_string(),
+        date: date.to_string(),
+        order_count: rows.len(),
+        config_sha: config_sha.to_string(),
+        ..Default::default()
+    };
+
+    for row in rows {
+        summary.total_amount += row.amount;
+        summary.total_profit += row.profit;
+        if matches!(row.audit, AuditState::LossClear) {
+            summary.loss_order_count += 1;
+        }
+    }
+
+    summary
+}
+
+fn print_metrics(metrics: &RiskMetrics) {
+    info!(
+        "max drawdown: {}, sharpe: {:?}, sortino: {:?}, win rate: {:.5}%, average win: {}, average loss: {}, profit factor: {:?}, exposure: {:.5}%",
+        metrics.max_drawdown,
+        metrics.sharpe,
+        metrics.sortino,
+        metrics.win_rate,
+        metrics.average_win,
+        metrics.average_loss,
+        metrics.profit_factor,
+        metrics.exposure
+    );
+}
+
+// Print profit attribution of rules, ex: live, shadow
+pub fn print_rules(label: &str, rules: &[RuleSummary]) {
+    for rule in rules {
+        info!(
+            "{:<6} rule: {:<12} evaluation: {:<5} orders: {:<4} win orders: {:<4} total profit: {}",
+            label,
+            rule.rule,
+            rule.evaluation,
+            rule.order_count,
+            rule.win_order_count,
+            rule.total_profit
+        );
+    }
+}
+
+fn order_row(config: &AppConfig, order: &Order, date: &str, closed_price: f32) -> OrderRow {
+    OrderRow {
+        id: order.id.clone(),
+        symbol: order.symbol.clone(),
+        pair: find_pair_key(config, &order.symbol),
+        date: date.to_string(),
+        created_time: order.created_time,
+        created_price: order.created_price,
+        created_volume: order.created_volume,
+        closed_price,
+        // FIXME: use accepted
+        amount: order.created_price * order.created_volume as f32,
+        profit: (closed_price - order.created_price) * order.created_volume as f32,
+        write_off_time: order.write_off_time,
+        status: format!("{:?}", order.status),
+        audit: order.audit.clone(),
+        constraint_id: order.constraint_id.clone().unwrap_or_default(),
+        rule: order
+            .decision
+            .as_ref()
+            .and_then(|d| d.rule_name())
+            .unwrap_or_default(),
+        evaluations: order
+            .decision
+            .as_ref()
+            .map(|d| d.evaluation_names().join(","))
+            .unwrap_or_default(),
+    }
+}
+
+// Key of ticker group, ex: SQQQ-TQQQ
+fn find_pair_key(config: &AppConfig, symbol: &str) -> String {
+    match config
+        .tickers
+        .symbols
+        .iter()
+        .find(|g| g.bull.id == symbol || g.bear.id == symbol)
+    {
+        Some(group) => {
+            let mut symbols = [group.bull.id.clone(), group.bear.id.clone()];
+            symbols.sort();
+            symbols.join("-")
+        }
+        None => symbol.to_string(),
+    }
+}
+
+fn print_summary_header() {
+    info!(
+        "| {symbol:<10} | {date:<10} | {order_count:>11} | {loss_order_count:>11} | {loss_order_rate:<16} | {total_amount:<12} | {total_profit:<12} | {profit_rate:<10} | {sha:<40} |",
+        symbol = "Symbols",
+        date = "Date",
+        order_count = "Order count",
+        loss_order_count = "Loss orders",
+        loss_order_rate = "Loss orders (%s)",
+        total_amount = "Total amount",
+        total_profit = "PnL",
+        profit_rate = "PnL (%s)",
+        sha = "Config SHA"
+    );
+    info!("|------------|------------|-------------|-------------|------------------|--------------|--------------|------------|------------------------------------------|");
+}
+
+fn print_summary_row(summary: &PairSummary) {
+    info!(
+        "| {pair:<10} | {date:<10} | {order_count:>11} | {loss_order_count:>11} | {loss_order_rate:<16.5} | {total_amount:<12} | {total_profit:<12} | {profit_rate:<10.5} | {sha} |",
+        pair = summary.pair,
+        date = summary.date,
+        order_count = summary.order_count,
+        loss_order_count = summary.loss_order_count,
+        loss_order_rate = summary.loss_order_rate(),
+        total_profit = summary.total_profit,
+        total_amount = summary.total_amount,
+        profit_rate = summary.profit_rate(),
+        sha = summary.config_sha
+    );
+}
+
+// print merged results of replayed files
+pub fn print_replay_summary(reports: &[BacktestReport]) {
+    let report = match BacktestReport::merge("total", reports) {
+        Some(report) => report,
+        None => return,
+    };
+
+    info!("####################################################################################################");
+    info!("Replay summary of {} files", reports.len());
+    print_summary_header();
+    for summary in &report.days {
+        print_summary_row(summary);
+    }
+    info!("|------------|------------|-------------|-------------|------------------|--------------|--------------|------------|------------------------------------------|");
+
+    // aggregate by pair
+    let mut pairs: BTreeMap<String, Vec<PairSummary>> = BTreeMap::new();
+    for summary in &report.pairs {
+        pairs
+            .entry(summary.pair.clone())
+            .or_default()
+            .push(summary.clone());
+    }
+    for (pair, summaries) in &pairs {
+        print_summary_row(&PairSummary::merge(pair, summaries));
+    }
+    info!("|------------|------------|-------------|-------------|------------------|--------------|--------------|------------|------------------------------------------|");
+    print_summary_row(&PairSummary {
+        pair: "Total".to_string(),
+        ..report.summary()
+    });
+    print_rules("live", &report.rules);
+    print_rules("shadow", &report.shadows);
+    info!("####################################################################################################");
 }
 
 fn generate_pair_key(close_prices: &HashMap<String, f32>) -> String {
@@ -221,8 +458,7 @@ fn generate_pair_key(close_prices: &HashMap<String, f32>) -> String {
 }
 
 fn get_config_sha(config: Arc<AppConfig>) -> String {
-    let config_file = config.extra_get(KEY_EXTRA_CONFIG_FILE_PATH).unwrap();
-    let base = Path::new(&config_file).parent().unwrap();
+    let base = Path::new(&config.switches.config_file).parent().unwrap();
     let sha = Path::file_name(base).unwrap().to_str().unwrap();
     sha.to_owned()
 }
@@ -250,8 +486,16 @@ fn print_config(name: &str, mode: &AuditMode) {
         "[Config] {}.loss_margin_rate: {:?}",
         name, &mode.loss_margin_rate
     );
+    info!(
+        "[Config] {}.action: {:?}, priority: {}, cooldown: {:?}",
+        name,
+        mode.action(name),
+        mode.priority,
+        mode.cooldown(name)
+    );
 
-    for (index, rule) in mode.rules.iter().filter(|r| !r.evaluation).enumerate() {
+    // index of rule is the same as audit decision, ex: flash#2
+    for (index, rule) in mode.rules.iter().enumerate().filter(|(_, r)| !r.evaluation) {
         info!(
             "########## [{} rule {} - {:?}] ##########",
             name, index, rule.mode
@@ -287,6 +531,17 @@ fn print_config(name: &str, mode: &AuditMode) {
                 index, lower.from, lower.to, lower.compare_to, lower.duration
             );
         }
+        for indicator in &rule.indicators {
+            info!(
+                "[rule {}] INDICATOR, unit: {}, name: {}, index: {}, min: {:?}, max: {:?}",
+                index,
+                indicator.unit,
+                indicator.name,
+                indicator.index,
+                indicator.min,
+                indicator.max
+            );
+        }
     }
 }
 
@@ -295,7 +550,14 @@ fn buffer_config(buffered: &mut Vec<String>, name: &str, mode: &AuditMode) {
         "[Config] {}.loss_margin_rate: {:?}",
         name, &mode.loss_margin_rate
     ));
-    for (index, rule) in mode.rules.iter().filter(|r| !r.evaluation).enumerate() {
+    buffered.push(format!(
+        "[Config] {}.action: {:?}, priority: {}, cooldown: {:?}",
+        name,
+        mode.action(name),
+        mode.priority,
+        mode.cooldown(name)
+    ));
+    for (index, rule) in mode.rules.iter().enumerate().filter(|(_, r)| !r.evaluation) {
         buffered.push(format!(
             "########## [{} rule {} - {:?}] ##########",
             name, index, rule.mode
@@ -331,6 +593,17 @@ fn buffer_config(buffered: &mut Vec<String>, name: &str, mode: &AuditMode) {
                 index, lower.from, lower.to, lower.compare_to, lower.duration
             ));
         }
+        for indicator in &rule.indicators {
+            buffered.push(format!(
+                "[rule {}] INDICATOR, unit: {}, name: {}, index: {}, min: {:?}, max: {:?}",
+                index,
+                indicator.unit,
+                indicator.name,
+                indicator.index,
+                indicator.min,
+                indicator.max
+            ));
+        }
     }
 }
 
@@ -342,8 +615,6 @@ fn buffer_matched_rules(
     mode_name: &str,
     mode: &AuditMode,
 ) {
-    let duration: usize = 10;
-
     for (index, rule) in mode.rules.iter().enumerate() {
         let result = validate_audit_rule(Arc::clone(&asset), Arc::clone(&config), trade, rule);
         let matched = result && !rule.evaluation;
@@ -354,17 +625,11 @@ fn buffer_matched_rules(
 
         for trend_rule in &rule.trends {
             let mut result = true;
-            let mut trend_from = 0;
-            let trend_to = trend_rule.to[1..].parse::<usize>().unwrap();
-            if let Some(from) = &trend_rule.from {
-                trend_from = from[1..].parse::<usize>().unwrap();
-            }
-
-            // calculate min duration
-            let keys: Vec<String> = trade.states.keys().map(|k| k.to_string()).collect();
-            let duration = get_min_duration(trend_to - trend_from, &keys).unwrap();
-
-            let rebound = rebound(&duration, trend_from, trade.states.get(&duration).unwrap());
+            let span = &trend_rule.span;
+            let rebound = match trade.states.get(&span.unit) {
+                Some(slopes) => rebound(&span.unit, span.from, slopes),
+                None => continue,
+            };
             let actual_trend = rebound.trend;
             let expected_trend = &trend_rule.trend;
 
@@ -412,32 +677,25 @@ fn buffer_matched_rules(
             ));
         }
         for deviation_rule in &rule.deviations {
-            let mut period_from = 0;
-
-            if let Some(from) = &deviation_rule.from {
-                period_from = from[1..].parse::<usize>().unwrap() / duration;
-            }
-
-            let base_unit = format!("m{:04}", duration);
-            // parse period from key (ex: m0070 => 70 / 10 = 7)
-            let period_to = deviation_rule.to[1..].parse::<usize>().unwrap() / duration;
+            let span = &deviation_rule.span;
 
             // min price
             let min_price = find_min_price(
                 Arc::clone(&asset),
                 &trade.id,
-                &base_unit,
-                period_from,
-                period_to,
+                &span.unit,
+                span.from,
+                span.to,
             );
 
             buffered.push(format!(
-                "[{}/{:?}] {} min price, period: {:04} - {:04}, price: {}, min price: {}, rate {:.03}% < deviation {:.03}% = {}",
+                "[{}/{:?}] {} min price, period: {} [{}, {}), price: {}, min price: {}, rate {:.03}% < deviation {:.03}% = {}",
                 index,
                 rule.mode,
                 mode_name,
-                period_from * duration,
-                period_to * duration,
+                span.unit,
+                span.from,
+                span.to,
                 trade.price,
                 min_price,
                 (trade.price - min_price) / min_price * 100.0,
@@ -447,40 +705,32 @@ fn buffer_matched_rules(
             ));
         }
         for oscillation_rule in &rule.oscillations {
-            let mut period_from = 0;
-
-            if let Some(from) = &oscillation_rule.from {
-                period_from = from[1..].parse::<usize>().unwrap() / duration;
-            }
-
-            let base_unit = format!("m{:04}", duration);
-
-            // parse period from key (ex: m0070 => 70 / 10 = 7)
-            let period_to = oscillation_rule.to[1..].parse::<usize>().unwrap() / duration;
+            let span = &oscillation_rule.span;
 
             // min price
             let min_price = find_min_price(
                 Arc::clone(&asset),
                 &trade.id,
-                &base_unit,
-                period_from,
-                period_to,
+                &span.unit,
+                span.from,
+                span.to,
             );
             let max_price = find_max_price(
                 Arc::clone(&asset),
                 &trade.id,
-                &base_unit,
-                period_from,
-                period_to,
+                &span.unit,
+                span.from,
+                span.to,
             );
 
             buffered.push(format!(
-                "[{}/{:?}] {} oscillation, period: {:04} - {:04}, max price: {}, min price: {}, rate {:.03}% > oscillation {:.03}% = {}",
+                "[{}/{:?}] {} oscillation, period: {} [{}, {}), max price: {}, min price: {}, rate {:.03}% > oscillation {:.03}% = {}",
                 index,
                 rule.mode,
                 mode_name,
-                period_from * duration,
-                period_to * duration,
+                span.unit,
+                span.from,
+                span.to,
                 max_price,
                 min_price,
                 (max_price - min_price) / max_price * 100.0,
@@ -489,64 +739,56 @@ fn buffer_matched_rules(
             ));
         }
         for lower_rule in &rule.lowers {
-            let mut period_from = 0;
-
-            if let Some(from) = &lower_rule.from {
-                period_from = from[1..].parse::<usize>().unwrap() / duration;
-            }
-
-            let base_unit = format!("m{:04}", duration);
-
-            // parse period from key (ex: m0070 => 70 / 10 = 7)
-            let period_to = lower_rule.to[1..].parse::<usize>().unwrap() / duration;
+            let span = &lower_rule.span;
 
             // get min price from wider range
             let min_price = find_min_price(
                 Arc::clone(&asset),
                 &trade.id,
-                &base_unit,
-                period_from,
-                period_to,
+                &span.unit,
+                span.from,
+                span.to,
             );
 
             // find price time lower than min_price before
             let mut recent_min_price = f32::NAN;
             if min_price.is_normal() {
-                let recent_period_to =
-                    lower_rule.compare_to[1..].parse::<usize>().unwrap() / duration;
+                let compare_span = &lower_rule.compare_span;
                 recent_min_price = find_min_price(
                     Arc::clone(&asset),
                     &trade.id,
-                    &base_unit,
-                    0,
-                    recent_period_to,
+                    &compare_span.unit,
+                    compare_span.from,
+                    compare_span.to,
                 );
 
                 if !recent_min_price.is_normal() || recent_min_price > min_price {
                     buffered.push(format!(
-                        "[{}/{:?}] {} lower, period: {:04} - {:04}, min price: {}, recent min price: {} in {} = false",
+                        "[{}/{:?}] {} lower, period: {} [{}, {}), min price: {}, recent min price: {} in {} = false",
                         index,
                         rule.mode,
                         mode_name,
-                        period_from * duration,
-                        period_to * duration,
+                        span.unit,
+                        span.from,
+                        span.to,
                         min_price,
                         recent_min_price,
-                        &lower_rule.compare_to[1..]
+                        lower_rule.compare_to
                     ));
                     continue;
                 }
             };
             buffered.push(format!(
-                "[{}/{:?}] {} lower, period: {:04} - {:04}, min price: {}, recent min price: {} in {} = true",
+                "[{}/{:?}] {} lower, period: {} [{}, {}), min price: {}, recent min price: {} in {} = true",
                 index,
                 rule.mode,
                 mode_name,
-                period_from * duration,
-                period_to * duration,
+                span.unit,
+                span.from,
+                span.to,
                 min_price,
                 recent_min_price,
-                &lower_rule.compare_to[1..]
+                lower_rule.compare_to
             ));
         }
 
@@ -575,7 +817,9 @@ pub fn print_meta(
     buffered.push(format!(
         "################################### MSG-{} @ {} ###################################",
         &trade.message_id,
-        Utc.timestamp_millis(trade.time).format("%Y-%m-%d %H:%M:%S")
+        Utc.timestamp_millis_opt(trade.time)
+            .unwrap()
+            .format("%Y-%m-%d %H:%M:%S")
     ));
 
     // output config
@@ -596,11 +840,7 @@ pub fn print_meta(
             "------------------------------------------------------------------------"
         ));
     }
-    for (name, mode) in [
-        ("flash", &config.trade.flash),
-        ("slug", &config.trade.slug),
-        ("revert", &config.trade.revert),
-    ] {
+    for (name, mode) in config.trade.modes() {
         buffer_config(&mut buffered, name, mode);
 
         buffered.push(format!(
@@ -618,17 +858,13 @@ pub fn print_meta(
         buffered.push(format!("{:?}", value));
     }
 
-    for (name, mode) in [
-        ("flash", &config.trade.flash),
-        ("slug", &config.trade.slug),
-        ("revert", &config.trade.revert),
-    ] {
+    for (name, mode) in config.trade.modes() {
         buffered.push(format!(
             "----------------------------------{}--------------------------------------",
             name
         ));
 
-        if name == "revert" {
+        if mode.action(name) == Some(AuditState::EarlyClear) {
             let option = config.trade.get_option(&trade.id);
             if !option.enable_early_clear {
                 continue;
@@ -732,30 +968,40 @@ pub fn print_meta(
     }
 
     if output_message {
-        let config_file = config.extra_get(KEY_EXTRA_CONFIG_FILE_PATH).unwrap();
-        let base = Path::new(&config_file).parent().unwrap();
+        let base = Path::new(&config.switches.config_file).parent().unwrap();
 
         let path = format!(
             "{base}/msgs/{symbol}/{day}/MSG-{time}-{id}.txt",
             base = base.to_str().unwrap(),
             symbol = &trade.id,
-            day = Utc.timestamp_millis(trade.time).format("%Y-%m-%d"),
-            time = Utc.timestamp_millis(trade.time).format("%Y%m%d%-H%M%S"),
+            day = Utc
+                .timestamp_millis_opt(trade.time)
+                .unwrap()
+                .format("%Y-%m-%d"),
+            time = Utc
+                .timestamp_millis_opt(trade.time)
+                .unwrap()
+                .format("%Y%m%d%-H%M%S"),
             id = &trade.message_id
         );
         write_file(&path, &buffered)?;
     }
 
     if output_order && !&order.is_none() {
-        let config_file = config.extra_get(KEY_EXTRA_CONFIG_FILE_PATH).unwrap();
-        let base = Path::new(&config_file).parent().unwrap();
+        let base = Path::new(&config.switches.config_file).parent().unwrap();
 
         let path = format!(
             "{base}/orders/{symbol}/{day}/ORD-{time}-{id}.txt",
             base = base.to_str().unwrap(),
             symbol = &trade.id,
-            day = Utc.timestamp_millis(trade.time).format("%Y-%m-%d"),
-            time = Utc.timestamp_millis(trade.time).format("%Y%m%d-%H%M%S"),
+            day = Utc
+                .timestamp_millis_opt(trade.time)
+                .unwrap()
+                .format("%Y-%m-%d"),
+            time = Utc
+                .timestamp_millis_opt(trade.time)
+                .unwrap()
+                .format("%Y%m%d-%H%M%S"),
             id = &trade.message_id
         );
 
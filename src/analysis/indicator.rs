@@ -0,0 +1,309 @@
+use crate::vo::{
+    biz::{BarType, MarketHoursType, Ticker, TimeUnit},
+    core::IndicatorConfig,
+};
+use std::collections::{BTreeMap, VecDeque};
+
+pub const INDICATOR_EMA: &str = "ema";
+pub const INDICATOR_RSI: &str = "rsi";
+// macd, signal, histogram
+pub const INDICATOR_MACD: &str = "macd";
+pub const INDICATOR_ATR: &str = "atr";
+// middle, upper, lower
+pub const INDICATOR_BOLLINGER: &str = "bollinger";
+pub const INDICATOR_VWAP: &str = "vwap";
+
+// Moving average, simple average while warming up, then smoothed by alpha
+#[derive(Debug, Clone)]
+struct Smoother {
+    period: usize,
+    alpha: f64,
+    count: usize,
+    value: f64,
+}
+
+impl Smoother {
+    // Exponential moving average
+    fn ema(period: usize) -> Self {
+        Self::new(period, 2.0 / (period as f64 + 1.0))
+    }
+
+    // Wilder's smoothing, used by RSI and ATR
+    fn wilder(period: usize) -> Self {
+        Self::new(period, 1.0 / period as f64)
+    }
+
+    fn new(period: usize, alpha: f64) -> Self {
+        Self {
+            period: period.max(1),
+            alpha,
+            count: 0,
+            value: 0.0,
+        }
+    }
+
+    fn update(&mut self, x: f64) {
+        self.count += 1;
+        if self.count <= self.period {
+            self.value += (x - self.value) / self.count as f64;
+        } else {
+            self.value += self.alpha * (x - self.value);
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.count >= self.period
+    }
+
+    fn get(&self) -> f64 {
+        if self.ready() {
+            self.value
+        } else {
+            f64::NAN
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bar {
+    // bar index of position
+    index: i64,
+    high: f64,
+    low: f64,
+    close: f64,
+}
+
+// State of closed bars
+#[derive(Debug, Clone)]
+struct IndicatorState {
+    ema: Smoother,
+    macd_fast: Smoother,
+    macd_slow: Smoother,
+    macd_signal: Smoother,
+    rsi_gain: Smoother,
+    rsi_loss: Smoother,
+    atr: Smoother,
+    closes: VecDeque<f64>,
+    previous_close: Option<f64>,
+}
+
+impl IndicatorState {
+    fn new(config: &IndicatorConfig) -> Self {
+        Self {
+            ema: Smoother::ema(config.ema),
+            macd_fast: Smoother::ema(config.macd_fast),
+            macd_slow: Smoother::ema(config.macd_slow),
+            macd_signal: Smoother::ema(config.macd_signal),
+            rsi_gain: Smoother::wilder(config.rsi),
+            rsi_loss: Smoother::wilder(config.rsi),
+            atr: Smoother::wilder(config.atr),
+            closes: VecDeque::new(),
+            previous_close: None,
+        }
+    }
+
+    fn apply(&mut self, bar: &Bar, config: &IndicatorConfig) {
+        self.ema.update(bar.close);
+
+        self.macd_fast.update(bar.close);
+        self.macd_slow.update(bar.close);
+        if self.macd_fast.ready() && self.macd_slow.ready() {
+            self.macd_signal
+                .update(self.macd_fast.get() - self.macd_slow.get());
+        }
+
+        // true range, high - low for the first bar
+        let true_range = match self.previous_close {
+            Some(close) => (bar.high - bar.low)
+                .max((bar.high - close).abs())
+                .max((bar.low - close).abs()),
+            None => bar.high - bar.low,
+        };
+        self.atr.update(true_range);
+
+        if let Some(close) = self.previous_close {
+            let change = bar.close - close;
+            self.rsi_gain.update(change.max(0.0));
+            self.rsi_loss.update((-change).max(0.0));
+        }
+
+        self.closes.push_back(bar.close);
+        while self.closes.len() > config.bollinger {
+            self.closes.pop_front();
+        }
+
+        self.previous_close = Some(bar.close);
+    }
+
+    fn values(&self, config: &IndicatorConfig) -> BTreeMap<String, Vec<f64>> {
+        let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+
+        values.insert(INDICATOR_EMA.to_string(), vec![self.ema.get()]);
+
+        let rsi = if self.rsi_gain.ready() {
+            let gain = self.rsi_gain.get();
+            let loss = self.rsi_loss.get();
+            if loss == 0.0 {
+                if gain == 0.0 {
+                    50.0
+                } else {
+                    100.0
+                }
+            } else {
+                100.0 - 100.0 / (1.0 + gain / loss)
+            }
+        } else {
+            f64::NAN
+        };
+        values.insert(INDICATOR_RSI.to_string(), vec![rsi]);
+
+        let macd = self.macd_fast.get() - self.macd_slow.get();
+        let signal = self.macd_signal.get();
+        values.insert(
+            INDICATOR_MACD.to_string(),
+            vec![macd, signal, macd - signal],
+        );
+
+        values.insert(INDICATOR_ATR.to_string(), vec![self.atr.get()]);
+
+        let bollinger = if self.closes.len() >= config.bollinger.max(1) {
+            let count = self.closes.len() as f64;
+            let middle = self.closes.iter().sum::<f64>() / count;
+            let variance = self
+                .closes
+                .iter()
+                .map(|close| (close - middle) * (close - middle))
+                .sum::<f64>()
+                / count;
+            let width = config.bollinger_width * variance.sqrt();
+            vec![middle, middle + width, middle - width]
+        } else {
+            vec![f64::NAN, f64::NAN, f64::NAN]
+        };
+        values.insert(INDICATOR_BOLLINGER.to_string(), bollinger);
+
+        values
+    }
+}
+
+// Incremental indicators of a symbol over fixed bars of unit size, in time, tick count or traded volume.
+// Closed bars are committed, the bar in progress is applied on a copy for current values.
+#[derive(Debug, Clone)]
+pub struct IndicatorEngine {
+    config: IndicatorConfig,
+    unit: TimeUnit,
+    // bar size in milliseconds, tick count or traded volume
+    size: i64,
+    state: IndicatorState,
+    bar: Option<Bar>,
+    last_time: i64,
+    // tick count or traded volume of updated tickers
+    activity: i64,
+    // session volume weighted price
+    market_hours: Option<MarketHoursType>,
+    last_day_volume: Option<i64>,
+    price_volume: f64,
+    volume: f64,
+}
+
+impl IndicatorEngine {
+    pub fn new(config: &IndicatorConfig, unit: &TimeUnit) -> Self {
+        Self {
+            config: config.clone(),
+            unit: unit.clone(),
+            size: unit.bar_size().max(1),
+            state: IndicatorState::new(config),
+            bar: None,
+            last_time: i64::MIN,
+            activity: 0,
+            market_hours: None,
+            last_day_volume: None,
+            price_volume: 0.0,
+            volume: 0.0,
+        }
+    }
+
+    pub fn unit(&self) -> TimeUnit {
+        self.unit.clone()
+    }
+
+    // Update by ticker, tickers older than the latest one are ignored
+    pub fn update(&mut self, ticker: &Ticker) -> BTreeMap<String, Vec<f64>> {
+        if ticker.time >= self.last_time {
+            self.last_time = ticker.time;
+            let position = match self.unit.bar {
+                BarType::Time => ticker.time,
+                // activity before the ticker
+                bar => {
+                    let position = self.activity;
+                    self.activity += match bar {
+                        BarType::Volume => ticker.volume.unwrap_or(0),
+                        _ => 1,
+                    };
+                    position
+                }
+            };
+            self.update_bar(position.div_euclid(self.size), ticker.price as f64);
+            self.update_vwap(ticker);
+        }
+        self.values()
+    }
+
+    pub fn values(&self) -> BTreeMap<String, Vec<f64>> {
+        let mut values = match &self.bar {
+            Some(bar) => {
+                let mut state = self.state.clone();
+                state.apply(bar, &self.config);
+                state.values(&self.config)
+            }
+            None => self.state.values(&self.config),
+        };
+
+        let vwap = if self.volume > 0.0 {
+            self.price_volume / self.volume
+        } else {
+            f64::NAN
+        };
+        values.insert(INDICATOR_VWAP.to_string(), vec![vwap]);
+
+        values
+    }
+
+    fn update_bar(&mut self, index: i64, price: f64) {
+        match &mut self.bar {
+            Some(bar) if bar.index == index => {
+                bar.high = bar.high.max(price);
+                bar.low = bar.low.min(price);
+                bar.close = price;
+            }
+            _ => {
+                // commit closed bar
+                if let Some(bar) = self.bar.take() {
+                    self.state.apply(&bar, &self.config);
+                }
+                self.bar = Some(Bar {
+                    index,
+                    high: price,
+                    low: price,
+                    close: price,
+                });
+            }
+        }
+    }
+
+    fn update_vwap(&mut self, ticker: &Ticker) {
+        // restart when market session changed
+        if self.market_hours != Some(ticker.market_hours) {
+            self.market_hours = Some(ticker.market_hours);
+            self.price_volume = 0.0;
+            self.volume = 0.0;
+        }
+
+        // volume of the first ticker is unknown
+        let last_day_volume = self.last_day_volume.unwrap_or(ticker.day_volume);
+        let volume = (ticker.day_volume - last_day_volume).max(0);
+        self.last_day_volume = Some(last_day_volume.max(ticker.day_volume));
+        self.price_volume += ticker.price as f64 * volume as f64;
+        self.volume += volume as f64;
+    }
+}
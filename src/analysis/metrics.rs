@@ -0,0 +1,172 @@
+use crate::vo::{
+    biz::{EquityPoint, OrderRow, PairSummary},
+    core::{AssetContext, PRICE_MARK_INTERVAL},
+};
+use std::collections::BTreeMap;
+
+// Interval of equity marks, same as kept price marks
+const EQUITY_INTERVAL: i64 = PRICE_MARK_INTERVAL;
+
+// Mark-to-market equity of orders by minute, from the first order to market close
+pub fn equity_curve(
+    asset: &AssetContext,
+    pair: &str,
+    date: &str,
+    rows: &[&OrderRow],
+    close_time: i64,
+) -> Vec<EquityPoint> {
+    let mut rows: Vec<&OrderRow> = rows.to_vec();
+    rows.sort_by_key(|row| row.created_time);
+    let first_time = match rows.first() {
+        Some(row) => row.created_time,
+        None => return Vec::new(),
+    };
+
+    // prices of ordered symbols, ordered by time
+    let mut prices: BTreeMap<String, Vec<(i64, f32)>> = BTreeMap::new();
+    for row in &rows {
+        if prices.contains_key(&row.symbol) {
+            continue;
+        }
+        let list = asset
+            .price_marks(&row.symbol)
+            .into_iter()
+            .filter(|(time, _)| *time <= close_time)
+            .collect();
+        prices.insert(row.symbol.clone(), list);
+    }
+
+    let mut marks: Vec<i64> = Vec::new();
+    let mut mark = first_time - first_time % EQUITY_INTERVAL + EQUITY_INTERVAL;
+    while mark < close_time {
+        marks.push(mark);
+        mark += EQUITY_INTERVAL;
+    }
+    marks.push(close_time);
+
+    // symbol => (volume, cost, cursor of prices, last price)
+    let mut positions: BTreeMap<&str, (f32, f32, usize, Option<f32>)> = BTreeMap::new();
+    let mut order_index = 0;
+    let mut curve: Vec<EquityPoint> = Vec::new();
+    for (index, mark) in marks.iter().enumerate() {
+        while order_index < rows.len() && rows[order_index].created_time <= *mark {
+            let row = rows[order_index];
+            let position = positions
+                .entry(row.symbol.as_str())
+                .or_insert((0.0, 0.0, 0, None));
+            position.0 += row.created_volume as f32;
+            position.1 += row.created_price * row.created_volume as f32;
+            order_index += 1;
+        }
+
+        let closing = index == marks.len() - 1;
+        let mut equity = 0.0;
+        for (symbol, (volume, cost, cursor, last_price)) in positions.iter_mut() {
+            let list = prices.get(*symbol).unwrap();
+            while *cursor < list.len() && list[*cursor].0 <= *mark {
+                *last_price = Some(list[*cursor].1);
+                *cursor += 1;
+            }
+
+            let price = if closing {
+                // settle with the same price of profit evaluation
+                rows.iter()
+                    .find(|row| row.symbol == *symbol)
+                    .map(|row| row.closed_price)
+            } else {
+                *last_price
+            };
+            // no ticker yet, marked at cost
+            equity += price.map_or(0.0, |p| p * *volume - *cost);
+        }
+
+        curve.push(EquityPoint {
+            pair: pair.to_string(),
+            date: date.to_string(),
+            time: *mark,
+            equity,
+        });
+    }
+
+    curve
+}
+
+// Fill drawdown, returns, win/loss and exposure of summary
+pub fn evaluate_risk(
+    summary: &mut PairSummary,
+    rows: &[&OrderRow],
+    curve: &[EquityPoint],
+    start_time: i64,
+    close_time: i64,
+) {
+    // drawdown of equity, starts from zero
+    let mut previous = 0.0_f32;
+    summary.highest_profit = 0.0;
+    summary.lowest_profit = 0.0;
+    summary.max_drawdown = 0.0;
+    for point in curve {
+        summary.highest_profit = summary.highest_profit.max(point.equity);
+        summary.lowest_profit = summary.lowest_profit.min(point.equity);
+        summary.max_drawdown = summary
+            .max_drawdown
+            .max(summary.highest_profit - point.equity);
+
+        // returns on total order amount
+        if summary.total_amount > 0.0 {
+            let value = ((point.equity - previous) / summary.total_amount) as f64;
+            summary.return_count += 1;
+            summary.return_sum += value;
+            summary.return_square_sum += value * value;
+            if value < 0.0 {
+                summary.downside_square_sum += value * value;
+            }
+        }
+        previous = point.equity;
+    }
+
+    for row in rows {
+        if row.profit > 0.0 {
+            summary.win_order_count += 1;
+            summary.gross_profit += row.profit;
+        } else if row.profit < 0.0 {
+            summary.losing_order_count += 1;
+            summary.gross_loss += -row.profit;
+        }
+    }
+
+    // union of periods with open orders
+    let mut periods: Vec<(i64, i64)> = rows
+        .iter()
+        .map(|row| {
+            let end = row.write_off_time.unwrap_or(close_time).min(close_time);
+            (row.created_time, end.max(row.created_time))
+        })
+        .collect();
+    periods.sort();
+    let mut exposure_time = 0;
+    let mut current: Option<(i64, i64)> = None;
+    for (start, end) in periods {
+        current = match current {
+            Some((s, e)) if start <= e => Some((s, e.max(end))),
+            Some((s, e)) => {
+                exposure_time += e - s;
+                Some((start, end))
+            }
+            None => Some((start, end)),
+        };
+    }
+    if let Some((s, e)) = current {
+        exposure_time += e - s;
+    }
+    summary.exposure_time = exposure_time;
+    summary.session_time = if start_time > 0 && start_time < close_time {
+        close_time - start_time
+    } else {
+        close_time
+            - rows
+                .iter()
+                .map(|r| r.created_time)
+                .min()
+                .unwrap_or(close_time)
+    };
+}
@@ -1,42 +1,58 @@
 mod computor;
 mod debug;
+pub mod indicator;
+mod metrics;
+pub mod optimize;
+pub mod rolling;
 pub mod trade;
 
 use self::trade::prepare_trade;
 use crate::{
-    analysis::{computor::draw_slop_lines, debug::profit_evaluate},
-    persist::es::{
-        bulk_index, protfolio_index_name, slope_index_name, take_index_time, trade_index_name,
-        ElasticTicker, ElasticTrade,
+    analysis::{
+        computor::draw_slop_lines,
+        debug::{print_replay_summary, profit_evaluate},
+    },
+    persist::{
+        es::{
+            bulk_index, equity_index_name, protfolio_index_name, slope_index_name, take_index_time,
+            trade_index_name, ElasticEquity, ElasticTrade, ElasticTradeStates,
+        },
+        parquet::write_parquet,
+        reader::{source_path, TickerFile},
+        sink::TickerSink,
+        sqlite::TickerQuery,
     },
     vo::{
-        biz::{MarketHoursType, Protfolio, Ticker, TimeUnit, TradeInfo},
-        core::AppContext,
+        biz::{BacktestReport, MarketHoursType, Order, Protfolio, Ticker, TimeUnit, TradeInfo},
+        core::{AppConfig, AppContext},
     },
     Result,
 };
 use chrono::{TimeZone, Utc};
+use futures::{stream, StreamExt};
 use log::{debug, error, info, trace, warn};
+use serde::Serialize;
 use std::{
+    cmp::max,
     collections::HashMap,
     fs::{create_dir_all, remove_dir_all, File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Write},
+    io::{BufWriter, Write},
     path::Path,
     sync::Arc,
     thread::sleep,
     time::Duration,
 };
-use tokio::sync::RwLock;
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast::error::RecvError, RwLock},
+};
 
 pub async fn init_dispatcher(context: &Arc<AppContext>) -> Result<()> {
     let config = context.config();
 
-    if config.sync_mongo_enabled() {
-        handle_message_for_mongo(Arc::clone(&context)).await?;
-    }
-
-    if config.sync_elasticsearch_enabled() {
-        handle_message_for_elasticsearch(Arc::clone(&context)).await?;
+    for sink in context.sinks() {
+        handle_message_for_sink(Arc::clone(context), Arc::clone(&sink)).await?;
+        handle_order_for_sink(Arc::clone(context), sink).await?;
     }
 
     if config.trade.enabled {
@@ -50,16 +66,20 @@ pub async fn init_dispatcher(context: &Arc<AppContext>) -> Result<()> {
     Ok(())
 }
 
-async fn handle_message_for_mongo(context: Arc<AppContext>) -> Result<()> {
-    info!("Initialize mongo event persist handler");
+async fn handle_message_for_sink(
+    context: Arc<AppContext>,
+    sink: Arc<dyn TickerSink>,
+) -> Result<()> {
+    info!("Initialize {} event persist handler", sink.name());
     let post_man = context.post_man();
     let mut rx = post_man.subscribe_store();
 
     let buffer: Arc<RwLock<Vec<Ticker>>> = Arc::new(RwLock::new(Vec::new()));
     let temp = Arc::clone(&buffer);
+    let name = sink.name().to_string();
 
     tokio::spawn(async move {
-        debug!("Initialize mongo event persist handler - receiver");
+        debug!("Initialize {} event persist handler - receiver", &name);
         loop {
             match rx.recv().await {
                 Ok(event) => {
@@ -67,26 +87,38 @@ async fn handle_message_for_mongo(context: Arc<AppContext>) -> Result<()> {
                     let mut guard = temp.write().await;
                     guard.push(ticker);
                 }
-                Err(err) => error!("Handle ticker for mongo error: {:?}", err),
+                Err(RecvError::Closed) => {
+                    debug!("Event persist handler of {} closed", &name);
+                    break;
+                }
+                Err(err) => error!("Handle ticker for {} error: {:?}", &name, err),
             }
         }
     });
 
-    let persist = context.persistence();
     let temp = Arc::clone(&buffer);
 
     tokio::spawn(async move {
-        debug!("Initialize mongo event persist handler - processor");
+        debug!(
+            "Initialize {} event persist handler - processor",
+            sink.name()
+        );
         loop {
+            // receiver dropped its buffer, nothing pushed after this drain
+            let closed = Arc::strong_count(&temp) == 1;
             let mut guard = temp.write().await;
-            if let Some(event) = guard.pop() {
-                let ticker: Ticker = event.into();
-                if let Err(err) = ticker.save_to_mongo(Arc::clone(&persist)).await {
-                    error!("Save ticker for mongo error: {:?}", err)
+            let items: Vec<Ticker> = guard.drain(..).collect();
+            std::mem::drop(guard);
+
+            if !items.is_empty() {
+                if let Err(err) = sink.save_tickers(&items).await {
+                    error!("Save ticker for {} error: {:?}", sink.name(), err);
                 }
+            } else if closed {
+                break;
             } else {
                 // avoid busy wait
-                std::thread::sleep(std::time::Duration::from_millis(100));
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
             }
         }
     });
@@ -94,50 +126,23 @@ async fn handle_message_for_mongo(context: Arc<AppContext>) -> Result<()> {
     Ok(())
 }
 
-async fn handle_message_for_elasticsearch(context: Arc<AppContext>) -> Result<()> {
-    info!("Initialize elasticsearch event persist handler");
-    let post_man = context.post_man();
-    let mut rx = post_man.subscribe_store();
-
-    let buffer: Arc<RwLock<Vec<ElasticTicker>>> = Arc::new(RwLock::new(Vec::new()));
-    let temp = Arc::clone(&buffer);
+async fn handle_order_for_sink(context: Arc<AppContext>, sink: Arc<dyn TickerSink>) -> Result<()> {
+    info!("Initialize {} order persist handler", sink.name());
+    let mut rx = context.asset().subscribe_orders();
 
     tokio::spawn(async move {
-        debug!("Initialize elasticsearch event persist handler - receiver");
         loop {
             match rx.recv().await {
                 Ok(event) => {
-                    let ticker: ElasticTicker = event.into();
-                    let mut guard = temp.write().await;
-                    guard.push(ticker);
+                    if let Err(err) = sink.save_order(&event).await {
+                        error!("Save order for {} error: {:?}", sink.name(), err)
+                    }
                 }
-                Err(err) => error!("Handle ticker for elasticsearch error: {:?}", err),
-            }
-        }
-    });
-
-    let persist = context.persistence();
-    let temp = Arc::clone(&buffer);
-
-    tokio::spawn(async move {
-        debug!("Initialize elasticsearch event persist handler - processor");
-        loop {
-            let mut guard = temp.write().await;
-            let mut items: Vec<ElasticTicker> = Vec::new();
-            while let Some(item) = guard.pop() {
-                items.push(item);
-            }
-            std::mem::drop(guard);
-
-            if !items.is_empty() {
-                if let Err(err) =
-                    ElasticTicker::batch_save_to_elasticsearch(Arc::clone(&persist), &items).await
-                {
-                    error!("Save ticker for elasticsearch error: {:?}", err);
+                Err(RecvError::Closed) => {
+                    debug!("Order persist handler of {} closed", sink.name());
+                    break;
                 }
-            } else {
-                // avoid busy wait
-                std::thread::sleep(std::time::Duration::from_millis(100));
+                Err(err) => error!("Handle order for {} error: {:?}", sink.name(), err),
             }
         }
     });
@@ -179,14 +184,7 @@ async fn handle_message_for_preparatory(ctx: Arc<AppContext>) -> Result<()> {
             if let Some(event) = guard.pop() {
                 let ticker: Ticker = event.into();
                 // Add into source list
-                if let Some(lock) = context.asset().symbol_tickers(&ticker.id) {
-                    if let Ok(mut guard) = lock.write() {
-                        guard.push_front(ticker.clone());
-                    } else {
-                        error!("get mutable tickers error: {}", &ticker.id);
-                        continue;
-                    }
-                } else {
+                if !context.asset().add_ticker(&ticker) {
                     error!("No tickers container {} initialized", &ticker.id);
                     continue;
                 }
@@ -389,12 +387,22 @@ impl AppContext {
             // Get target trade info
             let trade_lock = asset.find_trade(symbol, message_id).unwrap();
 
+            // Update indicators before slope, trade info is finalized by slopes of all units
+            if let Some(engine) = asset.get_indicators(symbol, &unit.name) {
+                let mut trade = trade_lock.write().unwrap();
+                if let Some(ticker) = symbol_tickers.iter().find(|t| t.time == trade.time) {
+                    let values = engine.lock().unwrap().update(ticker);
+                    trade.update_indicators(&unit.name, values);
+                }
+            }
+
             // Start calculation
             unit.rebalance(
                 symbol,
-                message_id,
                 &symbol_tickers,
                 &mut protfolios,
+                asset.get_aggregator(symbol, &unit.name),
+                asset.retention(),
                 trade_lock,
             )?;
         } else {
@@ -407,6 +415,8 @@ impl AppContext {
     }
 }
 
+type TickerStream = Box<dyn Iterator<Item = Result<Ticker>> + Send>;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ReplayMode {
     // Sync mode for normal replay
@@ -415,40 +425,38 @@ pub enum ReplayMode {
     Async { delay: u64 },
 }
 
-pub async fn replay(context: &AppContext, file: &str, mode: ReplayMode) -> Result<()> {
+pub async fn replay(
+    context: &AppContext,
+    file: &str,
+    mode: ReplayMode,
+) -> Result<Option<BacktestReport>> {
     info!("Loading tickers: {}", file);
 
     let config = context.config();
     let asset = context.asset();
 
-    let f = File::open(file)?;
-    let reader = BufReader::new(f);
-    let mut tickers: Vec<Ticker> = reader
-        .lines()
-        .into_iter()
-        .map(|w| w.unwrap())
-        .map(|line| serde_json::from_str::<Ticker>(&line).unwrap())
-        .collect();
+    let (file, tickers) = load_tickers(context, file)?;
+    let file = file.as_str();
 
-    let total = tickers.len();
     let mut handl_count = 0;
     let mut seconds = Utc::now().timestamp() / 60;
+    let mut report: Option<BacktestReport> = None;
 
-    info!("Loaded tickers: {} from {}", total, file);
-
-    for ticker in tickers.iter_mut() {
+    // tickers are streamed from source, not held in memory
+    for ticker in tickers {
+        let mut ticker = ticker?;
         if mode == ReplayMode::Sync {
             debug!("************************************************************************************************************");
             context
-                .dispatch_direct(ticker, asset.next_message_id())
+                .dispatch_direct(&mut ticker, asset.next_message_id())
                 .await?;
         } else {
-            context.dispatch(ticker).await?;
+            context.dispatch(&ticker).await?;
         }
         handl_count = handl_count + 1;
 
         if seconds < Utc::now().timestamp() / 60 {
-            debug!("Handled items {}/{} for {}", handl_count, total, file);
+            debug!("Handled items {} for {}", handl_count, file);
             seconds = seconds + 1;
         }
 
@@ -461,13 +469,28 @@ pub async fn replay(context: &AppContext, file: &str, mode: ReplayMode) -> Resul
 
         // settle all orders when turns to post market
         if matches!(ticker.market_hours, MarketHoursType::PostMarket) {
-            if profit_evaluate(context.asset(), context.config())? {
+            if let Some(mut result) = profit_evaluate(context.asset(), context.config())? {
+                result.source = file.to_string();
+                for summary in result.days.iter_mut().chain(result.pairs.iter_mut()) {
+                    summary.source = file.to_string();
+                }
+                report = Some(result);
                 break;
             }
         }
     }
     info!("Tickers: {} replay done", &file);
 
+    if let Some(report) = &report {
+        if config.replay.export_enabled("report") {
+            info!("Exporting backtest report for {}", file);
+            export_report(&config, file, report)?;
+        }
+        if config.data_source.sqlite.enabled {
+            context.persistence().sqlite()?.insert_report(report)?;
+        }
+    }
+
     if config.replay.outputs.file.enabled || config.replay.outputs.elasticsearch.enabled {
         let source_file = Path::new(file).file_name().unwrap().to_str().unwrap();
 
@@ -482,15 +505,216 @@ pub async fn replay(context: &AppContext, file: &str, mode: ReplayMode) -> Resul
         }
 
         if config.replay.export_enabled("trade") {
+            if config.retention.enabled {
+                warn!("Only retained trades are exported, disable retention to export all");
+            }
             info!("Exporting trades info for {}", source_file);
             export_trades(&context, source_file).await?;
         }
+
+        if config.replay.export_enabled("equity") {
+            if let Some(report) = &report {
+                info!("Exporting equity for {}", source_file);
+                export_equity(context, report).await?;
+            }
+        }
+    }
+
+    if config.replay.outputs.parquet.enabled {
+        let source_file = Path::new(file).file_name().unwrap().to_str().unwrap();
+        info!("Exporting parquet files for {}", source_file);
+        export_parquet(context, source_file)?;
     }
 
     // clean memory
     info!("Clean up cached data for next run");
     context.asset().clean()?;
 
+    Ok(report)
+}
+
+// Tickers of json lines file, or sqlite query named as split file, ex: tickers20220309.TQQQ-SQQQ.sqlite
+// Compressed and protobuf files are named without extension, ex: tickers20220309 of tickers20220309.pb.gz
+fn load_tickers(context: &AppContext, file: &str) -> Result<(String, TickerStream)> {
+    if let Some(query) = TickerQuery::parse(file)? {
+        let tickers = context.persistence().sqlite()?.query_tickers(&query)?;
+        info!("Loaded tickers: {} from {}", tickers.len(), file);
+        return Ok((
+            format!("{}.sqlite", query.name()),
+            Box::new(tickers.into_iter().map(Ok)),
+        ));
+    }
+
+    let tickers = TickerFile::open(file)?;
+    Ok((source_path(file).to_string(), Box::new(tickers)))
+}
+
+// Replay files in parallel, every file runs with its own context
+pub async fn replay_files(
+    config: &AppConfig,
+    files: &[String],
+    jobs: usize,
+) -> Result<Vec<BacktestReport>> {
+    let jobs = max(1, jobs);
+    info!("Replay {} files with {} jobs", files.len(), jobs);
+
+    let results: Vec<Result<Option<BacktestReport>>> = stream::iter(files.to_owned())
+        .map(|file| {
+            let config = config.isolate();
+            // replay holds sync locks across awaits, drive it on a blocking thread
+            let handle = Handle::current();
+            tokio::task::spawn_blocking(move || {
+                handle.block_on(async {
+                    let context = AppContext::new(config).init_replay().await?;
+                    replay(&context, &file, ReplayMode::Sync).await
+                })
+            })
+        })
+        .buffered(jobs)
+        .map(|joined| joined.unwrap_or_else(|err| Err(Box::new(err))))
+        .collect()
+        .await;
+
+    let mut reports: Vec<BacktestReport> = Vec::new();
+    for (file, result) in files.iter().zip(results) {
+        match result? {
+            Some(report) => reports.push(report),
+            None => warn!("No profit evaluated for {}", file),
+        }
+    }
+
+    if reports.len() > 1 {
+        print_replay_summary(&reports);
+    }
+
+    Ok(reports)
+}
+
+// Write backtest report as json and csv files, ex: tmp/reports/{sha}/{file}.json
+fn export_report(config: &AppConfig, file: &str, report: &BacktestReport) -> Result<()> {
+    let source_file = Path::new(file).file_stem().unwrap().to_str().unwrap();
+    let base_path = format!(
+        "{}/reports/{}",
+        &config.replay.outputs.base_folder, &report.config_sha
+    );
+    create_dir_all(&base_path)?;
+
+    let json_file = File::create(format!("{}/{}.json", base_path, source_file))?;
+    serde_json::to_writer_pretty(BufWriter::new(json_file), report)?;
+
+    write_csv(
+        &format!("{}/{}.orders.csv", base_path, source_file),
+        &report.orders,
+    )?;
+    write_csv(
+        &format!("{}/{}.pairs.csv", base_path, source_file),
+        &report.pairs,
+    )?;
+    write_csv(
+        &format!("{}/{}.days.csv", base_path, source_file),
+        &report.days,
+    )?;
+    write_csv(
+        &format!("{}/{}.metrics.csv", base_path, source_file),
+        &report.metrics,
+    )?;
+    write_csv(
+        &format!("{}/{}.equity.csv", base_path, source_file),
+        &report.equity,
+    )?;
+    write_csv(
+        &format!("{}/{}.rules.csv", base_path, source_file),
+        &report.rules,
+    )?;
+    write_csv(
+        &format!("{}/{}.shadows.csv", base_path, source_file),
+        &report.shadows,
+    )?;
+    write_csv(
+        &format!("{}/{}.shadow-orders.csv", base_path, source_file),
+        &report.shadow_orders,
+    )?;
+
+    Ok(())
+}
+
+async fn export_equity(context: &AppContext, report: &BacktestReport) -> Result<()> {
+    let config = context.config();
+    if config.replay.outputs.elasticsearch.enabled {
+        if let Some(point) = report.equity.first() {
+            let index_name = equity_index_name(&Utc.timestamp_millis_opt(point.time).unwrap());
+            let list: Vec<ElasticEquity> = report
+                .equity
+                .iter()
+                .map(|p| ElasticEquity::from(p, &report.config_sha))
+                .collect();
+            bulk_index(context, &index_name, &list).await?;
+        }
+    }
+    Ok(())
+}
+
+fn write_csv<T: Serialize>(path: &str, rows: &[T]) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Columnar files of enabled exports, ex: tmp/parquet/{file}/protfolios.parquet
+fn export_parquet(context: &AppContext, file: &str) -> Result<()> {
+    let config = context.config();
+    let asset = context.asset();
+    let base_path = format!("{}/parquet/{}", &config.replay.outputs.base_folder, file);
+
+    if config.replay.export_enabled("protfolio") {
+        let mut protfolios: Vec<Protfolio> = Vec::new();
+        for groups in asset.protfolios().values() {
+            for (unit, lock) in groups {
+                // ignore moving protfolios
+                if matches!(config.find_unit(unit), Some(u) if u.is_moving()) {
+                    continue;
+                }
+                protfolios.extend(lock.read().unwrap().iter().rev().cloned());
+            }
+        }
+        let path = format!("{}/protfolios.parquet", &base_path);
+        write_parquet(&path, &protfolios)?;
+        debug!("Finish protfolios: {} file", &path);
+    }
+
+    if config.replay.export_enabled("trade") {
+        let mut trades: Vec<ElasticTrade> = Vec::new();
+        for list_lock in asset.trades().values() {
+            let list_reader = list_lock.read().unwrap();
+            trades.extend(
+                list_reader
+                    .iter()
+                    .rev()
+                    .flat_map(|item_lock| ElasticTrade::from(&item_lock.read().unwrap())),
+            );
+        }
+        let path = format!("{}/trades.parquet", &base_path);
+        write_parquet(&path, &trades)?;
+        debug!("Finish trades: {} file", &path);
+    }
+
+    if config.replay.export_enabled("order") {
+        let orders: Vec<Order> = asset
+            .orders()
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .cloned()
+            .collect();
+        let path = format!("{}/orders.parquet", &base_path);
+        write_parquet(&path, &orders)?;
+        debug!("Finish orders: {} file", &path);
+    }
+
     Ok(())
 }
 
@@ -516,7 +740,7 @@ async fn export_protfolios(context: &AppContext, file: &str) -> Result<()> {
     for (symbol, groups) in context.asset().protfolios().as_ref() {
         for (unit, lock) in groups {
             // ignore moving protfolios
-            if TimeUnit::is_moving_unit(unit) {
+            if matches!(config.find_unit(unit), Some(u) if u.is_moving()) {
                 continue;
             }
 
@@ -548,7 +772,9 @@ async fn export_protfolios(context: &AppContext, file: &str) -> Result<()> {
                         list_reader.iter().map(|p| p.clone()).collect();
 
                     // generate index name
-                    let time = Utc.timestamp_millis(protfolios.first().unwrap().time);
+                    let time = Utc
+                        .timestamp_millis_opt(protfolios.first().unwrap().time)
+                        .unwrap();
                     let index_name = protfolio_index_name(&time);
 
                     bulk_index(&context, &index_name, &protfolios).await?;
@@ -582,7 +808,7 @@ async fn export_slope_points(context: &AppContext, file: &str) -> Result<()> {
     for (symbol, groups) in context.asset().protfolios().as_ref() {
         for (unit, lock) in groups {
             // ignore moving protfolios
-            if TimeUnit::is_moving_unit(unit) {
+            if matches!(config.find_unit(unit), Some(u) if u.is_moving()) {
                 continue;
             }
 
@@ -619,7 +845,9 @@ async fn export_slope_points(context: &AppContext, file: &str) -> Result<()> {
                     let points = draw_slop_lines(&protfolios);
 
                     // generate index name
-                    let time = Utc.timestamp_millis(points.first().unwrap().time);
+                    let time = Utc
+                        .timestamp_millis_opt(points.first().unwrap().time)
+                        .unwrap();
                     let index_name = slope_index_name(&time);
 
                     bulk_index(&context, &index_name, &points).await?;
@@ -677,14 +905,16 @@ async fn export_trades(context: &AppContext, file: &str) -> Result<()> {
         }
         // too many data, stop export temporary
         if config.replay.outputs.elasticsearch.enabled && false {
-            let trades: Vec<ElasticTrade> = list_reader
+            let trades: Vec<ElasticTradeStates> = list_reader
                 .iter()
-                .flat_map(|item_lock| ElasticTrade::from(&item_lock.read().unwrap()))
+                .map(|item_lock| ElasticTradeStates::from(&item_lock.read().unwrap()))
                 .collect();
 
             if !trades.is_empty() {
                 // generate index name
-                let time = Utc.timestamp_millis(trades.first().unwrap().timestamp);
+                let time = Utc
+                    .timestamp_millis_opt(trades.first().unwrap().timestamp)
+                    .unwrap();
                 let index_name = trade_index_name(&time);
 
                 for chunk in trades.chunks(10000) {
@@ -694,7 +924,7 @@ async fn export_trades(context: &AppContext, file: &str) -> Result<()> {
                         &chunk
                             .iter()
                             .map(|t| t.clone())
-                            .collect::<Vec<ElasticTrade>>(),
+                            .collect::<Vec<ElasticTradeStates>>(),
                     )
                     .await?;
                 }
@@ -0,0 +1,398 @@
+use super::replay_files;
+use crate::{
+    vo::{
+        biz::PairSummary,
+        core::{AppConfig, SweepSpec},
+    },
+    Result,
+};
+use chrono::NaiveDate;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs::{create_dir_all, File},
+    io::BufWriter,
+    path::Path,
+};
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SweepResult {
+    pub config_sha: String,
+    pub parameters: Vec<(String, Value)>,
+    pub summary: PairSummary,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WalkForwardWindow {
+    pub in_sample: Vec<String>,
+    pub out_of_sample: Vec<String>,
+    // best config on in-sample days
+    pub best: SweepResult,
+    pub summaries: Vec<PairSummary>,
+    pub summary: PairSummary,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DailyEquity {
+    pub date: String,
+    pub profit: f32,
+    pub equity: f32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WalkForwardReport {
+    pub windows: Vec<WalkForwardWindow>,
+    // stitched out-of-sample results
+    pub equity: Vec<DailyEquity>,
+    pub summary: PairSummary,
+}
+
+// Replay all files with every config variant of sweep spec, rank by PnL, loss orders and drawdown
+pub async fn optimize(
+    config: &AppConfig,
+    spec: &SweepSpec,
+    files: &[String],
+    jobs: usize,
+) -> Result<Vec<SweepResult>> {
+    let variants = spec.variants()?;
+    let base_path = format!("{}/optimize", &config.replay.outputs.base_folder);
+    info!(
+        "Optimize {} config variants with {} files",
+        variants.len(),
+        files.len()
+    );
+
+    let base = sweep_base(config)?;
+    let results = sweep(&base, &variants, files, jobs).await?;
+    print_sweep_results(&results);
+
+    let result_file = format!("{}/result.json", base_path);
+    create_dir_all(&base_path)?;
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&result_file)?), &results)?;
+    info!("Optimize result exported: {}", result_file);
+
+    Ok(results)
+}
+
+// Split dated files into rolling windows, pick the best config on in-sample days
+// and evaluate it on the following out-of-sample days
+pub async fn walk_forward(
+    config: &AppConfig,
+    spec: &SweepSpec,
+    files: &[String],
+    in_sample: usize,
+    out_of_sample: usize,
+    jobs: usize,
+) -> Result<WalkForwardReport> {
+    let mut days: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for file in files {
+        days.entry(take_file_date(file)?)
+            .or_default()
+            .push(file.to_string());
+    }
+    let dates: Vec<String> = days.keys().cloned().collect();
+    let splits = walk_forward_windows(&dates, in_sample, out_of_sample)?;
+
+    let variants = spec.variants()?;
+    let base = sweep_base(config)?;
+    let files_of = |range: &[String]| -> Vec<String> {
+        range
+            .iter()
+            .flat_map(|date| days.get(date).unwrap().clone())
+            .collect()
+    };
+
+    let mut windows: Vec<WalkForwardWindow> = Vec::new();
+    for (in_dates, out_dates) in splits {
+        info!(
+            "[window {}] in-sample: {:?}, out-of-sample: {:?}",
+            windows.len(),
+            in_dates,
+            out_dates
+        );
+
+        let results = sweep(&base, &variants, &files_of(in_dates), jobs).await?;
+        let best = results.first().unwrap().clone();
+        info!(
+            "[window {}] best config {} => {:?}",
+            windows.len(),
+            best.config_sha,
+            best.parameters
+        );
+
+        let (variant, sha) = prepare_variant(&base, &best.parameters)?;
+        let summaries: Vec<PairSummary> = replay_files(&variant, &files_of(out_dates), jobs)
+            .await?
+            .iter()
+            .map(|report| report.summary())
+            .collect();
+
+        windows.push(WalkForwardWindow {
+            in_sample: in_dates.to_vec(),
+            out_of_sample: out_dates.to_vec(),
+            best,
+            summary: PairSummary::merge(&sha, &summaries),
+            summaries,
+        });
+    }
+
+    // stitch out-of-sample results in date order
+    let summaries: Vec<PairSummary> = windows
+        .iter()
+        .flat_map(|w| w.summaries.iter().cloned())
+        .collect();
+    let mut equity: Vec<DailyEquity> = Vec::new();
+    let mut total = 0.0;
+    for window in &windows {
+        for date in &window.out_of_sample {
+            let profit: f32 = window
+                .summaries
+                .iter()
+                .filter(|s| take_file_date(&s.source).ok().as_ref() == Some(date))
+                .map(|s| s.total_profit)
+                .sum();
+            total += profit;
+            equity.push(DailyEquity {
+                date: date.to_string(),
+                profit,
+                equity: total,
+            });
+        }
+    }
+
+    let report = WalkForwardReport {
+        summary: PairSummary::merge("walk-forward", &summaries),
+        windows,
+        equity,
+    };
+    print_walk_forward(&report);
+
+    let base_path = format!("{}/walk-forward", &config.replay.outputs.base_folder);
+    let result_file = format!("{}/result.json", base_path);
+    create_dir_all(&base_path)?;
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&result_file)?), &report)?;
+    info!("Walk-forward result exported: {}", result_file);
+
+    Ok(report)
+}
+
+// Rolling windows of in-sample and following out-of-sample dates, moved forward by out-of-sample days
+pub fn walk_forward_windows(
+    dates: &[String],
+    in_sample: usize,
+    out_of_sample: usize,
+) -> Result<Vec<(&[String], &[String])>> {
+    if in_sample == 0 || out_of_sample == 0 {
+        return Err("In-sample and out-of-sample days must be positive".into());
+    }
+    if dates.len() < in_sample + out_of_sample {
+        return Err(format!(
+            "Not enough days for walk-forward, required: {}, found: {}",
+            in_sample + out_of_sample,
+            dates.len()
+        )
+        .into());
+    }
+
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start + in_sample + out_of_sample <= dates.len() {
+        windows.push((
+            &dates[start..start + in_sample],
+            &dates[start + in_sample..start + in_sample + out_of_sample],
+        ));
+        start += out_of_sample;
+    }
+    Ok(windows)
+}
+
+// no side effect to external services during sweep
+// per ticker exports are not separated by config sha, only report is kept
+fn sweep_base(config: &AppConfig) -> Result<AppConfig> {
+    let mut base = config.with_values(&[])?;
+    base.replay.outputs.elasticsearch.enabled = false;
+    base.replay.outputs.file.enabled = false;
+    base.replay.outputs.parquet.enabled = false;
+    base.replay
+        .exports
+        .iter_mut()
+        .filter(|content_type| content_type.name != "report")
+        .for_each(|content_type| content_type.enabled = false);
+    base.data_source.grafana.enabled = false;
+    Ok(base)
+}
+
+fn prepare_variant(
+    base: &AppConfig,
+    parameters: &[(String, Value)],
+) -> Result<(AppConfig, String)> {
+    let mut variant = base.with_values(parameters)?;
+    let sha = variant.sha()?;
+
+    // keep the layout of replay-all.sh, config sha is taken from folder name
+    let folder = format!("{}/optimize/{}", &base.replay.outputs.base_folder, sha);
+    create_dir_all(&folder)?;
+    let config_file = format!("{}/config.json", folder);
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&config_file)?), &variant)?;
+    variant.switches.config_file = config_file;
+
+    Ok((variant, sha))
+}
+
+// Replay files with every variant, results are ranked
+async fn sweep(
+    base: &AppConfig,
+    variants: &[Vec<(String, Value)>],
+    files: &[String],
+    jobs: usize,
+) -> Result<Vec<SweepResult>> {
+    let mut results: Vec<SweepResult> = Vec::new();
+    for (index, parameters) in variants.iter().enumerate() {
+        let (variant, sha) = prepare_variant(base, parameters)?;
+        info!("[variant {}] {} => {:?}", index, sha, parameters);
+
+        let summaries: Vec<PairSummary> = replay_files(&variant, files, jobs)
+            .await?
+            .iter()
+            .map(|report| report.summary())
+            .collect();
+        if summaries.is_empty() {
+            warn!("[variant {}] No profit evaluated", index);
+        }
+
+        results.push(SweepResult {
+            summary: PairSummary::merge(&sha, &summaries),
+            config_sha: sha,
+            parameters: parameters.clone(),
+        });
+    }
+
+    results.sort_by(rank_order);
+    Ok(results)
+}
+
+// Date of replay file by the first 8 digits token, ex: tmp/json/split.tickers20220309.TQQQ-SQQQ.json => 2022-03-09
+pub fn take_file_date(file: &str) -> Result<String> {
+    let name = Path::new(file)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    name.split(|c: char| !c.is_ascii_digit())
+        .filter(|token| token.len() == 8)
+        .find_map(|token| NaiveDate::parse_from_str(token, "%Y%m%d").ok())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .ok_or_else(|| format!("No date found in file name: {}", file).into())
+}
+
+// higher PnL first, then lower loss order rate, then lower drawdown
+fn rank_order(a: &SweepResult, b: &SweepResult) -> Ordering {
+    b.summary
+        .total_profit
+        .partial_cmp(&a.summary.total_profit)
+        .unwrap_or(Ordering::Equal)
+        .then(
+            a.summary
+                .loss_order_rate()
+                .partial_cmp(&b.summary.loss_order_rate())
+                .unwrap_or(Ordering::Equal),
+        )
+        .then(
+            a.summary
+                .max_drawdown
+                .partial_cmp(&b.summary.max_drawdown)
+                .unwrap_or(Ordering::Equal),
+        )
+}
+
+fn print_sweep_results(results: &[SweepResult]) {
+    info!("####################################################################################################");
+    info!(
+        "| {rank:>4} | {order_count:>11} | {loss_order_rate:<16} | {total_profit:<12} | {profit_rate:<10} | {drawdown:<12} | {sha:<40} | {parameters}",
+        rank = "Rank",
+        order_count = "Order count",
+        loss_order_rate = "Loss orders (%s)",
+        total_profit = "PnL",
+        profit_rate = "PnL (%s)",
+        drawdown = "Drawdown",
+        sha = "Config SHA",
+        parameters = "Parameters"
+    );
+    info!("|------|-------------|------------------|--------------|------------|--------------|------------------------------------------|------------");
+    for (rank, result) in results.iter().enumerate() {
+        let parameters: Vec<String> = result
+            .parameters
+            .iter()
+            .map(|(path, value)| format!("{}={}", path, value))
+            .collect();
+        info!(
+            "| {rank:>4} | {order_count:>11} | {loss_order_rate:<16.5} | {total_profit:<12} | {profit_rate:<10.5} | {drawdown:<12} | {sha:<40} | {parameters}",
+            rank = rank + 1,
+            order_count = result.summary.order_count,
+            loss_order_rate = result.summary.loss_order_rate(),
+            total_profit = result.summary.total_profit,
+            profit_rate = result.summary.profit_rate(),
+            drawdown = result.summary.max_drawdown,
+            sha = result.config_sha,
+            parameters = parameters.join(", ")
+        );
+    }
+    info!("####################################################################################################");
+}
+
+fn print_walk_forward(report: &WalkForwardReport) {
+    info!("####################################################################################################");
+    info!(
+        "| {window:>6} | {in_sample:<23} | {out_of_sample:<23} | {in_profit:<12} | {order_count:>11} | {loss_order_rate:<16} | {total_profit:<12} | {drawdown:<12} | {sha:<40} |",
+        window = "Window",
+        in_sample = "In-sample",
+        out_of_sample = "Out-of-sample",
+        in_profit = "IS PnL",
+        order_count = "Order count",
+        loss_order_rate = "Loss orders (%s)",
+        total_profit = "OOS PnL",
+        drawdown = "OOS Drawdown",
+        sha = "Config SHA"
+    );
+    info!("|--------|-------------------------|-------------------------|--------------|-------------|------------------|--------------|--------------|------------------------------------------|");
+    for (index, window) in report.windows.iter().enumerate() {
+        info!(
+            "| {window:>6} | {in_sample:<23} | {out_of_sample:<23} | {in_profit:<12} | {order_count:>11} | {loss_order_rate:<16.5} | {total_profit:<12} | {drawdown:<12} | {sha:<40} |",
+            window = index,
+            in_sample = date_range(&window.in_sample),
+            out_of_sample = date_range(&window.out_of_sample),
+            in_profit = window.best.summary.total_profit,
+            order_count = window.summary.order_count,
+            loss_order_rate = window.summary.loss_order_rate(),
+            total_profit = window.summary.total_profit,
+            drawdown = window.summary.max_drawdown,
+            sha = window.best.config_sha
+        );
+    }
+    info!("|--------|-------------------------|-------------------------|--------------|-------------|------------------|--------------|--------------|------------------------------------------|");
+    info!(
+        "Out-of-sample: order count: {}, loss orders: {:.5}%, PnL: {}, PnL: {:.5}%, max drawdown: {}",
+        report.summary.order_count,
+        report.summary.loss_order_rate(),
+        report.summary.total_profit,
+        report.summary.profit_rate(),
+        report.summary.max_drawdown
+    );
+    for point in &report.equity {
+        info!(
+            "equity: {} | {:<12} | {:<12}",
+            point.date, point.profit, point.equity
+        );
+    }
+    info!("####################################################################################################");
+}
+
+fn date_range(dates: &[String]) -> String {
+    match (dates.first(), dates.last()) {
+        (Some(first), Some(last)) if first != last => format!("{} ~ {}", first, last),
+        (Some(first), _) => first.to_string(),
+        _ => String::new(),
+    }
+}
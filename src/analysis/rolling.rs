@@ -0,0 +1,326 @@
+use crate::vo::biz::{BarType, MarketHoursType, Protfolio, QuoteType, Ticker, TimeUnit};
+use std::collections::{LinkedList, VecDeque};
+
+// Calculate slope for nearest line, samples of (time, price)
+// Reference to doc/trend.md
+pub fn calculate_slope(samples: &[(f64, f64)]) -> (f64, f64) {
+    match samples.len() {
+        0 => (f64::NAN, f64::NAN),
+        1 => {
+            let (_, y) = samples.first().unwrap();
+            (0.0, *y)
+        }
+        2 => {
+            let (x_1, y_1) = samples.first().unwrap();
+            let (x_2, y_2) = samples.last().unwrap();
+
+            // same timestamp
+            if x_1 == x_2 {
+                if y_1 == y_2 {
+                    return (0.0, *y_1);
+                } else {
+                    return (0.0, (y_1 + y_2) / 2.0);
+                }
+            }
+
+            // y = Ax + B
+            // -> y_1 = Ax_1 + B, y_2 = Ax_2 + B
+            let a = (y_1 - y_2) / (x_1 - x_2);
+            let b = y_1 - a * x_1;
+
+            (a, b)
+        }
+        _ => {
+            let count = samples.len() as f64;
+            let x_avg: f64 = samples.iter().map(|(x, _)| *x / count).sum();
+            let y_avg: f64 = samples.iter().map(|(_, y)| *y / count).sum();
+
+            let xy: f64 = samples
+                .iter()
+                .map(|(x, y)| (*x - x_avg) * (*y - y_avg))
+                .sum();
+
+            let x_x: f64 = samples
+                .iter()
+                .map(|(x, _)| (*x - x_avg) * (*x - x_avg))
+                .sum();
+
+            let a = xy / x_x;
+            let b = y_avg - a * x_avg;
+
+            // A = 2, y = Ax + B
+            // 2 = 2 * 1 + B, (x = 1, y = 2)
+            // 4 = 2 * 2 + B, (x = 2, y = 4)
+            // A = slope = delta-Y / delta-X = (4 - 2) / (2 - 1)
+            (a, b)
+        }
+    }
+}
+
+// Average price of samples, summed in given order
+pub fn average_price(prices: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = prices.fold((0.0, 0), |(sum, count), price| {
+        (sum + price as f64, count + 1)
+    });
+    (sum / count as f64) as f32
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    // position of bar, time, tick sequence or cumulative traded volume
+    position: i64,
+    time: i64,
+    price: f32,
+    day_volume: i64,
+    quote_type: QuoteType,
+    market_hours: MarketHoursType,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    // candidates of max/min price, (sequence, price)
+    highs: VecDeque<(u64, f32)>,
+    lows: VecDeque<(u64, f32)>,
+}
+
+impl Bucket {
+    // sample enters as the newest one
+    fn push(&mut self, sequence: u64, sample: &Sample) {
+        while matches!(self.highs.back(), Some((_, price)) if *price <= sample.price) {
+            self.highs.pop_back();
+        }
+        self.highs.push_back((sequence, sample.price));
+        while matches!(self.lows.back(), Some((_, price)) if *price >= sample.price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((sequence, sample.price));
+    }
+
+    // sample leaves as the oldest one
+    fn pop(&mut self, sequence: u64) {
+        if matches!(self.highs.front(), Some((s, _)) if *s == sequence) {
+            self.highs.pop_front();
+        }
+        if matches!(self.lows.front(), Some((s, _)) if *s == sequence) {
+            self.lows.pop_front();
+        }
+    }
+}
+
+// Rolling aggregation of a moving unit, buckets are relative to the latest ticker position
+// Each ticker moves from newer to older bucket as position goes, and leaves after the oldest one
+#[derive(Debug, Clone)]
+pub struct MovingAggregator {
+    unit: TimeUnit,
+    // bucket size in milliseconds, tick count or traded volume
+    size: i64,
+    id: String,
+    // samples in scope, oldest first
+    samples: VecDeque<Sample>,
+    // sequence of the oldest sample
+    head: u64,
+    // bucket k holds sequences in [cuts[k + 1], cuts[k])
+    cuts: Vec<u64>,
+    buckets: Vec<Bucket>,
+    // position of the latest sample
+    base: Option<i64>,
+    // size of ticker list consumed
+    consumed: usize,
+    // false when ticker time goes backward, recomputed from ticker list until in order
+    sorted: bool,
+}
+
+impl MovingAggregator {
+    pub fn new(unit: &TimeUnit) -> Self {
+        let period = unit.period as usize;
+        Self {
+            unit: unit.clone(),
+            size: unit.bar_size().max(1),
+            id: String::new(),
+            samples: VecDeque::new(),
+            head: 0,
+            cuts: vec![0; period + 1],
+            buckets: vec![Bucket::default(); period],
+            base: None,
+            consumed: 0,
+            sorted: true,
+        }
+    }
+
+    pub fn unit(&self) -> TimeUnit {
+        self.unit.clone()
+    }
+
+    // Consume new tickers (newest first) since last sync
+    // Returns false when tickers are out of order, full recomputation is required
+    pub fn sync(&mut self, tickers: &LinkedList<Ticker>) -> bool {
+        if !self.sorted || tickers.len() < self.consumed {
+            self.rebuild(tickers);
+        } else {
+            let news: Vec<&Ticker> = tickers.iter().take(tickers.len() - self.consumed).collect();
+            for ticker in news.into_iter().rev() {
+                if !self.push(ticker) {
+                    break;
+                }
+            }
+        }
+        self.consumed = tickers.len();
+        self.sorted
+    }
+
+    // Tickers dropped from the oldest side of list
+    pub fn evict(&mut self, count: usize) {
+        // unconsumed tickers dropped, recompute from ticker list
+        if count > self.consumed {
+            self.sorted = false;
+        }
+        self.consumed = self.consumed.saturating_sub(count);
+    }
+
+    // Aggregated protfolios, sort by time desc
+    pub fn protfolios(&self) -> Vec<Protfolio> {
+        (0..self.buckets.len())
+            .filter(|k| self.cuts[*k] > self.cuts[*k + 1])
+            .map(|k| self.protfolio(k))
+            .collect()
+    }
+
+    fn rebuild(&mut self, tickers: &LinkedList<Ticker>) {
+        *self = Self::new(&self.unit);
+        for ticker in scoped_tickers(&self.unit, tickers).into_iter().rev() {
+            if !self.push(ticker) {
+                break;
+            }
+        }
+    }
+
+    fn push(&mut self, ticker: &Ticker) -> bool {
+        let position = match (self.unit.bar, self.base) {
+            (BarType::Time, Some(base)) if ticker.time < base => {
+                self.sorted = false;
+                return false;
+            }
+            (BarType::Time, _) => ticker.time,
+            (BarType::Tick, base) => base.map_or(0, |base| base + 1),
+            (BarType::Volume, base) => base.unwrap_or(0) + ticker.volume.unwrap_or(0),
+        };
+
+        let sample = Sample {
+            position,
+            time: ticker.time,
+            price: ticker.price,
+            day_volume: ticker.day_volume,
+            quote_type: ticker.quote_type,
+            market_hours: ticker.market_hours,
+        };
+        let sequence = self.cuts[0];
+        if let Some(bucket) = self.buckets.first_mut() {
+            bucket.push(sequence, &sample);
+        }
+        self.samples.push_back(sample);
+        self.cuts[0] += 1;
+        self.id = ticker.id.clone();
+        self.base = Some(position);
+
+        // move samples to older buckets
+        let period = self.buckets.len();
+        for k in 1..=period {
+            let threshold = k as i64 * self.size;
+            while self.cuts[k] < self.cuts[k - 1] {
+                let sequence = self.cuts[k];
+                let sample = &self.samples[(sequence - self.head) as usize];
+                if position - sample.position < threshold {
+                    break;
+                }
+                self.buckets[k - 1].pop(sequence);
+                if k < period {
+                    self.buckets[k].push(sequence, sample);
+                }
+                self.cuts[k] += 1;
+            }
+        }
+
+        // drop samples out of scope
+        while self.head < self.cuts[period] {
+            self.samples.pop_front();
+            self.head += 1;
+        }
+
+        true
+    }
+
+    fn sample(&self, sequence: u64) -> &Sample {
+        &self.samples[(sequence - self.head) as usize]
+    }
+
+    fn protfolio(&self, k: usize) -> Protfolio {
+        let bucket = &self.buckets[k];
+        let first = self.sample(self.cuts[k] - 1);
+        let last = self.sample(self.cuts[k + 1]);
+        // time bar ends at bucket boundary, others end at the latest ticker
+        let unit_time = match self.unit.bar {
+            BarType::Time => self.base.unwrap() - k as i64 * self.size,
+            _ => first.time,
+        };
+        // samples newest first, same order as ticker list
+        let samples: Vec<&Sample> = (self.cuts[k + 1]..self.cuts[k])
+            .rev()
+            .map(|sequence| self.sample(sequence))
+            .collect();
+        let (slope, b_num) = calculate_slope(
+            &samples
+                .iter()
+                .map(|s| (s.time as f64, s.price as f64))
+                .collect::<Vec<(f64, f64)>>(),
+        );
+
+        Protfolio {
+            id: self.id.clone(),
+            price: average_price(samples.iter().map(|s| s.price)),
+            time: unit_time,
+            kind: 'p',
+            unit_time,
+            unit: self.unit.clone(),
+            period_type: self.unit.duration,
+            quote_type: first.quote_type,
+            market_hours: first.market_hours,
+            volume: first.day_volume - last.day_volume,
+            max_price: bucket.highs.front().unwrap().1,
+            min_price: bucket.lows.front().unwrap().1,
+            open_price: last.price,
+            close_price: first.price,
+            sample_size: samples.len() as u32,
+            slope: if slope.is_nan() { None } else { Some(slope) },
+            b_num: if b_num.is_nan() { None } else { Some(b_num) },
+        }
+    }
+}
+
+// Tickers in scope of moving unit, newest first
+pub fn scoped_tickers<'a>(unit: &TimeUnit, tickers: &'a LinkedList<Ticker>) -> Vec<&'a Ticker> {
+    match unit.bar {
+        BarType::Time => match tickers.front() {
+            Some(latest) => {
+                let scope = latest.time - unit.window();
+                tickers.iter().take_while(|t| t.time > scope).collect()
+            }
+            None => Vec::new(),
+        },
+        _ => {
+            // tick count or traded volume after the ticker
+            let scope = unit.bar_size() * unit.period as i64;
+            let mut distance = 0;
+            tickers
+                .iter()
+                .take_while(|t| {
+                    let scoped = distance < scope;
+                    distance += match unit.bar {
+                        BarType::Volume => t.volume.unwrap_or(0),
+                        _ => 1,
+                    };
+                    scoped
+                })
+                .collect()
+        }
+    }
+}
@@ -3,18 +3,19 @@ use crate::{
     persist::grafana::add_order_annotation,
     vo::{
         biz::{
-            AuditState, MarketHoursType, Order, PricePair, TotalProfit, TradeInfo, TradeTrend,
-            TradeTrendInfo, Trend,
+            AuditDecision, AuditOutcome, AuditState, CriterionKind, CriterionResult,
+            MarketHoursType, Order, PricePair, TotalProfit, TradeInfo, TradeTrend, TradeTrendInfo,
+            Trend,
         },
         core::{
-            AppConfig, AssetContext, AuditRule, DeviationCriteria, LowerCriteria,
-            OscillationCriteria, TrendCriteria, KEY_EXTRA_PRINT_TRADE_META_END_TIME,
-            KEY_EXTRA_PRINT_TRADE_META_START_TIME,
+            AppConfig, AssetContext, AuditMode, AuditRole, AuditRule, AuditRuleType,
+            DeviationCriteria, IndicatorCriteria, LowerCriteria, OscillationCriteria,
+            TrendCriteria,
         },
     },
     Result,
 };
-use chrono::{TimeZone, Utc};
+use chrono::{Duration, TimeZone, Utc};
 use log::*;
 use rsc::{
     computer::Computer,
@@ -38,7 +39,17 @@ pub fn prepare_trade(
     debug!("Trade info: {:?}", &trade);
 
     // audit trade
-    let state = audit_trade(Arc::clone(&asset), Arc::clone(&config), &trade);
+    let AuditOutcome {
+        state,
+        decision,
+        shadows,
+    } = audit_trade(Arc::clone(&asset), Arc::clone(&config), &trade);
+
+    // place hypothetical orders of evaluation-only rules
+    if !shadows.is_empty() && !asset.regular_marketing_closing(trade.action_time()) {
+        prepare_shadow_trade(Arc::clone(&asset), Arc::clone(&config), trade, &shadows);
+    }
+
     match state {
         AuditState::Flash
         | AuditState::Slug
@@ -66,6 +77,7 @@ pub fn prepare_trade(
                 estimated_volume,
                 trade.action_time(),
                 state.clone(),
+                decision,
             );
 
             if asset.add_order(order.clone()) {
@@ -106,7 +118,7 @@ pub fn prepare_trade(
                     .unwrap_or_default();
                 }
 
-                let time = Utc.timestamp_millis(trade.action_time());
+                let time = Utc.timestamp_millis_opt(trade.action_time()).unwrap();
                 let tags = vec![
                     trade.id.clone(),
                     order_id,
@@ -157,6 +169,7 @@ pub fn prepare_trade(
                 estimated_volume,
                 rival_trade.action_time(),
                 state.clone(),
+                None,
             );
 
             if asset.add_order(order.clone()) {
@@ -195,7 +208,7 @@ pub fn prepare_trade(
                     .unwrap_or_default();
                 }
 
-                let time = Utc.timestamp_millis(rival_trade.action_time());
+                let time = Utc.timestamp_millis_opt(rival_trade.action_time()).unwrap();
                 let tags = vec![
                     rival_trade.id.clone(),
                     order_id,
@@ -219,6 +232,53 @@ pub fn prepare_trade(
     Ok(())
 }
 
+// Place one hypothetical order for each fired evaluation-only rule, volume by max order amount
+fn prepare_shadow_trade(
+    asset: Arc<AssetContext>,
+    config: Arc<AppConfig>,
+    trade: &TradeInfo,
+    shadows: &[AuditDecision],
+) {
+    let max_amount = config.trade.get_option(&trade.id).max_order_amount;
+    let volume = ((max_amount as f32) / trade.price).round() as u32;
+    if volume == 0 {
+        return;
+    }
+
+    let mut rival_price = f32::NAN;
+    if let Some(rival_ticker) = asset.get_latest_rival_ticker(&trade.id) {
+        rival_price = rival_ticker.price;
+    }
+
+    for shadow in shadows {
+        let state = match config
+            .trade
+            .find_mode(&shadow.mode)
+            .and_then(|m| m.action(&shadow.mode))
+        {
+            Some(state) => state,
+            None => continue,
+        };
+        for index in &shadow.evaluations {
+            let order = Order::new(
+                &trade.id,
+                trade.price,
+                rival_price,
+                volume,
+                trade.action_time(),
+                state.clone(),
+                Some(AuditDecision {
+                    mode: shadow.mode.clone(),
+                    accepted: true,
+                    rule_index: Some(*index),
+                    ..Default::default()
+                }),
+            );
+            asset.add_shadow_order(order);
+        }
+    }
+}
+
 pub fn calculate_volum(asset: Arc<AssetContext>, config: Arc<AppConfig>, trade: &TradeInfo) -> u32 {
     // restricted amount
     let max_amount = config.trade.get_option(&trade.id).max_order_amount;
@@ -391,22 +451,9 @@ pub fn audit_trade(
     asset: Arc<AssetContext>,
     config: Arc<AppConfig>,
     trade: &TradeInfo,
-) -> AuditState {
+) -> AuditOutcome {
     // for debug, print period meta
-    if config.extra_present(KEY_EXTRA_PRINT_TRADE_META_START_TIME)
-        && config.extra_present(KEY_EXTRA_PRINT_TRADE_META_END_TIME)
-    {
-        let start_at = config
-            .extra_get(KEY_EXTRA_PRINT_TRADE_META_START_TIME)
-            .unwrap()
-            .parse::<i64>()
-            .unwrap();
-        let end_at = config
-            .extra_get(KEY_EXTRA_PRINT_TRADE_META_END_TIME)
-            .unwrap()
-            .parse::<i64>()
-            .unwrap();
-
+    if let Some((start_at, end_at)) = config.switches.meta_window {
         if trade.time > start_at && trade.time < end_at {
             // not affected by config.replay.exports.order
             print_meta(
@@ -422,15 +469,28 @@ pub fn audit_trade(
     }
 
     let mut result = AuditState::Decline;
+    let mut decision: Option<AuditDecision> = None;
+    let mut shadows: Vec<AuditDecision> = Vec::new();
+    let mut entered = false;
+    let mut exit_audited = false;
     let option = config.trade.get_option(&trade.id);
 
-    // flash check
-    if flash::audit(Arc::clone(&asset), Arc::clone(&config), trade) {
+    // entry modes check, ex: flash, slug
+    if let Some((state, entry_decision)) = audit_modes(
+        Arc::clone(&asset),
+        Arc::clone(&config),
+        trade,
+        AuditRole::Entry,
+        true,
+        &mut shadows,
+    ) {
         debug!(
-            "[{}] flash in, price = {}, profit check off",
-            &trade.id, &trade.price
+            "[{}] {} in, price = {}",
+            &trade.id, &entry_decision.mode, &trade.price
         );
-        result = AuditState::Flash;
+        result = state;
+        decision = Some(entry_decision);
+        entered = true;
     }
 
     let mut _last_profit_positive: Option<bool> = None;
@@ -470,13 +530,6 @@ pub fn audit_trade(
         }
     }
 
-    // slug check
-    if slug::audit(Arc::clone(&asset), Arc::clone(&config), trade) {
-        debug!("[{}] slug in, price = {}", &trade.id, &trade.price);
-        // TODO: check when last min > 0
-        result = AuditState::Slug;
-    }
-
     // check last pair order if exists, make sure make off gain profit
     if option.validate_increased_profit || option.enable_profit_take || option.enable_early_clear {
         if let Some(rival_symbol) = &rival_symbol_option {
@@ -494,7 +547,7 @@ pub fn audit_trade(
 
                     let estimated_profit = price_change * estimated_volume as f32;
 
-                    if matches!(result, AuditState::Flash | AuditState::Slug) {
+                    if entered {
                         if option.validate_increased_profit && !profit_increased.unwrap_or(true) {
                             debug!("[{}] block write off, price = {}", &trade.id, &trade.price);
                             result = AuditState::Decline;
@@ -522,27 +575,37 @@ pub fn audit_trade(
                                 result = AuditState::ProfitTaking;
                             }
                             // early sell when the trend is starting to go down
-                            else if option.enable_early_clear
-                                && revert::audit(Arc::clone(&asset), Arc::clone(&config), &trade)
-                            {
-                                debug!(
-                                    "[{}] early clear, price = {}, change rate = {:.5}/{:.5}",
-                                    &trade.id,
-                                    &trade.price,
-                                    rival_price_change_rate,
-                                    price_change_rate,
+                            else if option.enable_early_clear {
+                                exit_audited = true;
+                                let exit = audit_modes(
+                                    Arc::clone(&asset),
+                                    Arc::clone(&config),
+                                    trade,
+                                    AuditRole::Exit,
+                                    true,
+                                    &mut shadows,
                                 );
-                                trace!(
-                                "rival change rate: {rival_price_change_rate:.5}%, change rate: {price_change_rate:.5}%, change deviation: {change_deviation}%, rival profit: {rival_profit}, estimated profit: {estimated_profit} estimated volume: {estimated_volume}, total profit: {total_profit}",
-                                rival_price_change_rate = rival_price_change_rate * 100.0,
-                                price_change_rate = price_change_rate * 100.0,
-                                change_deviation = (rival_price_change_rate.abs() - price_change_rate.abs()) / rival_price_change_rate.abs(),
-                                rival_profit = rival_profit,
-                                estimated_profit = estimated_profit,
-                                estimated_volume = estimated_volume,
-                                total_profit = rival_profit + estimated_profit
-                            );
-                                result = AuditState::EarlyClear;
+                                if let Some((state, exit_decision)) = exit {
+                                    debug!(
+                                        "[{}] early clear, price = {}, change rate = {:.5}/{:.5}",
+                                        &trade.id,
+                                        &trade.price,
+                                        rival_price_change_rate,
+                                        price_change_rate,
+                                    );
+                                    trace!(
+                                    "rival change rate: {rival_price_change_rate:.5}%, change rate: {price_change_rate:.5}%, change deviation: {change_deviation}%, rival profit: {rival_profit}, estimated profit: {estimated_profit} estimated volume: {estimated_volume}, total profit: {total_profit}",
+                                    rival_price_change_rate = rival_price_change_rate * 100.0,
+                                    price_change_rate = price_change_rate * 100.0,
+                                    change_deviation = (rival_price_change_rate.abs() - price_change_rate.abs()) / rival_price_change_rate.abs(),
+                                    rival_profit = rival_profit,
+                                    estimated_profit = estimated_profit,
+                                    estimated_volume = estimated_volume,
+                                    total_profit = rival_profit + estimated_profit
+                                );
+                                    result = state;
+                                    decision = Some(exit_decision);
+                                }
                             }
                         }
                     }
@@ -550,6 +613,18 @@ pub fn audit_trade(
             }
         }
     }
+    // evaluation-only rules of exit modes run in shadow even if exit audit is skipped
+    if !exit_audited {
+        audit_modes(
+            Arc::clone(&asset),
+            Arc::clone(&config),
+            trade,
+            AuditRole::Exit,
+            false,
+            &mut shadows,
+        );
+    }
+
     // TODO: consider calculate diff between bear change rate / bulk change rate
 
     // TODO: reutrn if decline, unnecessary to check following
@@ -568,7 +643,11 @@ pub fn audit_trade(
                     "[{}] loss clear, price = {}, profit check off",
                     &trade.id, &trade.price
                 );
-                return AuditState::LossClear;
+                return AuditOutcome {
+                    state: AuditState::LossClear,
+                    decision: None,
+                    shadows,
+                };
             }
             result = AuditState::Decline;
         }
@@ -588,11 +667,19 @@ pub fn audit_trade(
                         "[{}] close clear, price = {}, profit check off, it's time to take rest",
                         &trade.id, &trade.price
                     );
-                        return AuditState::CloseTrade;
+                        return AuditOutcome {
+                            state: AuditState::CloseTrade,
+                            decision: None,
+                            shadows,
+                        };
                     }
                 } else {
                     // no running orders, decline all incoming orders when marketing is closing
-                    return AuditState::Decline;
+                    return AuditOutcome {
+                        state: AuditState::Decline,
+                        decision: None,
+                        shadows,
+                    };
                 }
             }
         }
@@ -601,7 +688,19 @@ pub fn audit_trade(
     // 區間內與最大值的價差（比率）
     // 與反向 eft 的利差（數值）
 
-    result
+    // decision only describes rule based states
+    if !matches!(
+        result,
+        AuditState::Flash | AuditState::Slug | AuditState::EarlyClear
+    ) {
+        decision = None;
+    }
+
+    AuditOutcome {
+        state: result,
+        decision,
+        shadows,
+    }
 }
 
 // see 'rate_calc.xlsx' in doc
@@ -743,8 +842,18 @@ fn recognize_loss(
     order: &Order,
 ) -> bool {
     if let Some(margin_rate) = match order.audit {
-        AuditState::Flash => config.trade.flash.loss_margin_rate,
-        AuditState::Slug => config.trade.slug.loss_margin_rate,
+        AuditState::Flash | AuditState::Slug => {
+            // margin rate of mode placed the order
+            let name = match &order.decision {
+                Some(decision) => decision.mode.as_str(),
+                None if order.audit == AuditState::Flash => "flash",
+                None => "slug",
+            };
+            config
+                .trade
+                .find_mode(name)
+                .and_then(|mode| mode.loss_margin_rate)
+        }
         _ => Some(0.006), // not affected
     } {
         let price = trade.price;
@@ -909,11 +1018,29 @@ pub fn validate_audit_rule(
     trade: &TradeInfo,
     rule: &AuditRule,
 ) -> bool {
-    let duration: usize = 10;
+    evaluate_audit_rule(asset, config, trade, rule)
+        .iter()
+        .all(|result| result.passed)
+}
+
+// Evaluate criteria of rule in order, stop at the first failed one
+pub fn evaluate_audit_rule(
+    asset: Arc<AssetContext>,
+    config: Arc<AppConfig>,
+    trade: &TradeInfo,
+    rule: &AuditRule,
+) -> Vec<CriterionResult> {
+    let mut results: Vec<CriterionResult> = Vec::new();
 
     // analysis trade trend and match config
-    if !validate_trend(Arc::clone(&asset), Arc::clone(&config), trade, &rule.trends) {
-        return false;
+    if !validate_trend(
+        Arc::clone(&asset),
+        Arc::clone(&config),
+        trade,
+        &rule.trends,
+        &mut results,
+    ) {
+        return results;
     }
 
     // validate deviations between current price to min price
@@ -921,20 +1048,20 @@ pub fn validate_audit_rule(
         Arc::clone(&asset),
         Arc::clone(&config),
         trade,
-        duration,
         &rule.deviations,
+        &mut results,
     ) {
-        return false;
+        return results;
     }
     // validate oscillations, between max price and min price
     if !validate_oscillation(
         Arc::clone(&asset),
         Arc::clone(&config),
         trade,
-        duration,
         &rule.oscillations,
+        &mut results,
     ) {
-        return false;
+        return results;
     }
 
     // validate min price, which has lower price than current min
@@ -942,13 +1069,177 @@ pub fn validate_audit_rule(
         Arc::clone(&asset),
         Arc::clone(&config),
         trade,
-        duration,
         &rule.lowers,
+        &mut results,
     ) {
-        return false;
+        return results;
     }
 
-    true
+    // validate technical indicators in range
+    validate_indicator(trade, &rule.indicators, &mut results);
+
+    results
+}
+
+// Audit trade by rules ordered by priority, evaluation-only rules are validated but never affect the result.
+// Remaining permit rules are skipped once one permitted, remaining live rules are skipped once one denied.
+pub fn audit_rules<'a>(
+    asset: Arc<AssetContext>,
+    config: Arc<AppConfig>,
+    trade: &TradeInfo,
+    mode: &str,
+    rules: impl Iterator<Item = (usize, &'a AuditRule)>,
+) -> AuditDecision {
+    let mut decision = AuditDecision::new(mode);
+    let mut results: Vec<bool> = Vec::new();
+    let mut denied = false;
+
+    let mut rules: Vec<(usize, &AuditRule)> = rules.collect();
+    rules.sort_by_key(|(_, rule)| -rule.priority);
+
+    // general validation from config rules, at least one success and no blocked rule
+    for (index, rule) in rules {
+        if !rule.evaluation
+            && (denied || (rule.mode == AuditRuleType::Permit && decision.rule_index.is_some()))
+        {
+            continue;
+        }
+
+        let criteria = evaluate_audit_rule(Arc::clone(&asset), Arc::clone(&config), trade, rule);
+        let validated = criteria.iter().all(|result| result.passed);
+
+        if rule.evaluation {
+            if validated {
+                decision.evaluations.push(index);
+            }
+            continue;
+        }
+
+        if validated {
+            match rule.mode {
+                AuditRuleType::Permit => {
+                    decision.rule_index = Some(index);
+                    decision.criteria = criteria;
+                    results.push(true);
+                }
+                AuditRuleType::Deny => {
+                    denied = true;
+                    results.push(false);
+                }
+            }
+        } else {
+            match rule.mode {
+                AuditRuleType::Permit => {} // ignore failed
+                AuditRuleType::Deny => {
+                    results.push(true);
+                }
+            }
+        }
+    }
+
+    decision.accepted = !results.is_empty() && results.iter().all(|success| *success);
+    decision.evaluations.sort_unstable();
+    decision
+}
+
+// Audit trade by rules of mode scoped to symbol, only evaluation-only rules are validated unless live
+pub fn audit_mode(
+    asset: Arc<AssetContext>,
+    config: Arc<AppConfig>,
+    trade: &TradeInfo,
+    name: &str,
+    mode: &AuditMode,
+    live: bool,
+) -> AuditDecision {
+    let mut decision = audit_rules(
+        Arc::clone(&asset),
+        Arc::clone(&config),
+        trade,
+        name,
+        scope_symbol_rules(&mode.rules, &trade.id, false)
+            .filter(|_| live)
+            .chain(scope_symbol_rules(&mode.rules, &trade.id, true)),
+    );
+
+    // check last order of mode action to prevent place mutiple orders
+    if let Some(cooldown) = mode.cooldown(name).filter(|_| decision.accepted) {
+        let action = mode.action(name).unwrap_or(AuditState::Decline);
+        if let Some(order) = asset.find_last_audit_order(&trade.id, &action) {
+            if trade.action_time() - order.created_time
+                < Duration::seconds(cooldown).num_milliseconds()
+            {
+                debug!(
+                    "Found order within {}s, ignore {} {:?}",
+                    cooldown, name, trade
+                );
+                decision.accepted = false;
+            }
+        }
+    }
+
+    decision
+}
+
+// Audit modes of role by priority, return the first accepted one with its action.
+// Decisions with evaluation-only rules fired are collected into shadows.
+fn audit_modes(
+    asset: Arc<AssetContext>,
+    config: Arc<AppConfig>,
+    trade: &TradeInfo,
+    role: AuditRole,
+    live: bool,
+    shadows: &mut Vec<AuditDecision>,
+) -> Option<(AuditState, AuditDecision)> {
+    let mut accepted: Option<(AuditState, AuditDecision)> = None;
+
+    for (name, mode) in config.trade.modes() {
+        let action = match (mode.action(name), mode.role(name)) {
+            (Some(action), Some(mode_role)) if mode_role == role => action,
+            _ => continue,
+        };
+
+        let decision = audit_mode(
+            Arc::clone(&asset),
+            Arc::clone(&config),
+            trade,
+            name,
+            mode,
+            live && accepted.is_none(),
+        );
+        if !decision.evaluations.is_empty() {
+            shadows.push(decision.clone());
+        }
+        if decision.accepted {
+            accepted = Some((action, decision));
+        }
+    }
+
+    accepted
+}
+
+// Rules scoped to symbol, fallback to general rules (without symbols) when no symbol rule configured
+pub fn scope_symbol_rules<'a>(
+    rules: &'a [AuditRule],
+    symbol: &str,
+    evaluation: bool,
+) -> impl Iterator<Item = (usize, &'a AuditRule)> + 'a {
+    let use_symbol_rule = rules
+        .iter()
+        .filter(|r| r.evaluation == evaluation)
+        .any(|r| r.symbols.iter().any(|s| s == symbol));
+    let symbol = symbol.to_string();
+
+    rules
+        .iter()
+        .enumerate()
+        .filter(move |(_, r)| r.evaluation == evaluation)
+        .filter(move |(_, r)| {
+            if use_symbol_rule {
+                r.symbols.contains(&symbol)
+            } else {
+                r.symbols.is_empty()
+            }
+        })
 }
 
 fn validate_trend(
@@ -956,26 +1247,27 @@ fn validate_trend(
     _config: Arc<AppConfig>,
     trade: &TradeInfo,
     trend_rules: &Vec<TrendCriteria>,
+    results: &mut Vec<CriterionResult>,
 ) -> bool {
     for trend_rule in trend_rules {
-        let mut trend_from = 0;
-        let trend_to = trend_rule.to[1..].parse::<usize>().unwrap();
-        if let Some(from) = &trend_rule.from {
-            trend_from = from[1..].parse::<usize>().unwrap();
-        }
-
-        // calculate min duration
-        let keys: Vec<String> = trade.states.keys().map(|k| k.to_string()).collect();
-        let duration = get_min_duration(trend_to - trend_from, &keys).unwrap();
-
-        let rebound = rebound(&duration, trend_from, trade.states.get(&duration).unwrap());
+        let span = &trend_rule.span;
+        let rebound = match trade.states.get(&span.unit) {
+            Some(slopes) => rebound(&span.unit, span.from, slopes),
+            None => {
+                results.push(CriterionResult {
+                    kind: CriterionKind::Trend,
+                    from: trend_rule.from.clone(),
+                    to: trend_rule.to.clone(),
+                    value: format!("no slopes of {}", span.unit),
+                    threshold: format!("{:?}", trend_rule.trend),
+                    passed: false,
+                });
+                return false;
+            }
+        };
         let actual_trend = rebound.trend;
         let expected_trend = &trend_rule.trend;
 
-        if &actual_trend != expected_trend {
-            return false;
-        }
-
         let first_section = rebound.sections[0];
         let second_section = if rebound.sections.len() > 1 {
             rebound.sections[1]
@@ -983,78 +1275,119 @@ fn validate_trend(
             0
         };
 
-        match actual_trend {
-            Trend::Upward => {
-                if !trend_rule.up_compare(first_section) {
-                    return false;
-                }
-                if !trend_rule.down_compare(second_section) {
-                    return false;
-                }
-            }
-            Trend::Downward => {
-                if !trend_rule.down_compare(first_section) {
-                    return false;
+        let passed = &actual_trend == expected_trend
+            && match actual_trend {
+                Trend::Upward => {
+                    trend_rule.up_compare(first_section) && trend_rule.down_compare(second_section)
                 }
-                if !trend_rule.up_compare(second_section) {
-                    return false;
+                Trend::Downward => {
+                    trend_rule.down_compare(first_section) && trend_rule.up_compare(second_section)
                 }
-            }
+            };
+
+        results.push(CriterionResult {
+            kind: CriterionKind::Trend,
+            from: trend_rule.from.clone(),
+            to: trend_rule.to.clone(),
+            value: format!("{:?} {:?}", actual_trend, rebound.sections),
+            threshold: format!(
+                "{:?} up: {} down: {}",
+                expected_trend,
+                trend_rule.up.as_deref().unwrap_or("*"),
+                trend_rule.down.as_deref().unwrap_or("*")
+            ),
+            passed,
+        });
+
+        if !passed {
+            return false;
         }
     }
 
     true
 }
 
-pub fn get_min_duration(duration: usize, keys: &Vec<String>) -> Result<String> {
-    let mut values: Vec<usize> = keys
-        .iter()
-        .map(|key| key[1..].parse::<usize>().unwrap())
-        .collect();
-
-    values.sort();
-    values.reverse();
-    for value in values {
-        if duration % value == 0 {
-            return Ok(format!("m{:04}", value));
+fn validate_indicator(
+    trade: &TradeInfo,
+    indicator_rules: &Vec<IndicatorCriteria>,
+    results: &mut Vec<CriterionResult>,
+) -> bool {
+    for indicator_rule in indicator_rules {
+        // not available before indicator warmed up
+        let value = trade
+            .indicator(&indicator_rule.unit, &indicator_rule.name)
+            .and_then(|values| values.get(indicator_rule.index))
+            .copied()
+            .unwrap_or(f64::NAN);
+
+        let passed = value.is_finite()
+            && indicator_rule.min.iter().all(|&min| value >= min)
+            && indicator_rule.max.iter().all(|&max| value <= max);
+        results.push(CriterionResult {
+            kind: CriterionKind::Indicator,
+            from: None,
+            to: format!(
+                "{}.{}[{}]",
+                indicator_rule.unit, indicator_rule.name, indicator_rule.index
+            ),
+            value: value.to_string(),
+            threshold: format!(
+                "{} - {}",
+                indicator_rule
+                    .min
+                    .map_or("*".to_string(), |min| min.to_string()),
+                indicator_rule
+                    .max
+                    .map_or("*".to_string(), |max| max.to_string())
+            ),
+            passed,
+        });
+
+        if !passed {
+            return false;
         }
     }
-    panic!("No available minimal duration found");
+
+    true
 }
 
 fn validate_deviation(
     asset: Arc<AssetContext>,
     _config: Arc<AppConfig>,
     trade: &TradeInfo,
-    duration: usize,
     deviation_rules: &Vec<DeviationCriteria>,
+    results: &mut Vec<CriterionResult>,
 ) -> bool {
     for deviation_rule in deviation_rules {
-        let mut period_from = 0;
-
-        if let Some(from) = &deviation_rule.from {
-            period_from = from[1..].parse::<usize>().unwrap() / duration;
-        }
-
-        let base_unit = format!("m{:04}", duration);
-        // parse period from key (ex: m0070 => 70 / 10 = 7)
-        let period_to = deviation_rule.to[1..].parse::<usize>().unwrap() / duration;
+        let span = &deviation_rule.span;
 
         // min price
         let min_price = find_min_price(
             Arc::clone(&asset),
             &trade.id,
-            &base_unit,
-            period_from,
-            period_to,
+            &span.unit,
+            span.from,
+            span.to,
         );
 
+        let deviation = (trade.price - min_price) / min_price;
+        let passed = min_price.is_normal() && deviation <= deviation_rule.value;
+        results.push(CriterionResult {
+            kind: CriterionKind::Deviation,
+            from: deviation_rule.from.clone(),
+            to: deviation_rule.to.clone(),
+            value: deviation.to_string(),
+            threshold: format!("<= {}", deviation_rule.value),
+            passed,
+        });
+
         // assume trade price is higher than min_price
-        if !min_price.is_normal() || (trade.price - min_price) / min_price > deviation_rule.value {
+        if !passed {
             debug!(
-                "validate min price failed, period: {:04} - {:04}, price: {}, min price: {}, value {} < deviation {}",
-                period_from * duration,
-                period_to * duration,
+                "validate min price failed, period: {} [{}, {}), price: {}, min price: {}, value {} < deviation {}",
+                span.unit,
+                span.from,
+                span.to,
                 trade.price,
                 min_price,
                 (trade.price - min_price) / min_price,
@@ -1071,46 +1404,47 @@ fn validate_oscillation(
     asset: Arc<AssetContext>,
     _config: Arc<AppConfig>,
     trade: &TradeInfo,
-    duration: usize,
     oscillation_rules: &Vec<OscillationCriteria>,
+    results: &mut Vec<CriterionResult>,
 ) -> bool {
     for oscillation_rule in oscillation_rules {
-        let mut period_from = 0;
-
-        if let Some(from) = &oscillation_rule.from {
-            period_from = from[1..].parse::<usize>().unwrap() / duration;
-        }
-
-        let base_unit = format!("m{:04}", duration);
-
-        // parse period from key (ex: m0070 => 70 / 10 = 7)
-        let period_to = oscillation_rule.to[1..].parse::<usize>().unwrap() / duration;
+        let span = &oscillation_rule.span;
 
         // min price
         let min_price = find_min_price(
             Arc::clone(&asset),
             &trade.id,
-            &base_unit,
-            period_from,
-            period_to,
+            &span.unit,
+            span.from,
+            span.to,
         );
         let max_price = find_max_price(
             Arc::clone(&asset),
             &trade.id,
-            &base_unit,
-            period_from,
-            period_to,
+            &span.unit,
+            span.from,
+            span.to,
         );
 
+        let oscillation = (max_price - min_price) / max_price;
+        let passed =
+            max_price.is_normal() && min_price.is_normal() && oscillation >= oscillation_rule.value;
+        results.push(CriterionResult {
+            kind: CriterionKind::Oscillation,
+            from: oscillation_rule.from.clone(),
+            to: oscillation_rule.to.clone(),
+            value: oscillation.to_string(),
+            threshold: format!(">= {}", oscillation_rule.value),
+            passed,
+        });
+
         // assume trade price is higher than min_price
-        if !max_price.is_normal()
-            || !min_price.is_normal()
-            || (max_price - min_price) / max_price < oscillation_rule.value
-        {
+        if !passed {
             debug!(
-                "validate oscillation failed, period: {:04} - {:04}, max price: {}, min price: {}, rate {} < oscillation {}",
-                period_from * duration,
-                period_to * duration,
+                "validate oscillation failed, period: {} [{}, {}), max price: {}, min price: {}, rate {} < oscillation {}",
+                span.unit,
+                span.from,
+                span.to,
                 max_price,
                 min_price,
                 (max_price - min_price) / max_price,
@@ -1127,197 +1461,49 @@ fn validate_lower(
     asset: Arc<AssetContext>,
     _config: Arc<AppConfig>,
     trade: &TradeInfo,
-    duration: usize,
     lower_rules: &Vec<LowerCriteria>,
+    results: &mut Vec<CriterionResult>,
 ) -> bool {
     for lower_rule in lower_rules {
-        let mut period_from = 0;
-
-        if let Some(from) = &lower_rule.from {
-            period_from = from[1..].parse::<usize>().unwrap() / duration;
-        }
-
-        let base_unit = format!("m{:04}", duration);
-
-        // parse period from key (ex: m0070 => 70 / 10 = 7)
-        let period_to = lower_rule.to[1..].parse::<usize>().unwrap() / duration;
+        let span = &lower_rule.span;
 
         // get min price from wider range
         let min_price = find_min_price(
             Arc::clone(&asset),
             &trade.id,
-            &base_unit,
-            period_from,
-            period_to,
+            &span.unit,
+            span.from,
+            span.to,
         );
 
         // find price time lower than min_price before
+        let mut recent_min_price = f32::NAN;
+        let mut passed = true;
         if min_price.is_normal() {
-            let recent_period_to = lower_rule.compare_to[1..].parse::<usize>().unwrap() / duration;
-            let recent_min_price = find_min_price(
+            let compare_span = &lower_rule.compare_span;
+            recent_min_price = find_min_price(
                 Arc::clone(&asset),
                 &trade.id,
-                &base_unit,
-                0,
-                recent_period_to,
+                &compare_span.unit,
+                compare_span.from,
+                compare_span.to,
             );
 
-            if !recent_min_price.is_normal() || recent_min_price > min_price {
-                return false;
-            }
+            passed = recent_min_price.is_normal() && recent_min_price <= min_price;
         };
-    }
-
-    true
-}
-
-pub mod flash {
-
-    use super::validate_audit_rule;
-    use crate::vo::{
-        biz::TradeInfo,
-        core::{AppConfig, AssetContext, AuditRuleType},
-    };
-    use chrono::Duration;
-    use log::*;
-    use std::sync::Arc;
-
-    pub fn audit(asset: Arc<AssetContext>, config: Arc<AppConfig>, trade: &TradeInfo) -> bool {
-        let mut results: Vec<bool> = Vec::new();
-
-        let use_symbol_rule = config
-            .trade
-            .flash
-            .rules
-            .iter()
-            .filter(|r| r.symbols.contains(&trade.id))
-            .filter(|r| !r.evaluation)
-            .any(|_| true);
-
-        // general validation from config rules, at least one success and no blocked rule
-        for rule in config.trade.flash.rules.iter().filter(|r| !r.evaluation) {
-            if (use_symbol_rule && !rule.symbols.contains(&trade.id))
-                || (!use_symbol_rule && !rule.symbols.is_empty())
-            {
-                continue;
-            }
-
-            if validate_audit_rule(Arc::clone(&asset), Arc::clone(&config), trade, rule) {
-                match rule.mode {
-                    AuditRuleType::Permit => {
-                        results.push(true);
-                    }
-                    AuditRuleType::Deny => {
-                        results.push(false);
-                    }
-                }
-            } else {
-                match rule.mode {
-                    AuditRuleType::Permit => {} // ignore failed
-                    AuditRuleType::Deny => {
-                        results.push(true);
-                    }
-                }
-            }
-        }
-
-        // check last order to prevent place mutiple orders (watch within 30s)
-        if let Some(order) = asset.find_last_flash_order(&trade.id) {
-            if trade.action_time() - order.created_time < Duration::seconds(30).num_milliseconds() {
-                debug!("Found flash order within 30s, ignore {:?}", trade);
-                results.push(false);
-            }
-        }
-
-        !results.is_empty() && results.iter().all(|success| *success)
-    }
-}
-
-pub mod slug {
-
-    use super::validate_audit_rule;
-    use crate::vo::{
-        biz::TradeInfo,
-        core::{AppConfig, AssetContext, AuditRuleType},
-    };
-    use std::sync::Arc;
-
-    pub fn audit(asset: Arc<AssetContext>, config: Arc<AppConfig>, trade: &TradeInfo) -> bool {
-        let mut results: Vec<bool> = Vec::new();
-
-        let use_symbol_rule = config
-            .trade
-            .slug
-            .rules
-            .iter()
-            .filter(|r| r.symbols.contains(&trade.id))
-            .filter(|r| !r.evaluation)
-            .any(|_| true);
-
-        // general validation from config rules, at least one success and no blocked rule
-        for rule in config.trade.slug.rules.iter().filter(|r| !r.evaluation) {
-            if (use_symbol_rule && !rule.symbols.contains(&trade.id))
-                || (!use_symbol_rule && !rule.symbols.is_empty())
-            {
-                continue;
-            }
-
-            if validate_audit_rule(Arc::clone(&asset), Arc::clone(&config), trade, rule) {
-                match rule.mode {
-                    AuditRuleType::Permit => {
-                        results.push(true);
-                    }
-                    AuditRuleType::Deny => {
-                        results.push(false);
-                    }
-                }
-            } else {
-                match rule.mode {
-                    AuditRuleType::Permit => {} // ignore failed
-                    AuditRuleType::Deny => {
-                        results.push(true);
-                    }
-                }
-            }
+        results.push(CriterionResult {
+            kind: CriterionKind::Lower,
+            from: lower_rule.from.clone(),
+            to: lower_rule.to.clone(),
+            value: recent_min_price.to_string(),
+            threshold: format!("<= {}", min_price),
+            passed,
+        });
+
+        if !passed {
+            return false;
         }
-
-        !results.is_empty() && results.iter().all(|success| *success)
     }
-}
-
-pub mod revert {
 
-    use super::validate_audit_rule;
-    use crate::vo::{
-        biz::TradeInfo,
-        core::{AppConfig, AssetContext, AuditRuleType},
-    };
-    use std::sync::Arc;
-
-    pub fn audit(asset: Arc<AssetContext>, config: Arc<AppConfig>, trade: &TradeInfo) -> bool {
-        let mut results: Vec<bool> = Vec::new();
-
-        // general validation from config rules, at least one success and no blocked rule
-        for rule in config.trade.revert.rules.iter().filter(|r| !r.evaluation) {
-            if validate_audit_rule(Arc::clone(&asset), Arc::clone(&config), trade, rule) {
-                match rule.mode {
-                    AuditRuleType::Permit => {
-                        results.push(true);
-                    }
-                    AuditRuleType::Deny => {
-                        results.push(false);
-                    }
-                }
-            } else {
-                match rule.mode {
-                    AuditRuleType::Permit => {} // ignore failed
-                    AuditRuleType::Deny => {
-                        results.push(true);
-                    }
-                }
-            }
-        }
-
-        !results.is_empty() && results.iter().all(|success| *success)
-    }
+    true
 }
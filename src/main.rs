@@ -1,22 +1,24 @@
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use clap::{Arg, ArgMatches, Command};
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use sminer::{
-    analysis::{replay, ReplayMode},
+    analysis::{
+        optimize::{optimize, walk_forward},
+        replay_files,
+    },
     init_log,
     persist::{
         es::{
             index_protfolios_from_file, index_tickers_from_file, take_index_time, ticker_index_name,
         },
         grafana::clear_annotations,
-        mongo::{export, import},
+        mongo::{export, import, TickerFilter},
+        parquet,
+        reader::{convert_file, source_name, PROTOBUF_EXTENSION},
+        sqlite::{self, TickerQuery},
     },
     provider::yahoo::consume,
-    vo::core::{
-        AppConfig, AppContext, KEY_EXTRA_CONFIG_FILE_PATH, KEY_EXTRA_ENABLE_DATA_TRUNCAT,
-        KEY_EXTRA_PRCOESS_IN_ASYNC, KEY_EXTRA_PRINT_TRADE_META_END_TIME,
-        KEY_EXTRA_PRINT_TRADE_META_START_TIME,
-    },
+    vo::core::{parse_override, AppConfig, AppContext, SweepSpec},
     Result,
 };
 use std::{collections::HashSet, sync::Arc};
@@ -34,9 +36,22 @@ async fn main() -> Result<()> {
 
             debug!("matches: {:?}", sub_matches);
 
-            // init
-            let mut config = AppConfig::load(config_file)?;
-            config.extra_put(KEY_EXTRA_CONFIG_FILE_PATH, config_file);
+            // init, invalid config rejected on startup
+            let overrides = sub_matches
+                .values_of("set")
+                .unwrap_or_default()
+                .map(parse_override)
+                .collect::<Result<Vec<_>>>()?;
+            let profile = sub_matches.value_of("profile");
+            let mut config = match AppConfig::load_layered(config_file, profile, &overrides) {
+                Ok(config) => config,
+                Err(e) => {
+                    for line in e.to_string().lines() {
+                        error!("{}", line);
+                    }
+                    return Err(format!("Invalid config file: {}", config_file).into());
+                }
+            };
 
             match name {
                 "consume" => {
@@ -45,6 +60,12 @@ async fn main() -> Result<()> {
                 "replay" => {
                     perform_replay(&mut config, sub_matches).await?;
                 }
+                "optimize" => {
+                    perform_optimize(&mut config, sub_matches).await?;
+                }
+                "walk-forward" => {
+                    perform_walk_forward(&mut config, sub_matches).await?;
+                }
                 "import" => {
                     perform_import(&mut config, sub_matches).await?;
                 }
@@ -54,9 +75,18 @@ async fn main() -> Result<()> {
                 "index" => {
                     perform_index(&mut config, sub_matches).await?;
                 }
+                "convert" => {
+                    perform_convert(&mut config, sub_matches).await?;
+                }
+                "es-setup" => {
+                    perform_es_setup(&mut config, sub_matches).await?;
+                }
                 "annotate" => {
                     perform_annotate(&mut config, sub_matches).await?;
                 }
+                "validate-config" => {
+                    perform_validate_config(&mut config, sub_matches).await?;
+                }
                 _ => {}
             }
         }
@@ -71,8 +101,9 @@ async fn main() -> Result<()> {
 
 async fn perform_consume(config: &mut AppConfig, _sub_matches: &ArgMatches) -> Result<()> {
     // add additional config
-    config.extra_put(KEY_EXTRA_PRCOESS_IN_ASYNC, "async_mode");
+    config.switches.async_process = true;
     let context = AppContext::new(config.to_owned()).init().await?;
+    context.watch_config()?;
     let config = context.config();
 
     let symbols = config.symbols();
@@ -98,7 +129,7 @@ async fn perform_replay(config: &mut AppConfig, sub_matches: &ArgMatches) -> Res
             from = Some(time.with_timezone(&Utc));
         }
         if let Ok(timestamp) = start.parse::<i64>() {
-            from = Some(Utc.timestamp_millis(timestamp));
+            from = Some(Utc.timestamp_millis_opt(timestamp).unwrap());
         }
     }
 
@@ -107,27 +138,16 @@ async fn perform_replay(config: &mut AppConfig, sub_matches: &ArgMatches) -> Res
             to = Some(time.with_timezone(&Utc));
         }
         if let Ok(timestamp) = end.parse::<i64>() {
-            to = Some(Utc.timestamp_millis(timestamp));
+            to = Some(Utc.timestamp_millis_opt(timestamp).unwrap());
         }
     }
 
-    if let Some(start) = from {
-        config.extra_put(
-            KEY_EXTRA_PRINT_TRADE_META_START_TIME,
-            &start.timestamp_millis().to_string(),
-        )
-    }
-    if let Some(end) = to {
-        config.extra_put(
-            KEY_EXTRA_PRINT_TRADE_META_END_TIME,
-            &end.timestamp_millis().to_string(),
-        )
+    if let (Some(start), Some(end)) = (from, to) {
+        config.switches.meta_window = Some((start.timestamp_millis(), end.timestamp_millis()));
     }
 
     let start_time = Utc::now().timestamp_millis();
     config_truncat(config, sub_matches)?;
-    let context = AppContext::new(config.to_owned()).init().await?;
-    let config = context.config();
 
     info!(
         "Available time unit: {:?}",
@@ -138,14 +158,16 @@ async fn perform_replay(config: &mut AppConfig, sub_matches: &ArgMatches) -> Res
             .collect::<Vec<_>>()
     );
 
-    let files: Vec<&str> = sub_matches.values_of("files").unwrap().collect();
+    let files: Vec<String> = sub_matches
+        .values_of("files")
+        .unwrap()
+        .map(|f| f.to_owned())
+        .collect();
     debug!("Input files: {:?}", files);
+    let jobs = sub_matches.value_of("jobs").unwrap().parse::<usize>()?;
 
-    for file in files {
-        // TODO: try renew context for release resource
-        // compare to original if speed up
-        replay(&context, &file, ReplayMode::Sync).await?
-    }
+    // every file replays with its own context
+    replay_files(&config, &files, jobs).await?;
 
     info!(
         "Replay time cost: {}",
@@ -155,15 +177,78 @@ async fn perform_replay(config: &mut AppConfig, sub_matches: &ArgMatches) -> Res
     Ok(())
 }
 
+async fn perform_optimize(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
+    let start_time = Utc::now().timestamp_millis();
+    let spec = SweepSpec::load(sub_matches.value_of("sweep-file").unwrap())?;
+    let files: Vec<String> = sub_matches
+        .values_of("files")
+        .unwrap()
+        .map(|f| f.to_owned())
+        .collect();
+    debug!("Input files: {:?}", files);
+    let jobs = sub_matches.value_of("jobs").unwrap().parse::<usize>()?;
+
+    optimize(&config, &spec, &files, jobs).await?;
+
+    info!(
+        "Optimize time cost: {}",
+        Duration::milliseconds(Utc::now().timestamp_millis() - start_time)
+    );
+
+    Ok(())
+}
+
+async fn perform_walk_forward(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
+    let start_time = Utc::now().timestamp_millis();
+    let spec = SweepSpec::load(sub_matches.value_of("sweep-file").unwrap())?;
+    let files: Vec<String> = sub_matches
+        .values_of("files")
+        .unwrap()
+        .map(|f| f.to_owned())
+        .collect();
+    debug!("Input files: {:?}", files);
+    let in_sample = sub_matches
+        .value_of("in-sample")
+        .unwrap()
+        .parse::<usize>()?;
+    let out_of_sample = sub_matches
+        .value_of("out-of-sample")
+        .unwrap()
+        .parse::<usize>()?;
+    let jobs = sub_matches.value_of("jobs").unwrap().parse::<usize>()?;
+
+    walk_forward(&config, &spec, &files, in_sample, out_of_sample, jobs).await?;
+
+    info!(
+        "Walk-forward time cost: {}",
+        Duration::milliseconds(Utc::now().timestamp_millis() - start_time)
+    );
+
+    Ok(())
+}
+
 async fn perform_import(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
     config_truncat(config, sub_matches)?;
 
     let context = AppContext::new(config.to_owned()).init().await?;
 
+    let config = context.config();
+    if !config.data_source.mongodb.enabled && !config.data_source.sqlite.enabled {
+        return Err("No MongoDB or SQLite data source enabled for import".into());
+    }
+
     let files: Vec<&str> = sub_matches.values_of("files").unwrap().collect();
     debug!("Input files: {:?}", files);
-    for file in files {
-        import(&context, &file).await?;
+    let collection = sub_matches.value_of("collection");
+    for (index, file) in files.iter().enumerate() {
+        if config.data_source.mongodb.enabled {
+            // files into the same collection are truncated once
+            let truncat = config.truncat_enabled() && (collection.is_none() || index == 0);
+            import(&context, file, collection, truncat).await?;
+        }
+        if config.data_source.sqlite.enabled {
+            sqlite::import(&context, file).await?;
+        }
     }
 
     Ok(())
@@ -174,8 +259,29 @@ async fn perform_export(config: &mut AppConfig, sub_matches: &ArgMatches) -> Res
 
     let files: Vec<&str> = sub_matches.values_of("collections").unwrap().collect();
     debug!("Target collections: {:?}", files);
+    let format = sub_matches.value_of("format").unwrap().to_lowercase();
+    debug!("Export format: {}", format);
+    let filter = TickerFilter::parse(
+        &sub_matches
+            .values_of("symbol")
+            .unwrap_or_default()
+            .collect::<Vec<&str>>(),
+        sub_matches.value_of("from"),
+        sub_matches.value_of("to"),
+    )?;
+    debug!("Export filter: {:?}", filter);
     for file in files {
-        export(&context, &file).await?;
+        if !filter.is_empty() && file.starts_with(sqlite::SPEC_PREFIX) {
+            warn!("Filters are ignored for {}, put them in the spec", file);
+        }
+        if format == "parquet" {
+            parquet::export(&context, file, &filter).await?;
+            continue;
+        }
+        match TickerQuery::parse(file)? {
+            Some(query) => sqlite::export(&context, &query).await?,
+            None => export(&context, file, &filter).await?,
+        }
     }
 
     Ok(())
@@ -228,6 +334,49 @@ async fn perform_index(config: &mut AppConfig, sub_matches: &ArgMatches) -> Resu
     Ok(())
 }
 
+async fn perform_convert(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
+    let format = sub_matches.value_of("format").unwrap().to_lowercase();
+    let compress = sub_matches.value_of("compress").unwrap().to_lowercase();
+    let files: Vec<&str> = sub_matches.values_of("files").unwrap().collect();
+    debug!("Convert files: {:?} to {} ({})", files, format, compress);
+
+    for file in files {
+        let mut target = format!(
+            "{}/{}",
+            &config.replay.outputs.base_folder,
+            source_name(file)
+        );
+        if format == "protobuf" {
+            target.push_str(PROTOBUF_EXTENSION);
+        }
+        if compress != "none" {
+            target.push_str(&format!(".{}", compress));
+        }
+        let count = convert_file(file, &target)?;
+        info!("File {} converted to {}, tickers: {}", file, &target, count);
+    }
+
+    Ok(())
+}
+
+async fn perform_es_setup(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
+    let context = AppContext::new(config.to_owned()).init().await?;
+    let persistence = context.persistence();
+
+    let lifecycle_days = match sub_matches.value_of("lifecycle-days") {
+        Some(days) => Some(days.parse::<u32>()?),
+        None => None,
+    };
+    if let Some(days) = lifecycle_days {
+        persistence.put_lifecycle_policy(days).await?;
+    }
+    persistence
+        .put_index_templates(lifecycle_days.is_some())
+        .await?;
+
+    Ok(())
+}
+
 async fn perform_annotate(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
     let mut from: Option<DateTime<Utc>> = None;
     let mut to: Option<DateTime<Utc>> = None;
@@ -237,7 +386,7 @@ async fn perform_annotate(config: &mut AppConfig, sub_matches: &ArgMatches) -> R
             from = Some(time.with_timezone(&Utc));
         }
         if let Ok(timestamp) = start.parse::<i64>() {
-            from = Some(Utc.timestamp_millis(timestamp));
+            from = Some(Utc.timestamp_millis_opt(timestamp).unwrap());
         }
     }
 
@@ -246,7 +395,7 @@ async fn perform_annotate(config: &mut AppConfig, sub_matches: &ArgMatches) -> R
             to = Some(time.with_timezone(&Utc));
         }
         if let Ok(timestamp) = end.parse::<i64>() {
-            to = Some(Utc.timestamp_millis(timestamp));
+            to = Some(Utc.timestamp_millis_opt(timestamp).unwrap());
         }
     }
 
@@ -269,6 +418,20 @@ async fn perform_annotate(config: &mut AppConfig, sub_matches: &ArgMatches) -> R
     Ok(())
 }
 
+async fn perform_validate_config(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
+    // checked on load, warnings are logged already
+    let warnings = config.validate()?;
+    info!(
+        "Config {} is valid, units: {}, modes: {}, warnings: {}",
+        sub_matches.value_of("config-file").unwrap(),
+        config.units.len(),
+        config.trade.modes().len(),
+        warnings.len()
+    );
+
+    Ok(())
+}
+
 fn config_truncat(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()> {
     let truncat_data = sub_matches.is_present("truncat")
         && sub_matches
@@ -276,9 +439,7 @@ fn config_truncat(config: &mut AppConfig, sub_matches: &ArgMatches) -> Result<()
             .unwrap()
             .to_lowercase()
             .parse::<bool>()?;
-    if truncat_data {
-        config.extra_put(KEY_EXTRA_ENABLE_DATA_TRUNCAT, "truncat");
-    }
+    config.switches.truncate = truncat_data;
 
     Ok(())
 }
@@ -299,6 +460,22 @@ fn command_args<'help>() -> Command<'help> {
         .default_value("config.yaml")
         .help("Path of config file");
 
+    let profile = Arg::new("profile")
+        .long("profile")
+        .takes_value(true)
+        .required(false)
+        .help(
+            "Profile overlay of config file, ex: prod for config.prod.yaml, or by SMINER_PROFILE",
+        );
+
+    let overrides = Arg::new("set")
+        .long("set")
+        .takes_value(true)
+        .value_name("PATH=VALUE")
+        .multiple_occurrences(true)
+        .required(false)
+        .help("Override config value by path, over SMINER_* environment variables");
+
     Command::new("sminer - Analysis and miner for stock infomation")
         .version("0.1.0")
         .author("Bruce Tsai")
@@ -306,12 +483,19 @@ fn command_args<'help>() -> Command<'help> {
         .subcommands(vec![
             Command::new("consume")
                 .about("Consume message for analysis")
-                .args(&[level.clone(), config_file.clone()]),
+                .args(&[
+                    level.clone(),
+                    config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                ]),
             Command::new("replay")
                 .about("Replay message for analysis")
                 .args(&[
                     level.clone(),
                     config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
                     Arg::new("truncat")
                         .short('k')
                         .long("truncat")
@@ -329,17 +513,83 @@ fn command_args<'help>() -> Command<'help> {
                         .takes_value(true)
                         .required(false)
                         .help("Print meta of trades, end time"),
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Number of files to be replayed in parallel"),
+                    Arg::new("files")
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .required(true)
+                        .help("Source files to be replay, or sqlite:[symbols@]from[..to]"),
+                ]),
+            Command::new("optimize")
+                .about("Replay message with parameter sweep of config")
+                .args(&[
+                    level.clone(),
+                    config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                    Arg::new("sweep-file")
+                        .short('s')
+                        .long("sweep")
+                        .default_value("sweep.yaml")
+                        .help("Path of sweep spec file"),
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Number of files to be replayed in parallel"),
                     Arg::new("files")
                         .takes_value(true)
                         .multiple_values(true)
                         .required(true)
                         .help("Source files to be replay"),
                 ]),
+            Command::new("walk-forward")
+                .about("Validate parameter sweep with rolling in-sample and out-of-sample days")
+                .args(&[
+                    level.clone(),
+                    config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                    Arg::new("sweep-file")
+                        .short('s')
+                        .long("sweep")
+                        .default_value("sweep.yaml")
+                        .help("Path of sweep spec file"),
+                    Arg::new("in-sample")
+                        .long("in-sample")
+                        .takes_value(true)
+                        .default_value("5")
+                        .help("Days of in-sample window"),
+                    Arg::new("out-of-sample")
+                        .long("out-of-sample")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Days of out-of-sample window"),
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Number of files to be replayed in parallel"),
+                    Arg::new("files")
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .required(true)
+                        .help("Dated source files to be replay"),
+                ]),
             Command::new("import")
-                .about("Import message into MongoDB collection")
+                .about("Import message into MongoDB collection or SQLite database")
                 .args(&[
                     level.clone(),
                     config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
                     Arg::new("truncat")
                         .short('k')
                         .long("truncat")
@@ -347,6 +597,11 @@ fn command_args<'help>() -> Command<'help> {
                         .default_value("true")
                         .ignore_case(true)
                         .help("Truncat existing data"),
+                    Arg::new("collection")
+                        .short('c')
+                        .long("collection")
+                        .takes_value(true)
+                        .help("Target MongoDB collection, named as file by default"),
                     Arg::new("files")
                         .takes_value(true)
                         .multiple_values(true)
@@ -354,21 +609,45 @@ fn command_args<'help>() -> Command<'help> {
                         .help("Source files to be import"),
                 ]),
             Command::new("export")
-                .about("Export message from MongoDB collection")
+                .about("Export message from MongoDB collection or SQLite database")
                 .args(&[
                     level.clone(),
                     config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                    Arg::new("format")
+                        .long("format")
+                        .possible_values(["json", "parquet"])
+                        .default_value("json")
+                        .ignore_case(true)
+                        .help("Output format, json lines or columnar parquet"),
+                    Arg::new("symbol")
+                        .short('s')
+                        .long("symbol")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Export tickers of symbols only, ex: TQQQ,SQQQ"),
+                    Arg::new("from")
+                        .long("from")
+                        .takes_value(true)
+                        .help("Export tickers since time, in date, RFC3339 or millis"),
+                    Arg::new("to")
+                        .long("to")
+                        .takes_value(true)
+                        .help("Export tickers before time, end date included"),
                     Arg::new("collections")
                         .takes_value(true)
                         .multiple_values(true)
                         .required(true)
-                        .help("Collection to be export"),
+                        .help("Collection to be export, or sqlite:[symbols@]from[..to]"),
                 ]),
             Command::new("index")
                 .about("Index message to Elasticsearch")
                 .args(&[
                     level.clone(),
                     config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
                     Arg::new("truncat")
                         .short('k')
                         .long("truncat")
@@ -388,11 +667,60 @@ fn command_args<'help>() -> Command<'help> {
                         .required(true)
                         .help("Source files to be indexed"),
                 ]),
+            Command::new("convert")
+                .about("Convert recorded tickers between JSON lines and length-delimited protobuf")
+                .args(&[
+                    level.clone(),
+                    config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                    Arg::new("format")
+                        .long("format")
+                        .possible_values(["protobuf", "json"])
+                        .default_value("protobuf")
+                        .ignore_case(true)
+                        .help("Target format, files are written under replay.outputs.baseFolder"),
+                    Arg::new("compress")
+                        .long("compress")
+                        .possible_values(["none", "gz", "zst"])
+                        .default_value("none")
+                        .ignore_case(true)
+                        .help("Compression of target files"),
+                    Arg::new("files")
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .required(true)
+                        .help("Source files, protobuf by .pb extension, optionally .gz or .zst"),
+                ]),
+            Command::new("es-setup")
+                .about(
+                    "Install Elasticsearch index templates and lifecycle policy of daily indices",
+                )
+                .args(&[
+                    level.clone(),
+                    config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                    Arg::new("lifecycle-days")
+                        .long("lifecycle-days")
+                        .takes_value(true)
+                        .help("Delete daily indices after days, no lifecycle policy when absent"),
+                ]),
+            Command::new("validate-config")
+                .about("Validate config file, units referenced by rules, symbols and rates")
+                .args(&[
+                    level.clone(),
+                    config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
+                ]),
             Command::new("annotate")
                 .about("Remove annotations from grafana")
                 .args(&[
                     level.clone(),
                     config_file.clone(),
+                    profile.clone(),
+                    overrides.clone(),
                     Arg::new("start")
                         .short('s')
                         .long("start")
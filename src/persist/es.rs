@@ -1,40 +1,48 @@
-use super::{DataSource, PersistenceContext};
+use super::{
+    reader::{JsonLines, TickerFile},
+    sink::TickerSink,
+    DataSource, PersistenceContext,
+};
 use crate::{
     proto::biz::TickerEvent,
     vo::{
-        biz::{MarketHoursType, Protfolio, QuoteType, Ticker, TradeInfo},
+        biz::{
+            AuditState, EquityPoint, MarketHoursType, OrderEvent, OrderStatus, Protfolio,
+            QuoteType, Ticker, TradeInfo,
+        },
         core::AppContext,
     },
     Result,
 };
-use chrono::{DateTime, TimeZone, Utc};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use elasticsearch::{
     http::{
         request::JsonBody,
         transport::{SingleNodeConnectionPool, TransportBuilder},
         Url,
     },
-    indices::IndicesDeleteParts,
+    ilm::IlmPutLifecycleParts,
+    indices::{IndicesDeleteParts, IndicesPutIndexTemplateParts},
     BulkParts, Elasticsearch, IndexParts,
 };
 use futures::executor::block_on;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::{
-    collections::HashMap,
-    fmt::Debug,
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
-    sync::Arc,
-};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fmt::Debug, path::Path, sync::Arc};
 
 const DATE_FORMAT: &str = "%Y-%m-%d";
 const INDEX_PREFIX_TICKER: &str = "sminer-ticker";
 const INDEX_PREFIX_PROTFOLIO: &str = "sminer-protfolio";
 const INDEX_PREFIX_SLOPE: &str = "sminer-slope";
 const INDEX_PREFIX_TRADE: &str = "sminer-trade";
+const INDEX_PREFIX_EQUITY: &str = "sminer-equity";
+const INDEX_PREFIX_ORDER: &str = "sminer-order";
+// Documents of one bulk request when indexing files
+const BULK_SIZE: usize = 10_000;
+// Lifecycle policy of daily indices, installed by es-setup
+pub const LIFECYCLE_POLICY: &str = "sminer-retention";
 
 async fn get_elasticsearch_client(uri: &str) -> Result<Elasticsearch> {
     let url = Url::parse(uri)?;
@@ -86,6 +94,199 @@ impl PersistenceContext {
     }
 }
 
+impl PersistenceContext {
+    // Delete daily indices after days, attached to index templates
+    pub async fn put_lifecycle_policy(&self, days: u32) -> Result<()> {
+        let client: Elasticsearch = self.get_connection()?;
+        let response = client
+            .ilm()
+            .put_lifecycle(IlmPutLifecycleParts::Policy(LIFECYCLE_POLICY))
+            .body(json!({
+                "policy": {
+                    "phases": {
+                        "hot": { "actions": {} },
+                        "delete": { "min_age": format!("{}d", days), "actions": { "delete": {} } }
+                    }
+                }
+            }))
+            .send()
+            .await?;
+        self.close_connection(client)?;
+        if !response.status_code().is_success() {
+            return Err(format!(
+                "Put lifecycle policy {} failed: {}",
+                LIFECYCLE_POLICY,
+                response.text().await?
+            )
+            .into());
+        }
+        info!(
+            "Lifecycle policy {} installed, delete after {} days",
+            LIFECYCLE_POLICY, days
+        );
+        Ok(())
+    }
+
+    // Install index templates, applied to daily indices created afterwards
+    pub async fn put_index_templates(&self, lifecycle: bool) -> Result<()> {
+        let client: Elasticsearch = self.get_connection()?;
+        for (name, body) in index_templates(lifecycle) {
+            let response = client
+                .indices()
+                .put_index_template(IndicesPutIndexTemplateParts::Name(&name))
+                .body(body)
+                .send()
+                .await?;
+            if !response.status_code().is_success() {
+                let text = response.text().await?;
+                self.close_connection(client)?;
+                return Err(format!("Put index template {} failed: {}", name, text).into());
+            }
+            info!("Index template {} installed", name);
+        }
+        self.close_connection(client)?;
+        Ok(())
+    }
+}
+
+// Time fields are RFC3339 text or epoch millis
+fn date_field() -> Value {
+    json!({ "type": "date", "format": "strict_date_optional_time||epoch_millis" })
+}
+
+fn field(r#type: &str) -> Value {
+    json!({ "type": r#type })
+}
+
+// Explicit mappings of documents by index prefix
+fn index_mappings() -> Vec<(&'static str, Value)> {
+    vec![
+        (
+            INDEX_PREFIX_TICKER,
+            json!({
+                "id": field("keyword"),
+                "unit": field("long"),
+                "price": field("float"),
+                "time": date_field(),
+                "quote_type": field("keyword"),
+                "market_hours": field("keyword"),
+                "day_volume": field("long"),
+                "volume": field("long"),
+                "change": field("float"),
+                "period_type": field("integer"),
+            }),
+        ),
+        (
+            INDEX_PREFIX_PROTFOLIO,
+            json!({
+                "id": field("keyword"),
+                "time": date_field(),
+                "price": field("float"),
+                "kind": field("keyword"),
+                "quote_type": field("keyword"),
+                "market_hours": field("keyword"),
+                "volume": field("long"),
+                "unit": {
+                    "properties": {
+                        "name": field("keyword"),
+                        "duration": field("integer"),
+                        "period": field("integer"),
+                        "bar": field("keyword"),
+                    }
+                },
+                "unit_time": date_field(),
+                "period_type": field("integer"),
+                "max_price": field("float"),
+                "min_price": field("float"),
+                "open_price": field("float"),
+                "close_price": field("float"),
+                "sample_size": field("integer"),
+                "slope": field("double"),
+                "b_num": field("double"),
+            }),
+        ),
+        (
+            INDEX_PREFIX_SLOPE,
+            json!({
+                "id": field("keyword"),
+                "price": field("double"),
+                "time": date_field(),
+                "kind": field("keyword"),
+                "period_type": field("integer"),
+            }),
+        ),
+        (
+            // one document per trade, slopes of units queried as nested states
+            INDEX_PREFIX_TRADE,
+            json!({
+                "id": field("keyword"),
+                "time": date_field(),
+                "timestamp": date_field(),
+                "kind": field("keyword"),
+                "price": field("float"),
+                "states": {
+                    "type": "nested",
+                    "properties": {
+                        "unit": field("keyword"),
+                        "index": field("integer"),
+                        "slope": field("double"),
+                    }
+                },
+            }),
+        ),
+        (
+            INDEX_PREFIX_EQUITY,
+            json!({
+                "pair": field("keyword"),
+                "time": date_field(),
+                "timestamp": date_field(),
+                "equity": field("float"),
+                "config_sha": field("keyword"),
+            }),
+        ),
+        (
+            INDEX_PREFIX_ORDER,
+            json!({
+                "id": field("keyword"),
+                "symbol": field("keyword"),
+                "time": date_field(),
+                "timestamp": date_field(),
+                "status": field("keyword"),
+                "audit": field("keyword"),
+                "price": field("float"),
+                "rival_price": field("float"),
+                "volume": field("integer"),
+                "created_time": date_field(),
+                "constraint_id": field("keyword"),
+                "rule": field("keyword"),
+            }),
+        ),
+    ]
+}
+
+// Composable templates of daily indices, ex: sminer-ticker for sminer-ticker-*
+pub fn index_templates(lifecycle: bool) -> Vec<(String, Value)> {
+    index_mappings()
+        .into_iter()
+        .map(|(prefix, properties)| {
+            let mut settings = json!({});
+            if lifecycle {
+                settings["index.lifecycle.name"] = json!(LIFECYCLE_POLICY);
+            }
+            let body = json!({
+                "index_patterns": [format!("{}-*", prefix)],
+                "priority": 100,
+                "template": {
+                    "settings": settings,
+                    "mappings": { "properties": properties }
+                },
+                "_meta": { "source": "sminer es-setup" }
+            });
+            (prefix.to_string(), body)
+        })
+        .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ElasticTicker {
     // Symbol name
@@ -111,7 +312,7 @@ impl From<Ticker> for ElasticTicker {
             id: t.id.clone(),
             unit: 0,
             price: t.price,
-            time: Utc.timestamp_millis(t.time).to_rfc3339(),
+            time: Utc.timestamp_millis_opt(t.time).unwrap().to_rfc3339(),
             quote_type: t.quote_type,
             market_hours: t.market_hours,
             day_volume: t.day_volume,
@@ -128,7 +329,7 @@ impl From<TickerEvent> for ElasticTicker {
             id: t.id.clone(),
             unit: 0,
             price: t.price,
-            time: Utc.timestamp_millis(t.time).to_rfc3339(),
+            time: Utc.timestamp_millis_opt(t.time).unwrap().to_rfc3339(),
             quote_type: t.quote_type.try_into().unwrap(),
             market_hours: t.market_hours.try_into().unwrap(),
             day_volume: t.day_volume,
@@ -192,6 +393,28 @@ impl ElasticTicker {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ElasticEquity {
+    pub pair: String,
+    pub time: String,
+    pub timestamp: i64,
+    pub equity: f32,
+    pub config_sha: String,
+}
+
+impl ElasticEquity {
+    pub fn from(point: &EquityPoint, config_sha: &str) -> Self {
+        Self {
+            pair: point.pair.clone(),
+            time: Utc.timestamp_millis_opt(point.time).unwrap().to_rfc3339(),
+            timestamp: point.time,
+            equity: point.equity,
+            config_sha: config_sha.to_string(),
+        }
+    }
+}
+
+// Trade states flattened as one row per unit slope, for columnar export
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ElasticTrade {
     pub id: String,
@@ -211,7 +434,7 @@ impl ElasticTrade {
             .flat_map(|(unit, slopes)| {
                 slopes.iter().enumerate().map(|(index, slope)| Self {
                     id: trade.id.clone(),
-                    time: Utc.timestamp_millis(trade.time).to_rfc3339(),
+                    time: Utc.timestamp_millis_opt(trade.time).unwrap().to_rfc3339(),
                     timestamp: trade.time,
                     kind: trade.kind,
                     unit: format!("{}{:03}", &unit.clone(), &index),
@@ -222,6 +445,147 @@ impl ElasticTrade {
     }
 }
 
+// Trade document with slopes of units as nested states
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ElasticTradeStates {
+    pub id: String,
+    pub time: String,
+    pub timestamp: i64,
+    pub kind: char,
+    pub price: f32,
+    pub states: Vec<ElasticState>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ElasticState {
+    pub unit: String,
+    // index of slope in unit period
+    pub index: usize,
+    pub slope: f64,
+}
+
+impl ElasticTradeStates {
+    pub fn from(trade: &TradeInfo) -> Self {
+        Self {
+            id: trade.id.clone(),
+            time: Utc.timestamp_millis_opt(trade.time).unwrap().to_rfc3339(),
+            timestamp: trade.time,
+            kind: trade.kind,
+            price: trade.price,
+            states: trade
+                .states
+                .iter()
+                .flat_map(|(unit, slopes)| {
+                    slopes
+                        .iter()
+                        .enumerate()
+                        .map(|(index, slope)| ElasticState {
+                            unit: unit.clone(),
+                            index,
+                            slope: *slope,
+                        })
+                })
+                .collect(),
+        }
+    }
+}
+
+// Order lifecycle event, orders of a pair share the constraint ID
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ElasticOrder {
+    pub id: String,
+    pub symbol: String,
+    pub time: String,
+    pub timestamp: i64,
+    pub status: OrderStatus,
+    pub audit: AuditState,
+    pub price: f32,
+    pub rival_price: f32,
+    pub volume: u32,
+    pub created_time: i64,
+    pub constraint_id: Option<String>,
+    pub rule: Option<String>,
+}
+
+impl From<&OrderEvent> for ElasticOrder {
+    fn from(event: &OrderEvent) -> Self {
+        let order = &event.order;
+        Self {
+            id: order.id.clone(),
+            symbol: order.symbol.clone(),
+            time: Utc.timestamp_millis_opt(event.time).unwrap().to_rfc3339(),
+            timestamp: event.time,
+            status: event.status.clone(),
+            audit: order.audit.clone(),
+            price: order.created_price,
+            rival_price: order.created_rival_price,
+            volume: order.created_volume,
+            created_time: order.created_time,
+            constraint_id: order.constraint_id.clone(),
+            rule: order.decision.as_ref().and_then(|d| d.rule_name()),
+        }
+    }
+}
+
+impl ElasticOrder {
+    pub async fn save_to_elasticsearch(&self, datasource: Arc<PersistenceContext>) -> Result<()> {
+        let client: Elasticsearch = datasource.get_connection()?;
+        let index_name = order_index_name(&Utc.timestamp_millis_opt(self.timestamp).unwrap());
+        let response = client
+            .index(IndexParts::Index(&index_name))
+            .body(json!(self))
+            .send()
+            .await?;
+
+        let successful = response.status_code().is_success();
+        datasource.close_connection(client)?;
+        if !successful {
+            warn!("result = {:?}, {:?}", response, self);
+        }
+        Ok(())
+    }
+}
+
+// Tickers and orders in indices by day, sminer-ticker-2022-03-09 and sminer-order-2022-03-09
+#[derive(Debug)]
+pub struct ElasticSink {
+    context: Arc<PersistenceContext>,
+}
+
+impl ElasticSink {
+    pub fn new(context: Arc<PersistenceContext>) -> Self {
+        Self { context }
+    }
+}
+
+#[async_trait]
+impl TickerSink for ElasticSink {
+    fn name(&self) -> &str {
+        "elasticsearch"
+    }
+
+    async fn save_tickers(&self, tickers: &[Ticker]) -> Result<()> {
+        let mut indices: HashMap<String, Vec<ElasticTicker>> = HashMap::new();
+        for ticker in tickers {
+            let item = ElasticTicker::from(ticker.clone());
+            indices
+                .entry(ticker_index_name(&item.timestamp()))
+                .or_default()
+                .push(item);
+        }
+        for items in indices.values() {
+            ElasticTicker::batch_save_to_elasticsearch(Arc::clone(&self.context), items).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_order(&self, event: &OrderEvent) -> Result<()> {
+        ElasticOrder::from(event)
+            .save_to_elasticsearch(Arc::clone(&self.context))
+            .await
+    }
+}
+
 fn take_digitals(str: &str) -> String {
     let filename = Path::new(str).file_name().unwrap().to_str().unwrap();
     filename
@@ -232,8 +596,9 @@ fn take_digitals(str: &str) -> String {
 
 pub fn take_index_time(name: &str) -> DateTime<Utc> {
     let digital = take_digitals(name);
-    Utc.datetime_from_str(&format!("{} 00:00:00", digital), "%Y%m%d %H:%M:%S")
-        .unwrap()
+    let time =
+        NaiveDateTime::parse_from_str(&format!("{} 00:00:00", digital), "%Y%m%d %H:%M:%S").unwrap();
+    Utc.from_utc_datetime(&time)
 }
 
 pub fn slope_index_name(time: &DateTime<Utc>) -> String {
@@ -252,38 +617,48 @@ pub fn trade_index_name(time: &DateTime<Utc>) -> String {
     format!("{}-{}", INDEX_PREFIX_TRADE, time.format(DATE_FORMAT))
 }
 
+pub fn equity_index_name(time: &DateTime<Utc>) -> String {
+    format!("{}-{}", INDEX_PREFIX_EQUITY, time.format(DATE_FORMAT))
+}
+
+pub fn order_index_name(time: &DateTime<Utc>) -> String {
+    format!("{}-{}", INDEX_PREFIX_ORDER, time.format(DATE_FORMAT))
+}
+
 pub async fn index_tickers_from_file(context: &AppContext, path: &str) -> Result<()> {
     info!("Import messages from {}", &path);
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let mut tickers: Vec<ElasticTicker> = reader
-        .lines()
-        .into_iter()
-        .map(|w| w.unwrap())
-        .map(|line| serde_json::from_str::<Ticker>(&line).unwrap())
-        .map(|t| ElasticTicker::from(t))
-        .collect();
+    // generate index name
+    let digital = take_digitals(&path);
+    let time = NaiveDateTime::parse_from_str(&format!("{} 00:00:00", digital), "%Y%m%d %H:%M:%S")?;
+    let time = Utc.from_utc_datetime(&time);
+    let index_name = ticker_index_name(&time);
 
     let mut previous_volume: HashMap<String, i64> = HashMap::new();
-    // calculate volume
-    for ticker in tickers.iter_mut() {
+    let mut tickers: Vec<ElasticTicker> = Vec::with_capacity(BULK_SIZE);
+    let mut count = 0;
+    for ticker in TickerFile::open(path)? {
+        let mut ticker = ElasticTicker::from(ticker?);
+        // calculate volume
         let prev = *previous_volume.get(&ticker.id).unwrap_or(&0);
         if prev < ticker.day_volume {
             ticker.volume = ticker.day_volume - prev;
             previous_volume.insert(ticker.id.to_string(), ticker.day_volume);
         }
-    }
+        tickers.push(ticker);
 
-    info!("ticker size: {} for {}", &tickers.len(), &path);
-
-    // generate index name
-    let digital = take_digitals(&path);
-    let time = Utc.datetime_from_str(&format!("{} 00:00:00", digital), "%Y%m%d %H:%M:%S")?;
-    let index_name = ticker_index_name(&time);
+        if tickers.len() >= BULK_SIZE {
+            count += tickers.len();
+            bulk_index(&context, &index_name, &tickers).await?;
+            tickers.clear();
+        }
+    }
+    if !tickers.is_empty() {
+        count += tickers.len();
+        bulk_index(&context, &index_name, &tickers).await?;
+    }
 
-    bulk_index(&context, &index_name, &tickers).await?;
+    info!("ticker size: {} for {}", count, &path);
 
     Ok(())
 }
@@ -291,23 +666,30 @@ pub async fn index_tickers_from_file(context: &AppContext, path: &str) -> Result
 pub async fn index_protfolios_from_file(context: &AppContext, path: &str) -> Result<()> {
     info!("Import messages from {}", &path);
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let protfolios: Vec<Protfolio> = reader
-        .lines()
-        .into_iter()
-        .map(|w| w.unwrap())
-        .map(|line| serde_json::from_str::<Protfolio>(&line).unwrap())
-        .collect();
-
-    info!("Protfolio size: {} for {}", &protfolios.len(), &path);
+    let mut index_name: Option<String> = None;
+    let mut protfolios: Vec<Protfolio> = Vec::with_capacity(BULK_SIZE);
+    let mut count = 0;
+    for protfolio in JsonLines::<Protfolio>::open(path)? {
+        let protfolio = protfolio?;
+        // generate index name by the first protfolio
+        if index_name.is_none() {
+            let time = Utc.timestamp_millis_opt(protfolio.time).unwrap();
+            index_name = Some(protfolio_index_name(&time));
+        }
+        protfolios.push(protfolio);
 
-    // generate index name
-    let time = Utc.timestamp_millis(protfolios.first().unwrap().time);
-    let index_name = protfolio_index_name(&time);
+        if protfolios.len() >= BULK_SIZE {
+            count += protfolios.len();
+            bulk_index(&context, index_name.as_ref().unwrap(), &protfolios).await?;
+            protfolios.clear();
+        }
+    }
+    if !protfolios.is_empty() {
+        count += protfolios.len();
+        bulk_index(&context, index_name.as_ref().unwrap(), &protfolios).await?;
+    }
 
-    bulk_index(&context, &index_name, &protfolios).await?;
+    info!("Protfolio size: {} for {}", count, &path);
 
     Ok(())
 }
@@ -1,10 +1,22 @@
 pub mod es;
 pub mod grafana;
 pub mod mongo;
+pub mod parquet;
+pub mod reader;
+pub mod sink;
+pub mod snapshot;
+pub mod sqlite;
 
-use self::mongo::get_mongo_client;
+use self::{
+    es::ElasticSink,
+    mongo::{get_mongo_client, MongoSink},
+    sink::{TickerSink, TickerSource},
+    sqlite::SqliteStore,
+};
 use crate::{vo::core::AppConfig, Result};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 use elasticsearch::Elasticsearch;
+use log::error;
 use mongodb::Client;
 use std::{
     fmt::Debug,
@@ -15,7 +27,10 @@ use std::{
 pub struct PersistenceContext {
     pub config: Arc<AppConfig>,
     elastic_connections: Arc<Mutex<Vec<Elasticsearch>>>,
-    mongo_connections: Arc<Mutex<Vec<Client>>>,
+    // client keeps its own connection pool, shared by clones
+    mongo_client: Arc<Mutex<Option<Client>>>,
+    // opened on first use
+    sqlite: Arc<Mutex<Option<Arc<SqliteStore>>>>,
 }
 
 impl PersistenceContext {
@@ -23,7 +38,8 @@ impl PersistenceContext {
         Self {
             config,
             elastic_connections: Arc::new(Mutex::new(Vec::new())),
-            mongo_connections: Arc::new(Mutex::new(Vec::new())),
+            mongo_client: Arc::new(Mutex::new(None)),
+            sqlite: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -32,17 +48,90 @@ impl PersistenceContext {
     }
 
     pub async fn init_mongo(&self) -> Result<()> {
-        // TODO: temp sollution
-        if self.config().data_source.mongodb.enabled {
-            for _ in 1..30 {
-                let conn = get_mongo_client(&self.config.data_source.mongodb.uri).await?;
-                self.close_connection(conn)?;
-            }
+        if self.config().data_source.mongodb.enabled && self.mongo_client.lock().unwrap().is_none()
+        {
+            let client = get_mongo_client(&self.config.data_source.mongodb.uri).await?;
+            *self.mongo_client.lock().unwrap() = Some(client);
         }
         Ok(())
     }
 }
 
+impl PersistenceContext {
+    pub fn sqlite(&self) -> Result<Arc<SqliteStore>> {
+        let mut guard = self.sqlite.lock().unwrap();
+        if let Some(store) = guard.as_ref() {
+            return Ok(Arc::clone(store));
+        }
+        let store = Arc::new(SqliteStore::open(&self.config.data_source.sqlite.uri)?);
+        *guard = Some(Arc::clone(&store));
+        Ok(store)
+    }
+}
+
+// Sinks of enabled data sources, tickers are persisted by async process only
+pub fn create_sinks(persistence: &Arc<PersistenceContext>) -> Vec<Arc<dyn TickerSink>> {
+    let config = persistence.config();
+    let mut sinks: Vec<Arc<dyn TickerSink>> = Vec::new();
+    if config.sync_mongo_enabled() {
+        sinks.push(Arc::new(MongoSink::new(Arc::clone(persistence))));
+    }
+    if config.sync_elasticsearch_enabled() {
+        sinks.push(Arc::new(ElasticSink::new(Arc::clone(persistence))));
+    }
+    if config.data_source.sqlite.enabled && config.async_process() {
+        match persistence.sqlite() {
+            Ok(store) => sinks.push(store),
+            Err(err) => error!("Open SQLite sink error: {:?}", err),
+        }
+    }
+    sinks
+}
+
+// Source of stored tickers, for regular market start time and backfill
+pub fn create_source(persistence: &Arc<PersistenceContext>) -> Option<Arc<dyn TickerSource>> {
+    let config = persistence.config();
+    if config.data_source.mongodb.enabled {
+        Some(Arc::new(MongoSink::new(Arc::clone(persistence))))
+    } else if config.data_source.sqlite.enabled {
+        match persistence.sqlite() {
+            Ok(store) => Some(store),
+            Err(err) => {
+                error!("Open SQLite source error: {:?}", err);
+                None
+            }
+        }
+    } else {
+        None
+    }
+}
+
+// Start of UTC day of time
+pub fn day_start(time: i64) -> i64 {
+    let day = Duration::days(1).num_milliseconds();
+    time - time.rem_euclid(day)
+}
+
+// Start of time, or the next day of date when it's an end
+pub fn parse_time(text: &str, end: bool) -> Result<i64> {
+    let text = text.trim();
+    for format in ["%Y%m%d", "%Y-%m-%d"] {
+        if let Ok(date) = NaiveDate::parse_from_str(text, format) {
+            let date = if end { date + Duration::days(1) } else { date };
+            return Ok(Utc
+                .from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                .timestamp_millis());
+        }
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.timestamp_millis());
+    }
+    if let Ok(timestamp) = text.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    Err(format!("Invalid time: {}", text).into())
+}
+
 pub trait DataSource<T> {
     fn get_connection(&self) -> Result<T>;
     fn close_connection(&self, conn: T) -> Result<()>;
@@ -1,11 +1,17 @@
-use super::{DataSource, PersistenceContext};
+use super::{
+    day_start, parse_time,
+    reader::{source_name, TickerFile},
+    sink::{TickerSink, TickerSource},
+    DataSource, PersistenceContext,
+};
 use crate::{
     vo::{
-        biz::Ticker,
-        core::{AppConfig, AppContext},
+        biz::{OrderEvent, Ticker},
+        core::AppContext,
     },
     Result,
 };
+use async_trait::async_trait;
 use chrono::{Duration, TimeZone, Utc};
 use futures::TryStreamExt;
 use log::{debug, info, trace};
@@ -15,13 +21,15 @@ use mongodb::{
     Client, Cursor,
 };
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, BufWriter, Write},
-    path::Path,
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::{BufWriter, Write},
     sync::Arc,
-    thread,
 };
 
+// Tickers of one insert_many in import
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
 pub async fn get_mongo_client(uri: &str) -> Result<Client> {
     let client_options = ClientOptions::parse(uri).await?;
     let client = Client::with_options(client_options)?;
@@ -30,28 +38,14 @@ pub async fn get_mongo_client(uri: &str) -> Result<Client> {
 
 impl DataSource<Client> for PersistenceContext {
     fn get_connection(&self) -> Result<Client> {
-        let mutex = Arc::clone(&self.mongo_connections);
-        let mut pool = mutex.lock().unwrap();
-        if pool.is_empty() {
-            // TODO: send command for creating connection
-            for _ in 0..10 {
-                if pool.is_empty() {
-                    info!("sleep for 1s");
-                    thread::sleep(std::time::Duration::from_secs(1));
-                }
-            }
-            let client = pool.pop().unwrap();
-            Ok(client)
-        } else {
-            let client = pool.pop().unwrap();
-            Ok(client)
+        match self.mongo_client.lock().unwrap().as_ref() {
+            Some(client) => Ok(client.clone()),
+            None => Err("MongoDB client not initialized".into()),
         }
     }
 
-    fn close_connection(&self, client: Client) -> Result<()> {
-        let mutex = Arc::clone(&self.mongo_connections);
-        let mut pool = mutex.lock().unwrap();
-        pool.push(client);
+    fn close_connection(&self, _client: Client) -> Result<()> {
+        // returned to the shared pool when dropped
         Ok(())
     }
 }
@@ -74,7 +68,9 @@ impl Ticker {
     pub async fn save_to_mongo(&self, context: Arc<PersistenceContext>) -> Result<()> {
         let collection_name = format!(
             "tickers{}",
-            Utc.timestamp_millis(self.time).format("%Y%m%d")
+            Utc.timestamp_millis_opt(self.time)
+                .unwrap()
+                .format("%Y%m%d")
         );
         let config = context.config();
         let db_name = config.data_source.mongodb.target.as_ref().unwrap();
@@ -88,15 +84,100 @@ impl Ticker {
     }
 }
 
-pub async fn get_start_time(context: Arc<PersistenceContext>, config: Arc<AppConfig>) -> i64 {
-    let db_name = config.data_source.mongodb.target.as_ref().unwrap();
-    let now = Utc::now().timestamp_millis();
-    let client = context.get_connection().unwrap();
-    let start_time = get_reglar_market_start_time(&client, db_name, now)
-        .await
-        .unwrap();
-    context.close_connection(client).unwrap();
-    start_time
+impl OrderEvent {
+    pub async fn save_to_mongo(&self, context: Arc<PersistenceContext>) -> Result<()> {
+        let collection_name = format!(
+            "orders{}",
+            Utc.timestamp_millis_opt(self.time)
+                .unwrap()
+                .format("%Y%m%d")
+        );
+        let config = context.config();
+        let db_name = config.data_source.mongodb.target.as_ref().unwrap();
+        let client: Client = context.get_connection()?;
+        let db = client.database(db_name);
+        let collection = db.collection::<Self>(&collection_name);
+
+        let _ = collection.insert_one(self, None).await?;
+        context.close_connection(client)?;
+        Ok(())
+    }
+}
+
+// Tickers and orders in collections by day, tickers20220309 and orders20220309
+#[derive(Debug)]
+pub struct MongoSink {
+    context: Arc<PersistenceContext>,
+}
+
+impl MongoSink {
+    pub fn new(context: Arc<PersistenceContext>) -> Self {
+        Self { context }
+    }
+
+    fn db_name(&self) -> Result<String> {
+        match &self.context.config().data_source.mongodb.target {
+            Some(name) => Ok(name.clone()),
+            None => Err("No MongoDB target database configured".into()),
+        }
+    }
+}
+
+#[async_trait]
+impl TickerSink for MongoSink {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn save_tickers(&self, tickers: &[Ticker]) -> Result<()> {
+        let mut collections: BTreeMap<String, Vec<&Ticker>> = BTreeMap::new();
+        for ticker in tickers {
+            let name = format!(
+                "tickers{}",
+                Utc.timestamp_millis_opt(ticker.time)
+                    .unwrap()
+                    .format("%Y%m%d")
+            );
+            collections.entry(name).or_default().push(ticker);
+        }
+
+        let db_name = self.db_name()?;
+        let client: Client = self.context.get_connection()?;
+        let db = client.database(&db_name);
+        for (name, list) in collections {
+            let collection = db.collection::<Ticker>(&name);
+            collection.insert_many(list, None).await?;
+        }
+        self.context.close_connection(client)?;
+        Ok(())
+    }
+
+    async fn save_order(&self, event: &OrderEvent) -> Result<()> {
+        event.save_to_mongo(Arc::clone(&self.context)).await
+    }
+}
+
+#[async_trait]
+impl TickerSource for MongoSink {
+    fn name(&self) -> &str {
+        "mongodb"
+    }
+
+    async fn regular_start_time(&self, time: i64) -> Result<i64> {
+        let db_name = self.db_name()?;
+        let client: Client = self.context.get_connection()?;
+        let start_time = get_reglar_market_start_time(&client, &db_name, time).await;
+        self.context.close_connection(client)?;
+        start_time
+    }
+
+    async fn tickers_after(&self, time: i64, symbols: &[String]) -> Result<Vec<Ticker>> {
+        let db_name = self.db_name()?;
+        let client: Client = self.context.get_connection()?;
+        let tickers = query_tickers_after(&client, &db_name, time, symbols).await;
+        self.context.close_connection(client)?;
+        tickers
+    }
 }
 
 pub async fn get_reglar_market_start_time(
@@ -106,7 +187,10 @@ pub async fn get_reglar_market_start_time(
 ) -> Result<i64> {
     let start_time = time - time % Duration::days(1).num_milliseconds();
     let end_time = start_time + Duration::days(1).num_milliseconds();
-    let collection_name = format!("tickers{}", Utc.timestamp_millis(time).format("%Y%m%d"));
+    let collection_name = format!(
+        "tickers{}",
+        Utc.timestamp_millis_opt(time).unwrap().format("%Y%m%d")
+    );
     let db = client.database(db_name);
     let collection = db.collection::<Ticker>(&collection_name);
     let mut cursor = collection
@@ -131,16 +215,125 @@ pub async fn get_reglar_market_start_time(
     }
 }
 
+// Regular market tickers of symbols after time in the same day, sort by time
+pub async fn query_tickers_after(
+    client: &Client,
+    db_name: &str,
+    time: i64,
+    symbols: &[String],
+) -> Result<Vec<Ticker>> {
+    let collection_name = format!(
+        "tickers{}",
+        Utc.timestamp_millis_opt(time).unwrap().format("%Y%m%d")
+    );
+    let db = client.database(db_name);
+    let collection = db.collection::<Ticker>(&collection_name);
+    let cursor = collection
+        .find(
+            doc! {
+                "id": { "$in": symbols },
+                "market_hours" : "RegularMarket",
+                "time": { "$gt": time }
+            },
+            FindOptions::builder()
+                .sort(doc! { "time" : 1, "day_volume": 1 })
+                .build(),
+        )
+        .await?;
+    let tickers: Vec<Ticker> = cursor.try_collect().await?;
+    Ok(tickers)
+}
+
+// Exported tickers of symbols in [from, to), everything when empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TickerFilter {
+    pub symbols: Vec<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl TickerFilter {
+    // Time in date (20220309, 2022-03-09), RFC3339 or millis, end date included
+    pub fn parse(symbols: &[&str], from: Option<&str>, to: Option<&str>) -> Result<Self> {
+        let filter = Self {
+            symbols: symbols
+                .iter()
+                .flat_map(|s| s.split(','))
+                .map(|s| s.trim().to_uppercase())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            from: from.map(|t| parse_time(t, false)).transpose()?,
+            to: to.map(|t| parse_time(t, true)).transpose()?,
+        };
+        if let (Some(from), Some(to)) = (filter.from, filter.to) {
+            if from >= to {
+                return Err(
+                    format!("Invalid time range {}..{}, end before start", from, to).into(),
+                );
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty() && self.from.is_none() && self.to.is_none()
+    }
+
+    pub fn to_document(&self) -> Document {
+        let mut filter = doc! {};
+        if !self.symbols.is_empty() {
+            filter.insert("id", doc! { "$in": &self.symbols });
+        }
+        let mut time = doc! {};
+        if let Some(from) = self.from {
+            time.insert("$gte", from);
+        }
+        if let Some(to) = self.to {
+            time.insert("$lt", to);
+        }
+        if !time.is_empty() {
+            filter.insert("time", time);
+        }
+        filter
+    }
+
+    // Suffix of exported file in UTC, ex: .TQQQ-SQQQ.1430-1600 in one day,
+    // dated when range is open or across days, ex: .202203090000-202203110000
+    pub fn suffix(&self) -> String {
+        let mut suffix = String::new();
+        if !self.symbols.is_empty() {
+            suffix.push_str(&format!(".{}", self.symbols.join("-")));
+        }
+        if self.from.is_some() || self.to.is_some() {
+            let pattern = match (self.from, self.to) {
+                (Some(from), Some(to)) if day_start(from) == day_start(to - 1) => "%H%M",
+                _ => "%Y%m%d%H%M",
+            };
+            let format = |time: Option<i64>| {
+                time.map_or(String::new(), |t| {
+                    Utc.timestamp_millis_opt(t)
+                        .unwrap()
+                        .format(pattern)
+                        .to_string()
+                })
+            };
+            suffix.push_str(&format!(".{}-{}", format(self.from), format(self.to)));
+        }
+        suffix
+    }
+}
+
 pub async fn query_ticker(
     client: &Client,
     db_name: &str,
     collection: &str,
+    filter: &TickerFilter,
 ) -> Result<Cursor<Ticker>> {
     let db = client.database(db_name);
     let collection = db.collection::<Ticker>(collection);
     let cursor = collection
         .find(
-            doc! {},
+            filter.to_document(),
             FindOptions::builder()
                 .sort(doc! { "time" : 1, "day_volume": 1 })
                 .build(),
@@ -149,20 +342,17 @@ pub async fn query_ticker(
     Ok(cursor)
 }
 
-pub async fn import(context: &AppContext, path: &str) -> Result<()> {
+// Import json lines file into collection, named as file when not given
+// Lines are streamed and inserted in batches, memory stays flat for large files
+pub async fn import(
+    context: &AppContext,
+    path: &str,
+    collection: Option<&str>,
+    truncat: bool,
+) -> Result<()> {
     info!("Import messages from {}", &path);
 
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-
-    let tickers: Vec<Ticker> = reader
-        .lines()
-        .into_iter()
-        .map(|w| w.unwrap())
-        .map(|line| serde_json::from_str::<Ticker>(&line).unwrap())
-        .collect();
-
-    info!("Loaded tickers: {} for {}", tickers.len(), path);
+    let mut tickers = TickerFile::open(path)?;
 
     let persistence = context.persistence();
     let config = context.config();
@@ -170,37 +360,58 @@ pub async fn import(context: &AppContext, path: &str) -> Result<()> {
     let client: Client = persistence.get_connection()?;
     let db = client.database(db_name);
 
-    let collection_name = Path::new(&path).file_name().unwrap().to_str().unwrap();
+    let collection_name = match collection {
+        Some(name) => name,
+        None => source_name(path),
+    };
 
     // delete original
-    if context.config().truncat_enabled() {
+    if truncat {
         info!(
             "Drop collection {}.{} for clean data",
             &db_name, &collection_name
         );
-        let collection = db.collection::<Document>(&collection_name);
+        let collection = db.collection::<Document>(collection_name);
         collection.drop(None).await?;
     } else {
         debug!("Ignore drop collection: {}.{}", &db_name, &collection_name);
     }
 
     info!("Importing data into {}.{}", &db_name, &collection_name);
-    let typed_collection = db.collection::<Ticker>(&collection_name);
-    typed_collection.insert_many(tickers, None).await?;
-    info!("Import {} done", &path);
+    let typed_collection = db.collection::<Ticker>(collection_name);
+    let mut batch: Vec<Ticker> = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut count = 0;
+    for ticker in tickers.by_ref() {
+        batch.push(ticker?);
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            count += batch.len();
+            typed_collection.insert_many(batch.drain(..), None).await?;
+            debug!("Imported tickers: {} for {}", count, path);
+        }
+    }
+    if !batch.is_empty() {
+        count += batch.len();
+        typed_collection.insert_many(batch, None).await?;
+    }
+    info!(
+        "Import {} done, tickers: {}, skipped lines: {}",
+        &path,
+        count,
+        tickers.skipped()
+    );
 
     persistence.close_connection(client)?;
 
     Ok(())
 }
 
-pub async fn export(context: &AppContext, name: &str) -> Result<()> {
+pub async fn export(context: &AppContext, name: &str, filter: &TickerFilter) -> Result<()> {
     let persistence = context.persistence();
     let config = context.config();
     let db_name = config.data_source.mongodb.target.as_ref().unwrap();
     let base_path = config.replay.outputs.base_folder.as_str();
     let client: Client = persistence.get_connection()?;
-    let path = format!("{}/{}", &base_path, &name);
+    let path = format!("{}/{}{}", &base_path, &name, filter.suffix());
 
     std::fs::create_dir_all(&base_path)?;
 
@@ -211,15 +422,17 @@ pub async fn export(context: &AppContext, name: &str) -> Result<()> {
         .open(&path)?;
     let mut writer = BufWriter::new(file);
 
-    info!("Export collection: {}", &name);
-    let mut cursor = query_ticker(&client, &db_name, &name).await?;
+    info!("Export collection: {}, filter: {:?}", &name, filter);
+    let mut cursor = query_ticker(&client, &db_name, &name, filter).await?;
+    let mut count = 0;
     while let Some(ticker) = cursor.try_next().await? {
         trace!("{:?}", ticker);
         // write file
         let json = serde_json::to_string(&ticker)?;
         write!(&mut writer, "{}\n", &json)?;
+        count += 1;
     }
-    info!("File {} exported", &path);
+    info!("File {} exported, tickers: {}", &path, count);
     persistence.close_connection(client)?;
 
     Ok(())
@@ -0,0 +1,261 @@
+use super::{
+    es::ElasticTrade,
+    mongo::{query_ticker, TickerFilter},
+    sqlite::TickerQuery,
+    DataSource,
+};
+use crate::{
+    vo::{
+        biz::{Order, Protfolio, Ticker},
+        core::AppContext,
+    },
+    Result,
+};
+use ::parquet::{
+    basic::Compression,
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use futures::TryStreamExt;
+use log::info;
+use mongodb::Client;
+use std::{fs::File, path::Path, sync::Arc};
+
+// Rows of one row group, keep memory of column buffers bounded
+const ROW_GROUP_SIZE: usize = 100_000;
+
+// Values of a column, None is written as null
+enum Values {
+    Long(Vec<Option<i64>>),
+    Double(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+}
+
+impl Values {
+    fn physical_type(&self) -> &str {
+        match self {
+            Values::Long(_) => "INT64",
+            Values::Double(_) => "DOUBLE",
+            Values::Text(_) => "BYTE_ARRAY",
+        }
+    }
+
+    // Definition levels of optional column, 0 for null
+    fn levels<T>(values: &[Option<T>]) -> Vec<i16> {
+        values.iter().map(|v| v.is_some() as i16).collect()
+    }
+}
+
+// Flat columns of rows, named as json fields
+#[derive(Default)]
+pub struct Columns {
+    fields: Vec<(&'static str, Values)>,
+}
+
+impl Columns {
+    pub fn long<T>(
+        mut self,
+        name: &'static str,
+        rows: &[T],
+        f: impl Fn(&T) -> Option<i64>,
+    ) -> Self {
+        self.fields
+            .push((name, Values::Long(rows.iter().map(f).collect())));
+        self
+    }
+
+    pub fn double<T>(
+        mut self,
+        name: &'static str,
+        rows: &[T],
+        f: impl Fn(&T) -> Option<f64>,
+    ) -> Self {
+        self.fields
+            .push((name, Values::Double(rows.iter().map(f).collect())));
+        self
+    }
+
+    pub fn text<T>(
+        mut self,
+        name: &'static str,
+        rows: &[T],
+        f: impl Fn(&T) -> Option<String>,
+    ) -> Self {
+        self.fields
+            .push((name, Values::Text(rows.iter().map(f).collect())));
+        self
+    }
+
+    fn message_type(&self) -> String {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(name, values)| match values {
+                Values::Text(_) => format!("OPTIONAL BYTE_ARRAY {} (UTF8);", name),
+                _ => format!("OPTIONAL {} {};", values.physical_type(), name),
+            })
+            .collect();
+        format!("message schema {{ {} }}", fields.join(" "))
+    }
+}
+
+// Record written as a parquet row
+pub trait ParquetRecord: Sized {
+    fn columns(rows: &[Self]) -> Columns;
+}
+
+impl ParquetRecord for Ticker {
+    fn columns(rows: &[Self]) -> Columns {
+        Columns::default()
+            .text("id", rows, |t| Some(t.id.clone()))
+            .double("price", rows, |t| Some(t.price as f64))
+            .long("time", rows, |t| Some(t.time))
+            .text("quote_type", rows, |t| Some(format!("{:?}", t.quote_type)))
+            .text("market_hours", rows, |t| {
+                Some(format!("{:?}", t.market_hours))
+            })
+            .long("day_volume", rows, |t| Some(t.day_volume))
+            .double("change", rows, |t| Some(t.change as f64))
+            .long("time_diff", rows, |t| Some(t.time_diff))
+    }
+}
+
+impl ParquetRecord for Protfolio {
+    fn columns(rows: &[Self]) -> Columns {
+        Columns::default()
+            .text("id", rows, |p| Some(p.id.clone()))
+            .long("time", rows, |p| Some(p.time))
+            .double("price", rows, |p| Some(p.price as f64))
+            .text("kind", rows, |p| Some(p.kind.to_string()))
+            .text("quote_type", rows, |p| Some(format!("{:?}", p.quote_type)))
+            .text("market_hours", rows, |p| {
+                Some(format!("{:?}", p.market_hours))
+            })
+            .long("volume", rows, |p| Some(p.volume))
+            .text("unit", rows, |p| Some(p.unit.name.clone()))
+            .long("unit_time", rows, |p| Some(p.unit_time))
+            .long("period_type", rows, |p| Some(p.period_type as i64))
+            .double("max_price", rows, |p| Some(p.max_price as f64))
+            .double("min_price", rows, |p| Some(p.min_price as f64))
+            .double("open_price", rows, |p| Some(p.open_price as f64))
+            .double("close_price", rows, |p| Some(p.close_price as f64))
+            .long("sample_size", rows, |p| Some(p.sample_size as i64))
+            .double("slope", rows, |p| p.slope)
+            .double("b_num", rows, |p| p.b_num)
+    }
+}
+
+impl ParquetRecord for ElasticTrade {
+    fn columns(rows: &[Self]) -> Columns {
+        Columns::default()
+            .text("id", rows, |t| Some(t.id.clone()))
+            .long("timestamp", rows, |t| Some(t.timestamp))
+            .text("kind", rows, |t| Some(t.kind.to_string()))
+            .text("unit", rows, |t| Some(t.unit.clone()))
+            .double("slope", rows, |t| Some(t.slope))
+    }
+}
+
+impl ParquetRecord for Order {
+    fn columns(rows: &[Self]) -> Columns {
+        Columns::default()
+            .text("id", rows, |o| Some(o.id.clone()))
+            .text("symbol", rows, |o| Some(o.symbol.clone()))
+            .long("created_time", rows, |o| Some(o.created_time))
+            .double("created_price", rows, |o| Some(o.created_price as f64))
+            .double("created_rival_price", rows, |o| {
+                Some(o.created_rival_price as f64)
+            })
+            .long("created_volume", rows, |o| Some(o.created_volume as i64))
+            .long("accepted_time", rows, |o| o.accepted_time)
+            .double("accepted_price", rows, |o| {
+                o.accepted_price.map(|p| p as f64)
+            })
+            .long("accepted_volume", rows, |o| {
+                o.accepted_volume.map(|v| v as i64)
+            })
+            .long("write_off_time", rows, |o| o.write_off_time)
+            .text("status", rows, |o| Some(format!("{:?}", o.status)))
+            .text("audit", rows, |o| Some(format!("{:?}", o.audit)))
+            .text("constraint_id", rows, |o| o.constraint_id.clone())
+            .text("rule", rows, |o| {
+                o.decision.as_ref().and_then(|d| d.rule_name())
+            })
+    }
+}
+
+// Write rows as snappy compressed parquet file, parent folders are created
+pub fn write_parquet<T: ParquetRecord>(path: &str, rows: &[T]) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let schema = Arc::new(parse_message_type(&T::columns(&[]).message_type())?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+
+    for chunk in rows.chunks(ROW_GROUP_SIZE) {
+        let columns = T::columns(chunk);
+        let mut row_group = writer.next_row_group()?;
+        for (name, values) in columns.fields.iter() {
+            let mut column = row_group
+                .next_column()?
+                .ok_or_else(|| format!("Column {} not found in schema", name))?;
+            match (column.untyped(), values) {
+                (ColumnWriter::Int64ColumnWriter(w), Values::Long(values)) => {
+                    let data: Vec<i64> = values.iter().flatten().copied().collect();
+                    w.write_batch(&data, Some(&Values::levels(values)), None)?;
+                }
+                (ColumnWriter::DoubleColumnWriter(w), Values::Double(values)) => {
+                    let data: Vec<f64> = values.iter().flatten().copied().collect();
+                    w.write_batch(&data, Some(&Values::levels(values)), None)?;
+                }
+                (ColumnWriter::ByteArrayColumnWriter(w), Values::Text(values)) => {
+                    let data: Vec<ByteArray> = values
+                        .iter()
+                        .flatten()
+                        .map(|s| ByteArray::from(s.as_str()))
+                        .collect();
+                    w.write_batch(&data, Some(&Values::levels(values)), None)?;
+                }
+                _ => return Err(format!("Unmatched column type of {}", name).into()),
+            }
+            column.close()?;
+        }
+        row_group.close()?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+// Export filtered tickers of MongoDB collection or sqlite query as parquet file, ex: tmp/tickers20220309.parquet
+pub async fn export(context: &AppContext, name: &str, filter: &TickerFilter) -> Result<()> {
+    let config = context.config();
+    let (name, tickers) = match TickerQuery::parse(name)? {
+        Some(query) => (
+            query.name(),
+            context.persistence().sqlite()?.query_tickers(&query)?,
+        ),
+        None => {
+            let persistence = context.persistence();
+            let db_name = config.data_source.mongodb.target.as_ref().unwrap();
+            let client: Client = persistence.get_connection()?;
+            let tickers: Vec<Ticker> = query_ticker(&client, db_name, name, filter)
+                .await?
+                .try_collect()
+                .await?;
+            persistence.close_connection(client)?;
+            (format!("{}{}", name, filter.suffix()), tickers)
+        }
+    };
+
+    let path = format!("{}/{}.parquet", &config.replay.outputs.base_folder, name);
+    write_parquet(&path, &tickers)?;
+    info!("File {} exported, tickers: {}", &path, tickers.len());
+    Ok(())
+}
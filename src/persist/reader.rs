@@ -0,0 +1,347 @@
+use crate::{
+    proto::biz::TickerEvent,
+    vo::biz::{MarketHoursType, QuoteType, Ticker},
+    Result,
+};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::warn;
+use prost::Message;
+use serde::de::DeserializeOwned;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Lines, Read, Write},
+    marker::PhantomData,
+    path::Path,
+};
+
+const GZIP_EXTENSION: &str = ".gz";
+const ZSTD_EXTENSION: &str = ".zst";
+pub const PROTOBUF_EXTENSION: &str = ".pb";
+// Upper bound of a TickerEvent record, larger length is a corrupted prefix
+const MAX_RECORD_LENGTH: usize = 1024 * 1024;
+
+// Buffered reader of file, decompressed by extension of .gz or .zst
+pub fn open_file(path: &str) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).map_err(|err| format!("Open {} error: {}", path, err))?;
+    let reader: Box<dyn BufRead + Send> = if path.ends_with(GZIP_EXTENSION) {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else if path.ends_with(ZSTD_EXTENSION) {
+        Box::new(BufReader::new(zstd::Decoder::new(file)?))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    Ok(reader)
+}
+
+// Path without compression and protobuf extension, ex: tmp/tickers20220309 of tmp/tickers20220309.pb.gz
+pub fn source_path(path: &str) -> &str {
+    let path = path
+        .strip_suffix(GZIP_EXTENSION)
+        .or_else(|| path.strip_suffix(ZSTD_EXTENSION))
+        .unwrap_or(path);
+    path.strip_suffix(PROTOBUF_EXTENSION).unwrap_or(path)
+}
+
+// File name of source path, ex: tickers20220309 of tmp/tickers20220309.pb.gz
+pub fn source_name(path: &str) -> &str {
+    let path = source_path(path);
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+// Length-delimited protobuf file by extension, ex: tickers20220309.pb, tickers20220309.pb.zst
+pub fn is_protobuf(path: &str) -> bool {
+    path.strip_suffix(GZIP_EXTENSION)
+        .or_else(|| path.strip_suffix(ZSTD_EXTENSION))
+        .unwrap_or(path)
+        .ends_with(PROTOBUF_EXTENSION)
+}
+
+// Items of json lines file, read one line at a time
+// Malformed lines are logged with line number and skipped, broken files stop with error
+pub struct JsonLines<T> {
+    path: String,
+    lines: Lines<Box<dyn BufRead + Send>>,
+    line_number: usize,
+    skipped: usize,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> JsonLines<T> {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            lines: open_file(path)?.lines(),
+            line_number: 0,
+            skipped: 0,
+            item: PhantomData,
+        })
+    }
+
+    // Count of malformed lines so far
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for JsonLines<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?;
+            self.line_number += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    return Some(Err(format!(
+                        "Read {} at line {} error: {}",
+                        &self.path, self.line_number, err
+                    )
+                    .into()))
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<T>(&line) {
+                Ok(item) => return Some(Ok(item)),
+                Err(err) => {
+                    warn!(
+                        "Skip malformed line {} of {}: {}",
+                        self.line_number, &self.path, err
+                    );
+                    self.skipped += 1;
+                }
+            }
+        }
+    }
+}
+
+// Varint length prefix of record, None at the end of file
+fn read_length(reader: &mut dyn BufRead) -> io::Result<Option<usize>> {
+    let mut length: u64 = 0;
+    for index in 0..10 {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            if index == 0 {
+                return Ok(None);
+            }
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated length"));
+        }
+        length |= ((byte[0] & 0x7f) as u64) << (7 * index);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(length as usize));
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "length overflow"))
+}
+
+// Tickers of length-delimited TickerEvent file
+// Undecodable records are logged and skipped, truncated files stop with error
+pub struct ProtobufTickers {
+    path: String,
+    reader: Box<dyn BufRead + Send>,
+    record: usize,
+    skipped: usize,
+    buffer: Vec<u8>,
+}
+
+impl ProtobufTickers {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            reader: open_file(path)?,
+            record: 0,
+            skipped: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    // Count of undecodable records so far
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    fn read_record(&mut self) -> io::Result<bool> {
+        let length = match read_length(&mut self.reader)? {
+            Some(length) => length,
+            None => return Ok(false),
+        };
+        self.record += 1;
+        if length > MAX_RECORD_LENGTH {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "record length {} exceeds limit {}",
+                    length, MAX_RECORD_LENGTH
+                ),
+            ));
+        }
+        self.buffer.resize(length, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        Ok(true)
+    }
+}
+
+impl Iterator for ProtobufTickers {
+    type Item = Result<Ticker>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.read_record() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(err) => {
+                    return Some(Err(format!(
+                        "Read {} at record {} error: {}",
+                        &self.path,
+                        self.record + 1,
+                        err
+                    )
+                    .into()))
+                }
+            }
+            match TickerEvent::decode(self.buffer.as_slice()) {
+                Ok(event)
+                    if QuoteType::try_from(event.quote_type).is_ok()
+                        && MarketHoursType::try_from(event.market_hours).is_ok() =>
+                {
+                    return Some(Ok(Ticker::from(event)))
+                }
+                Ok(event) => warn!(
+                    "Skip record {} of {}, unknown quote type {} or market hours {}",
+                    self.record, &self.path, event.quote_type, event.market_hours
+                ),
+                Err(err) => warn!(
+                    "Skip malformed record {} of {}: {}",
+                    self.record, &self.path, err
+                ),
+            }
+            self.skipped += 1;
+        }
+    }
+}
+
+// Recorded tickers, in json lines or protobuf by extension
+pub enum TickerFile {
+    Json(JsonLines<Ticker>),
+    Protobuf(ProtobufTickers),
+}
+
+impl TickerFile {
+    pub fn open(path: &str) -> Result<Self> {
+        if is_protobuf(path) {
+            Ok(TickerFile::Protobuf(ProtobufTickers::open(path)?))
+        } else {
+            Ok(TickerFile::Json(JsonLines::open(path)?))
+        }
+    }
+
+    pub fn skipped(&self) -> usize {
+        match self {
+            TickerFile::Json(lines) => lines.skipped(),
+            TickerFile::Protobuf(records) => records.skipped(),
+        }
+    }
+}
+
+impl Iterator for TickerFile {
+    type Item = Result<Ticker>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TickerFile::Json(lines) => lines.next(),
+            TickerFile::Protobuf(records) => records.next(),
+        }
+    }
+}
+
+enum FileWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl FileWriter {
+    fn as_write(&mut self) -> &mut dyn Write {
+        match self {
+            FileWriter::Plain(writer) => writer,
+            FileWriter::Gzip(writer) => writer,
+            FileWriter::Zstd(writer) => writer,
+        }
+    }
+}
+
+// Writer of recorded tickers, format and compression by extension as TickerFile
+pub struct TickerWriter {
+    writer: FileWriter,
+    protobuf: bool,
+}
+
+impl TickerWriter {
+    pub fn create(path: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = BufWriter::new(File::create(path)?);
+        let writer = if path.ends_with(GZIP_EXTENSION) {
+            FileWriter::Gzip(GzEncoder::new(file, Compression::default()))
+        } else if path.ends_with(ZSTD_EXTENSION) {
+            FileWriter::Zstd(zstd::Encoder::new(file, 0)?)
+        } else {
+            FileWriter::Plain(file)
+        };
+        Ok(Self {
+            writer,
+            protobuf: is_protobuf(path),
+        })
+    }
+
+    pub fn write(&mut self, ticker: &Ticker) -> Result<()> {
+        let writer = self.writer.as_write();
+        if self.protobuf {
+            writer.write_all(&TickerEvent::from(ticker).encode_length_delimited_to_vec())?;
+        } else {
+            writeln!(writer, "{}", serde_json::to_string(ticker)?)?;
+        }
+        Ok(())
+    }
+
+    // Flush and end compression stream, the file is incomplete without it
+    pub fn finish(self) -> Result<()> {
+        match self.writer {
+            FileWriter::Plain(mut writer) => writer.flush()?,
+            FileWriter::Gzip(writer) => writer.finish()?.flush()?,
+            FileWriter::Zstd(writer) => writer.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+// Convert recorded tickers between json lines and protobuf, format of files by extension
+pub fn convert_file(source: &str, target: &str) -> Result<usize> {
+    if Path::new(target).exists()
+        && std::fs::canonicalize(source)? == std::fs::canonicalize(target)?
+    {
+        return Err(format!("Convert target is the same as source: {}", source).into());
+    }
+    let mut tickers = TickerFile::open(source)?;
+    let mut writer = TickerWriter::create(target)?;
+    let mut count = 0;
+    for ticker in tickers.by_ref() {
+        writer.write(&ticker?)?;
+        count += 1;
+    }
+    writer.finish()?;
+    if tickers.skipped() > 0 {
+        warn!(
+            "Skipped {} malformed items of {}",
+            tickers.skipped(),
+            source
+        );
+    }
+    Ok(count)
+}
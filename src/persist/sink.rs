@@ -0,0 +1,102 @@
+use crate::{
+    vo::biz::{MarketHoursType, OrderEvent, Ticker},
+    Result,
+};
+use async_trait::async_trait;
+use chrono::{Duration, TimeZone, Utc};
+use std::{fmt::Debug, sync::RwLock};
+
+// Destination of consumed tickers and order events
+#[async_trait]
+pub trait TickerSink: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // Tickers with changed volume, in received order
+    async fn save_tickers(&self, tickers: &[Ticker]) -> Result<()>;
+
+    async fn save_order(&self, event: &OrderEvent) -> Result<()>;
+}
+
+// Stored tickers of previous process
+#[async_trait]
+pub trait TickerSource: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    // Time of the first regular market ticker in the day of time, 0 when not found
+    async fn regular_start_time(&self, time: i64) -> Result<i64>;
+
+    // Regular market tickers of symbols after time in the same day, sort by time
+    async fn tickers_after(&self, time: i64, symbols: &[String]) -> Result<Vec<Ticker>>;
+}
+
+// Keep everything in memory, for test and debug
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    tickers: RwLock<Vec<Ticker>>,
+    orders: RwLock<Vec<OrderEvent>>,
+}
+
+impl MemorySink {
+    pub fn tickers(&self) -> Vec<Ticker> {
+        self.tickers.read().unwrap().clone()
+    }
+
+    pub fn orders(&self) -> Vec<OrderEvent> {
+        self.orders.read().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl TickerSink for MemorySink {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn save_tickers(&self, tickers: &[Ticker]) -> Result<()> {
+        self.tickers.write().unwrap().extend_from_slice(tickers);
+        Ok(())
+    }
+
+    async fn save_order(&self, event: &OrderEvent) -> Result<()> {
+        self.orders.write().unwrap().push(event.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TickerSource for MemorySink {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    async fn regular_start_time(&self, time: i64) -> Result<i64> {
+        let day = Utc.timestamp_millis_opt(time).unwrap().date_naive();
+        let start_time = self
+            .tickers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|t| t.market_hours == MarketHoursType::RegularMarket)
+            .filter(|t| Utc.timestamp_millis_opt(t.time).unwrap().date_naive() == day)
+            .map(|t| t.time)
+            .min()
+            .unwrap_or(0);
+        Ok(start_time)
+    }
+
+    async fn tickers_after(&self, time: i64, symbols: &[String]) -> Result<Vec<Ticker>> {
+        let day = Duration::days(1).num_milliseconds();
+        let end_time = time - time.rem_euclid(day) + day;
+        let mut tickers: Vec<Ticker> = self
+            .tickers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|t| t.market_hours == MarketHoursType::RegularMarket)
+            .filter(|t| t.time > time && t.time < end_time && symbols.contains(&t.id))
+            .cloned()
+            .collect();
+        tickers.sort_by_key(|t| (t.time, t.day_volume));
+        Ok(tickers)
+    }
+}
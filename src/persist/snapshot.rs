@@ -0,0 +1,223 @@
+use super::sink::TickerSource;
+use crate::{
+    vo::{
+        biz::{Order, Ticker, TradeInfo},
+        core::{AppContext, AssetContext},
+    },
+    Result,
+};
+use chrono::{TimeZone, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{cmp::max, collections::BTreeMap, fs, path::Path, sync::Arc};
+
+// State of consume session, restored after crash in the same day
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SessionSnapshot {
+    // taken time
+    pub time: i64,
+    pub sequence: i64,
+    pub regular_start_time: i64,
+    // newest first
+    pub orders: Vec<Order>,
+    pub shadow_orders: Vec<Order>,
+    // cached tickers by symbol, oldest first
+    pub tickers: BTreeMap<String, Vec<Ticker>>,
+}
+
+impl SessionSnapshot {
+    pub fn take(asset: &AssetContext) -> Self {
+        let orders = asset.orders().read().unwrap().iter().cloned().collect();
+        let shadow_orders = asset
+            .shadow_orders()
+            .read()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect();
+        let tickers = asset
+            .tickers()
+            .iter()
+            .map(|(symbol, lock)| {
+                let list = lock.read().unwrap();
+                (symbol.clone(), list.iter().rev().cloned().collect())
+            })
+            .collect();
+
+        Self {
+            time: Utc::now().timestamp_millis(),
+            sequence: asset.sequence(),
+            regular_start_time: asset.get_regular_start_time(),
+            orders,
+            shadow_orders,
+            tickers,
+        }
+    }
+
+    // Write to temp file then rename, a broken write keeps the previous snapshot
+    pub fn write(&self, file: &str) -> Result<()> {
+        let path = Path::new(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = format!("{}.tmp", file);
+        fs::write(&temp, serde_json::to_vec(self)?)?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    pub fn read(file: &str) -> Result<Option<Self>> {
+        if !Path::new(file).exists() {
+            return Ok(None);
+        }
+        let content = fs::read(file)?;
+        let snapshot = serde_json::from_slice(&content)
+            .map_err(|err| format!("Invalid session snapshot {}: {}", file, err))?;
+        Ok(Some(snapshot))
+    }
+
+    // Date of snapshot in UTC, tickers are collected by day
+    pub fn date(&self) -> String {
+        Utc.timestamp_millis_opt(self.time)
+            .unwrap()
+            .format("%Y%m%d")
+            .to_string()
+    }
+}
+
+impl AppContext {
+    // Rebuild session state from snapshot, and backfill tickers after snapshot from MongoDB
+    // Returns false when no snapshot restored
+    pub async fn restore_session(&self) -> Result<bool> {
+        let config = self.config();
+        if !config.snapshot.enabled {
+            return Ok(false);
+        }
+        let snapshot = match SessionSnapshot::read(&config.snapshot.file)? {
+            Some(snapshot) => snapshot,
+            None => {
+                info!("No session snapshot found: {}", &config.snapshot.file);
+                return Ok(false);
+            }
+        };
+        let today = Utc::now().format("%Y%m%d").to_string();
+        if snapshot.date() != today {
+            info!(
+                "Session snapshot of {} is outdated, start a new session",
+                snapshot.date()
+            );
+            return Ok(false);
+        }
+
+        let asset = self.asset();
+        *asset.orders().write().unwrap() = snapshot.orders.iter().cloned().collect();
+        *asset.shadow_orders().write().unwrap() = snapshot.shadow_orders.iter().cloned().collect();
+        // new message IDs continue after restored orders
+        asset.set_sequence(snapshot.sequence);
+        asset.set_regular_start_time(snapshot.regular_start_time);
+
+        let mut count = 0;
+        for (symbol, tickers) in snapshot.tickers.iter() {
+            if asset.symbol_tickers(symbol).is_none() {
+                warn!("Skip restoring tickers of unknown symbol: {}", symbol);
+                continue;
+            }
+            for ticker in tickers {
+                self.rebuild_ticker(ticker)?;
+                count += 1;
+            }
+        }
+        info!(
+            "Session restored from {}, orders: {}, tickers: {}, taken at: {}",
+            &config.snapshot.file,
+            snapshot.orders.len(),
+            count,
+            Utc.timestamp_millis_opt(snapshot.time).unwrap()
+        );
+
+        if config.snapshot.backfill {
+            match self.source() {
+                Some(source) => self.backfill_session(source, snapshot.time).await?,
+                None => warn!("No ticker source for backfill of session"),
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Fill tickers stored by previous process after snapshot taken
+    async fn backfill_session(&self, source: Arc<dyn TickerSource>, time: i64) -> Result<()> {
+        let config = self.config();
+        let asset = self.asset();
+        let symbols = config.symbols();
+        // latest restored ticker of each symbol, symbols without tickers are filled from the earliest
+        let latest: BTreeMap<String, i64> = symbols
+            .iter()
+            .filter_map(|s| asset.get_latest_ticker(s).map(|t| (s.clone(), t.time)))
+            .collect();
+        let from = latest.values().copied().min().unwrap_or(time);
+
+        let mut count = 0;
+        for ticker in source.tickers_after(from, &symbols).await? {
+            if ticker.time > *latest.get(&ticker.id).unwrap_or(&0) {
+                self.rebuild_ticker(&ticker)?;
+                count += 1;
+            }
+        }
+        info!(
+            "Backfill {} tickers after {} from {}",
+            count,
+            Utc.timestamp_millis_opt(from).unwrap(),
+            source.name()
+        );
+        Ok(())
+    }
+
+    // Add ticker and compute protfolios without trading, past tickers never place orders
+    fn rebuild_ticker(&self, ticker: &Ticker) -> Result<()> {
+        let mut ticker = ticker.clone();
+        // changed volume is not serialized
+        ticker.volume = Some(max(0, ticker.day_volume - self.last_volume(&ticker.id)));
+
+        let asset = self.asset();
+        asset.add_ticker(&ticker);
+
+        let units = self.config().time_units();
+        let unit_size = units.iter().filter(|u| u.period > 0).count();
+        let message_id = asset.next_message_id();
+        asset.add_trade(
+            &ticker.id,
+            TradeInfo::from(&ticker, message_id, unit_size, false),
+        );
+        for unit in units.iter() {
+            self.route(message_id, &ticker.id, unit)?;
+        }
+        Ok(())
+    }
+
+    // Take snapshot of session periodically
+    pub fn watch_session(self: &Arc<Self>) {
+        let config = self.config();
+        if !config.snapshot.enabled {
+            return;
+        }
+
+        let file = config.snapshot.file.clone();
+        let period = std::time::Duration::from_secs(config.snapshot.interval.max(1));
+        let context = Arc::clone(self);
+        info!("Take session snapshot every {:?} to {}", period, &file);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // skip the immediate tick
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let snapshot = SessionSnapshot::take(&context.asset());
+                match snapshot.write(&file) {
+                    Ok(_) => debug!("Session snapshot written: {}", &file),
+                    Err(err) => error!("Write session snapshot error: {:?}", err),
+                }
+            }
+        });
+    }
+}
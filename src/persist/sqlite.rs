@@ -0,0 +1,486 @@
+use super::{
+    day_start, parse_time,
+    reader::TickerFile,
+    sink::{TickerSink, TickerSource},
+};
+use crate::{
+    vo::{
+        biz::{BacktestReport, MarketHoursType, OrderEvent, Ticker},
+        core::AppContext,
+    },
+    Result,
+};
+use async_trait::async_trait;
+use chrono::{Duration, TimeZone, Utc};
+use log::{debug, info};
+use rusqlite::{params, Connection, Row, ToSql};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// Replay and export source of sqlite, ex: sqlite:TQQQ,SQQQ@20220309
+pub const SPEC_PREFIX: &str = "sqlite:";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS tickers (
+    id TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    price REAL NOT NULL,
+    quote_type TEXT NOT NULL,
+    market_hours TEXT NOT NULL,
+    day_volume INTEGER NOT NULL,
+    volume INTEGER,
+    change REAL NOT NULL,
+    time_diff INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tickers_id_time ON tickers (id, time);
+CREATE INDEX IF NOT EXISTS tickers_time ON tickers (time);
+CREATE TABLE IF NOT EXISTS orders (
+    id TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    constraint_id TEXT,
+    event TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS orders_time ON orders (time);
+CREATE TABLE IF NOT EXISTS reports (
+    source TEXT NOT NULL,
+    config_sha TEXT NOT NULL,
+    created_time INTEGER NOT NULL,
+    report TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reports_sha ON reports (config_sha);
+";
+
+// Tickers of symbols in [from, to), all symbols when empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickerQuery {
+    pub symbols: Vec<String>,
+    pub from: i64,
+    pub to: i64,
+}
+
+impl TickerQuery {
+    // Parse sqlite:[symbols@]from[..to], time in date (20220309, 2022-03-09), RFC3339 or millis
+    // The whole day of from when no end given, returns None for other sources
+    pub fn parse(spec: &str) -> Result<Option<Self>> {
+        let body = match spec.strip_prefix(SPEC_PREFIX) {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let (symbols, range) = match body.split_once('@') {
+            Some((symbols, range)) => (
+                symbols
+                    .split(',')
+                    .map(|s| s.trim().to_uppercase())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+                range,
+            ),
+            None => (Vec::new(), body),
+        };
+        let (from, to) = match range.split_once("..") {
+            Some((from, to)) => (parse_time(from, false)?, parse_time(to, true)?),
+            None => {
+                let from = parse_time(range, false)?;
+                (from, day_start(from) + Duration::days(1).num_milliseconds())
+            }
+        };
+        if from >= to {
+            return Err(format!("Invalid time range of {}, end before start", spec).into());
+        }
+        Ok(Some(Self { symbols, from, to }))
+    }
+
+    // Name as split files, ex: tickers20220309.TQQQ-SQQQ
+    pub fn name(&self) -> String {
+        let date = Utc
+            .timestamp_millis_opt(self.from)
+            .unwrap()
+            .format("%Y%m%d");
+        if self.symbols.is_empty() {
+            format!("tickers{}", date)
+        } else {
+            format!("tickers{}.{}", date, self.symbols.join("-"))
+        }
+    }
+}
+
+// Enum variants as text, same as JSON
+fn to_text<T: Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        Value::String(text) => Ok(text),
+        other => Ok(other.to_string()),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(text: String) -> rusqlite::Result<T> {
+    serde_json::from_value(Value::String(text))
+        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))
+}
+
+fn ticker_from_row(row: &Row) -> rusqlite::Result<Ticker> {
+    Ok(Ticker {
+        id: row.get(0)?,
+        time: row.get(1)?,
+        price: row.get::<_, f64>(2)? as f32,
+        quote_type: from_text(row.get(3)?)?,
+        market_hours: from_text(row.get(4)?)?,
+        day_volume: row.get(5)?,
+        volume: row.get(6)?,
+        change: row.get::<_, f64>(7)? as f32,
+        time_diff: row.get(8)?,
+    })
+}
+
+const TICKER_COLUMNS: &str =
+    "id, time, price, quote_type, market_hours, day_volume, volume, change, time_diff";
+
+// Tickers of one insert in import
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+fn insert_rows(conn: &Connection, tickers: &[Ticker]) -> Result<()> {
+    let mut statement = conn.prepare_cached(&format!(
+        "INSERT INTO tickers ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        TICKER_COLUMNS
+    ))?;
+    for ticker in tickers {
+        statement.execute(params![
+            &ticker.id,
+            ticker.time,
+            ticker.price as f64,
+            to_text(&ticker.quote_type)?,
+            to_text(&ticker.market_hours)?,
+            ticker.day_volume,
+            ticker.volume,
+            ticker.change as f64,
+            ticker.time_diff,
+        ])?;
+    }
+    Ok(())
+}
+
+fn delete_rows(conn: &Connection, query: &TickerQuery) -> Result<usize> {
+    let mut removed = 0;
+    if query.symbols.is_empty() {
+        removed += conn.execute(
+            "DELETE FROM tickers WHERE time >= ?1 AND time < ?2",
+            params![query.from, query.to],
+        )?;
+    } else {
+        for symbol in query.symbols.iter() {
+            removed += conn.execute(
+                "DELETE FROM tickers WHERE id = ?1 AND time >= ?2 AND time < ?3",
+                params![symbol, query.from, query.to],
+            )?;
+        }
+    }
+    Ok(removed)
+}
+
+// Embedded store of tickers, order events and backtest reports
+// Clones share the same connection
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(file: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(file).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(file)?;
+        conn.execute_batch(SCHEMA)?;
+        info!("Open SQLite database: {}", file);
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    // Run blocking store calls out of async runtime threads
+    async fn blocking<T, F>(&self, action: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SqliteStore) -> Result<T> + Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || action(&store)).await?
+    }
+
+    pub fn insert_tickers(&self, tickers: &[Ticker]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        insert_rows(&tx, tickers)?;
+        tx.commit()?;
+        Ok(tickers.len())
+    }
+
+    // Remove tickers of symbols in time range, returns removed size
+    pub fn delete_tickers(&self, query: &TickerQuery) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        delete_rows(&conn, query)
+    }
+
+    // Insert streamed tickers in batches within one transaction, tickers of replaced range removed first
+    // Nothing is stored when any ticker fails
+    pub fn import_tickers(
+        &self,
+        tickers: impl Iterator<Item = Result<Ticker>>,
+        replaced: Option<&TickerQuery>,
+    ) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if let Some(query) = replaced {
+            let removed = delete_rows(&tx, query)?;
+            debug!("Remove {} existing tickers", removed);
+        }
+
+        let mut batch: Vec<Ticker> = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        let mut count = 0;
+        for ticker in tickers {
+            batch.push(ticker?);
+            if batch.len() >= IMPORT_BATCH_SIZE {
+                insert_rows(&tx, &batch)?;
+                count += batch.len();
+                batch.clear();
+                debug!("Imported tickers: {}", count);
+            }
+        }
+        insert_rows(&tx, &batch)?;
+        count += batch.len();
+
+        tx.commit()?;
+        Ok(count)
+    }
+
+    // Tickers in received order
+    pub fn query_tickers(&self, query: &TickerQuery) -> Result<Vec<Ticker>> {
+        let mut sql = format!(
+            "SELECT {} FROM tickers WHERE time >= ? AND time < ?",
+            TICKER_COLUMNS
+        );
+        if !query.symbols.is_empty() {
+            let marks = vec!["?"; query.symbols.len()].join(", ");
+            sql.push_str(&format!(" AND id IN ({})", marks));
+        }
+        sql.push_str(" ORDER BY rowid");
+
+        let mut values: Vec<&dyn ToSql> = vec![&query.from, &query.to];
+        values.extend(query.symbols.iter().map(|s| s as &dyn ToSql));
+
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare_cached(&sql)?;
+        let tickers = statement
+            .query_map(values.as_slice(), ticker_from_row)?
+            .collect::<rusqlite::Result<Vec<Ticker>>>()?;
+        Ok(tickers)
+    }
+
+    pub fn insert_order(&self, event: &OrderEvent) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO orders (id, symbol, time, status, constraint_id, event) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                &event.order.id,
+                &event.order.symbol,
+                event.time,
+                to_text(&event.status)?,
+                &event.order.constraint_id,
+                serde_json::to_string(event)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Order events in [from, to)
+    pub fn query_orders(&self, from: i64, to: i64) -> Result<Vec<OrderEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare_cached(
+            "SELECT event FROM orders WHERE time >= ?1 AND time < ?2 ORDER BY rowid",
+        )?;
+        let rows = statement
+            .query_map(params![from, to], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let mut events = Vec::new();
+        for row in rows {
+            events.push(serde_json::from_str(&row)?);
+        }
+        Ok(events)
+    }
+
+    pub fn insert_report(&self, report: &BacktestReport) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO reports (source, config_sha, created_time, report) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                &report.source,
+                &report.config_sha,
+                Utc::now().timestamp_millis(),
+                serde_json::to_string(report)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Backtest reports of config, all when none
+    pub fn query_reports(&self, config_sha: Option<&str>) -> Result<Vec<BacktestReport>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare_cached(
+            "SELECT report FROM reports WHERE ?1 IS NULL OR config_sha = ?1 ORDER BY rowid",
+        )?;
+        let rows = statement
+            .query_map(params![config_sha], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        let mut reports = Vec::new();
+        for row in rows {
+            reports.push(serde_json::from_str(&row)?);
+        }
+        Ok(reports)
+    }
+}
+
+#[async_trait]
+impl TickerSink for SqliteStore {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    async fn save_tickers(&self, tickers: &[Ticker]) -> Result<()> {
+        let tickers = tickers.to_vec();
+        self.blocking(move |store| store.insert_tickers(&tickers))
+            .await?;
+        Ok(())
+    }
+
+    async fn save_order(&self, event: &OrderEvent) -> Result<()> {
+        let event = event.clone();
+        self.blocking(move |store| store.insert_order(&event)).await
+    }
+}
+
+#[async_trait]
+impl TickerSource for SqliteStore {
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+
+    async fn regular_start_time(&self, time: i64) -> Result<i64> {
+        let start_time = day_start(time);
+        self.blocking(move |store| {
+            let conn = store.conn.lock().unwrap();
+            let result: Option<i64> = conn.query_row(
+                "SELECT MIN(time) FROM tickers WHERE market_hours = ?1 AND time >= ?2 AND time < ?3",
+                params![
+                    to_text(&MarketHoursType::RegularMarket)?,
+                    start_time,
+                    start_time + Duration::days(1).num_milliseconds()
+                ],
+                |row| row.get(0),
+            )?;
+            Ok(result.unwrap_or(0))
+        })
+        .await
+    }
+
+    async fn tickers_after(&self, time: i64, symbols: &[String]) -> Result<Vec<Ticker>> {
+        let query = TickerQuery {
+            symbols: symbols.to_vec(),
+            from: time + 1,
+            to: day_start(time) + Duration::days(1).num_milliseconds(),
+        };
+        let mut tickers: Vec<Ticker> = self
+            .blocking(move |store| store.query_tickers(&query))
+            .await?
+            .into_iter()
+            .filter(|t| t.market_hours == MarketHoursType::RegularMarket)
+            .collect();
+        tickers.sort_by_key(|t| (t.time, t.day_volume));
+        Ok(tickers)
+    }
+}
+
+// Import tickers of json lines file, tickers in time range of file are replaced when truncate enabled
+// Lines are streamed and inserted in batches, memory stays flat for large files
+pub async fn import(context: &AppContext, path: &str) -> Result<()> {
+    info!("Import {} into SQLite", &path);
+    let config = context.config();
+    let store = context.persistence().sqlite()?;
+
+    // time range and symbols of file, scanned before streaming inserts
+    let replaced = if config.truncat_enabled() {
+        file_range(path)?
+    } else {
+        None
+    };
+
+    // changed volume is not serialized
+    let mut previous_volume: HashMap<String, i64> = HashMap::new();
+    let mut tickers = TickerFile::open(path)?;
+    let count = store.import_tickers(
+        tickers.by_ref().map(|ticker| {
+            ticker.map(|mut ticker| {
+                let previous = previous_volume.insert(ticker.id.clone(), ticker.day_volume);
+                ticker.volume = Some((ticker.day_volume - previous.unwrap_or(0)).max(0));
+                ticker
+            })
+        }),
+        replaced.as_ref(),
+    )?;
+    info!(
+        "Import {} tickers of {} done, skipped lines: {}",
+        count,
+        &path,
+        tickers.skipped()
+    );
+    Ok(())
+}
+
+// Query of tickers covered by file, None when file is empty
+fn file_range(path: &str) -> Result<Option<TickerQuery>> {
+    let mut symbols: BTreeSet<String> = BTreeSet::new();
+    let mut range: Option<(i64, i64)> = None;
+    for ticker in TickerFile::open(path)? {
+        let ticker = ticker?;
+        range = Some(match range {
+            Some((from, to)) => (from.min(ticker.time), to.max(ticker.time)),
+            None => (ticker.time, ticker.time),
+        });
+        symbols.insert(ticker.id);
+    }
+    Ok(range.map(|(from, to)| TickerQuery {
+        symbols: symbols.into_iter().collect(),
+        from,
+        to: to + 1,
+    }))
+}
+
+// Export tickers as json lines file named by query, ex: tmp/tickers20220309.TQQQ-SQQQ
+pub async fn export(context: &AppContext, query: &TickerQuery) -> Result<()> {
+    let config = context.config();
+    let store = context.persistence().sqlite()?;
+    let base_path = config.replay.outputs.base_folder.as_str();
+    let path = format!("{}/{}", base_path, query.name());
+
+    std::fs::create_dir_all(base_path)?;
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+    let mut writer = BufWriter::new(file);
+
+    let tickers = store.query_tickers(query)?;
+    for ticker in tickers.iter() {
+        writeln!(&mut writer, "{}", serde_json::to_string(ticker)?)?;
+    }
+    info!("File {} exported, tickers: {}", &path, tickers.len());
+    Ok(())
+}
@@ -62,14 +62,16 @@ pub async fn send_subscribe(
 }
 
 pub async fn consume(context: &Arc<AppContext>, addr: &str, symbols: &Vec<String>) -> Result<()> {
+    // recover from previous process
+    context.restore_session().await?;
+    context.watch_session();
+
     let mut client = create_websocket_client(addr).await?;
     send_subscribe(symbols, &mut client).await?;
 
     let mut connected = true;
     let asset = context.asset();
 
-    // TODO: recover from previous process
-
     loop {
         if connected {
             match handle_message(&Arc::clone(&context), &mut client).await {
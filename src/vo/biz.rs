@@ -1,3 +1,4 @@
+use super::core::AppConfig;
 use crate::proto::{biz::TickerEvent, yahoo::YahooTicker};
 use chrono::Utc;
 use rand::Rng;
@@ -137,6 +138,7 @@ impl From<&Ticker> for TickerEvent {
             day_volume: value.day_volume,
             volume: value.volume.unwrap_or(0),
             change: value.change,
+            time_diff: value.time_diff,
         }
     }
 }
@@ -156,16 +158,28 @@ impl From<TickerEvent> for Ticker {
                 Some(value.volume)
             },
             change: value.change,
-            time_diff: 0,
+            time_diff: value.time_diff,
         }
     }
 }
 
+// Bar of unit, bucketed by time, tick count or traded volume
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BarType {
+    #[default]
+    Time,
+    Tick,
+    Volume,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct TimeUnit {
     pub name: String,
+    // bar size, seconds of time bar, tick count of tick bar or traded volume of volume bar
     pub duration: i32,
     pub period: u32,
+    #[serde(default)]
+    pub bar: BarType,
 }
 
 impl Display for TimeUnit {
@@ -180,15 +194,43 @@ impl TimeUnit {
             name: name.to_string(),
             duration,
             period,
+            bar: BarType::Time,
         }
     }
 
-    pub fn is_moving_unit(name: &str) -> bool {
-        name.starts_with("m")
+    // Bar size in milliseconds, tick count or traded volume
+    pub fn bar_size(&self) -> i64 {
+        match self.bar {
+            BarType::Time => self.duration as i64 * 1000,
+            _ => self.duration as i64,
+        }
+    }
+
+    // Milliseconds of tickers required for aggregation
+    pub fn window(&self) -> i64 {
+        match (self.bar, self.period) {
+            // fixed unit takes source data in 3x time range
+            (BarType::Time, 0) => self.bar_size() * 3,
+            (BarType::Time, _) => self.bar_size() * self.period as i64,
+            _ => 0,
+        }
+    }
+
+    // Tick count or traded volume required for aggregation
+    pub fn activity(&self, bar: BarType) -> i64 {
+        if self.bar == bar && bar != BarType::Time {
+            self.bar_size() * self.period as i64
+        } else {
+            0
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.period > 0
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Protfolio {
     pub id: String,
     pub time: i64,
@@ -237,6 +279,9 @@ pub struct TradeInfo {
     #[serde(skip_serializing, skip_deserializing)]
     pub unit_size: usize,
     pub states: BTreeMap<String, Vec<f64>>,
+    // keyed by unit and indicator, ex: m0060.rsi
+    #[serde(default)]
+    pub indicators: BTreeMap<String, Vec<f64>>,
 }
 
 impl TradeInfo {
@@ -253,6 +298,7 @@ impl TradeInfo {
             unit_size,
             replay,
             states: BTreeMap::new(),
+            indicators: BTreeMap::new(),
         }
     }
 
@@ -260,6 +306,16 @@ impl TradeInfo {
         self.states.insert(unit.to_string(), slope);
     }
 
+    pub fn update_indicators(&mut self, unit: &str, values: BTreeMap<String, Vec<f64>>) {
+        for (name, value) in values {
+            self.indicators.insert(format!("{}.{}", unit, name), value);
+        }
+    }
+
+    pub fn indicator(&self, unit: &str, name: &str) -> Option<&Vec<f64>> {
+        self.indicators.get(&format!("{}.{}", unit, name))
+    }
+
     pub fn finalized(&self) -> bool {
         self.unit_size == self.states.len()
     }
@@ -301,6 +357,9 @@ pub struct Order {
     pub audit: AuditState,
     // rival order ID
     pub constraint_id: Option<String>,
+    // rule decision which placed this order
+    #[serde(default)]
+    pub decision: Option<AuditDecision>,
 }
 
 impl Order {
@@ -311,6 +370,7 @@ impl Order {
         volume: u32,
         time: i64,
         audit: AuditState,
+        decision: Option<AuditDecision>,
     ) -> Self {
         Self {
             id: format!(
@@ -331,6 +391,25 @@ impl Order {
             audit,
             constraint_id: None,
             write_off_time: None,
+            decision,
+        }
+    }
+}
+
+// Lifecycle change of order, status is the one changed to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrderEvent {
+    pub time: i64,
+    pub status: OrderStatus,
+    pub order: Order,
+}
+
+impl OrderEvent {
+    pub fn new(time: i64, order: &Order) -> Self {
+        Self {
+            time,
+            status: order.status.clone(),
+            order: order.clone(),
         }
     }
 }
@@ -357,7 +436,7 @@ pub enum OrderStatus {
     LossPair,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
 pub enum AuditState {
     Flash,
     Slug,
@@ -366,9 +445,77 @@ pub enum AuditState {
     ProfitTaking,
     EarlyClear,
     CloseTrade,
+    #[default]
     Decline,
 }
 
+// Result of auditing a trade with rules of an audit mode
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AuditDecision {
+    // audit mode, ex: flash, slug, revert
+    pub mode: String,
+    pub accepted: bool,
+    // index of first matched permit rule in mode rules
+    pub rule_index: Option<usize>,
+    // criteria results of matched rule
+    pub criteria: Vec<CriterionResult>,
+    // indexes of evaluation-only rules which would have fired
+    pub evaluations: Vec<usize>,
+}
+
+impl AuditDecision {
+    pub fn new(mode: &str) -> Self {
+        Self {
+            mode: mode.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Name of matched rule, ex: flash#2
+    pub fn rule_name(&self) -> Option<String> {
+        self.rule_index
+            .map(|index| format!("{}#{}", self.mode, index))
+    }
+
+    pub fn evaluation_names(&self) -> Vec<String> {
+        self.evaluations
+            .iter()
+            .map(|index| format!("{}#{}", self.mode, index))
+            .collect()
+    }
+}
+
+// Audit result of a trade
+#[derive(Debug, Clone)]
+pub struct AuditOutcome {
+    pub state: AuditState,
+    // decision of the rule based state
+    pub decision: Option<AuditDecision>,
+    // decisions with evaluation-only rules fired, for shadow orders
+    pub shadows: Vec<AuditDecision>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum CriterionKind {
+    Trend,
+    Deviation,
+    Oscillation,
+    Lower,
+    Indicator,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CriterionResult {
+    pub kind: CriterionKind,
+    pub from: Option<String>,
+    pub to: String,
+    // computed value, ex: 0.0023, Upward [3, 2]
+    pub value: String,
+    // configured threshold, ex: <= 0.003, Upward up: 3+ down: 2-
+    pub threshold: String,
+    pub passed: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum Trend {
     Upward,
@@ -434,3 +581,363 @@ pub struct PricePair {
     pub current: f32,
     pub rival: f32,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PairSummary {
+    // Replay source
+    pub source: String,
+    // Symbols of pair, ex: SQQQ-TQQQ
+    pub pair: String,
+    pub date: String,
+    pub order_count: usize,
+    pub loss_order_count: usize,
+    pub total_amount: f32,
+    pub total_profit: f32,
+    // highest/lowest cumulative profit, ordered by order created time
+    pub highest_profit: f32,
+    pub lowest_profit: f32,
+    pub max_drawdown: f32,
+    pub win_order_count: usize,
+    // orders with negative profit, break-even ones excluded
+    #[serde(default)]
+    pub losing_order_count: usize,
+    pub gross_profit: f32,
+    // absolute value of losses
+    pub gross_loss: f32,
+    // time in millis with open orders and of regular market
+    pub exposure_time: i64,
+    pub session_time: i64,
+    // accumulation of equity returns, for sharpe/sortino
+    pub return_count: usize,
+    pub return_sum: f64,
+    pub return_square_sum: f64,
+    pub downside_square_sum: f64,
+    pub config_sha: String,
+}
+
+impl PairSummary {
+    pub fn loss_order_rate(&self) -> f64 {
+        if self.order_count == 0 {
+            0.0
+        } else {
+            100.0 * self.loss_order_count as f64 / self.order_count as f64
+        }
+    }
+
+    pub fn profit_rate(&self) -> f32 {
+        if self.total_amount == 0.0 {
+            0.0
+        } else {
+            self.total_profit / self.total_amount * 100.0
+        }
+    }
+
+    pub fn win_rate(&self) -> f64 {
+        if self.order_count == 0 {
+            0.0
+        } else {
+            100.0 * self.win_order_count as f64 / self.order_count as f64
+        }
+    }
+
+    pub fn average_win(&self) -> f32 {
+        if self.win_order_count == 0 {
+            0.0
+        } else {
+            self.gross_profit / self.win_order_count as f32
+        }
+    }
+
+    pub fn average_loss(&self) -> f32 {
+        if self.losing_order_count == 0 {
+            0.0
+        } else {
+            self.gross_loss / self.losing_order_count as f32
+        }
+    }
+
+    // None when no loss
+    pub fn profit_factor(&self) -> Option<f64> {
+        if self.gross_loss == 0.0 {
+            None
+        } else {
+            Some(self.gross_profit as f64 / self.gross_loss as f64)
+        }
+    }
+
+    pub fn exposure(&self) -> f64 {
+        if self.session_time == 0 {
+            0.0
+        } else {
+            100.0 * self.exposure_time as f64 / self.session_time as f64
+        }
+    }
+
+    // Sharpe ratio of intraday returns, not annualized
+    pub fn sharpe(&self) -> Option<f64> {
+        if self.return_count < 2 {
+            return None;
+        }
+        let n = self.return_count as f64;
+        let mean = self.return_sum / n;
+        let variance = (self.return_square_sum - n * mean * mean) / (n - 1.0);
+        if variance <= 0.0 {
+            None
+        } else {
+            Some(mean / variance.sqrt())
+        }
+    }
+
+    // Sortino ratio of intraday returns, not annualized
+    pub fn sortino(&self) -> Option<f64> {
+        if self.return_count == 0 || self.downside_square_sum <= 0.0 {
+            return None;
+        }
+        let n = self.return_count as f64;
+        Some((self.return_sum / n) / (self.downside_square_sum / n).sqrt())
+    }
+
+    // Merge results of multiple replays into one
+    pub fn merge(name: &str, summaries: &[PairSummary]) -> Self {
+        let mut pairs: Vec<String> = summaries.iter().map(|s| s.pair.clone()).collect();
+        pairs.sort();
+        pairs.dedup();
+        let mut shas: Vec<String> = summaries.iter().map(|s| s.config_sha.clone()).collect();
+        shas.sort();
+        shas.dedup();
+        let mut dates: Vec<String> = summaries.iter().map(|s| s.date.clone()).collect();
+        dates.sort();
+        dates.dedup();
+
+        // chain cumulative profit of summaries in order
+        let mut equity = 0.0_f32;
+        let mut highest_profit = 0.0_f32;
+        let mut lowest_profit = 0.0_f32;
+        let mut max_drawdown = 0.0_f32;
+        for summary in summaries {
+            max_drawdown = max_drawdown
+                .max(summary.max_drawdown)
+                .max(highest_profit - (equity + summary.lowest_profit));
+            lowest_profit = lowest_profit.min(equity + summary.lowest_profit);
+            highest_profit = highest_profit.max(equity + summary.highest_profit);
+            equity += summary.total_profit;
+        }
+
+        Self {
+            source: name.to_string(),
+            pair: pairs.join(","),
+            // keep date only when all from the same day
+            date: if dates.len() == 1 {
+                dates.remove(0)
+            } else {
+                String::new()
+            },
+            order_count: summaries.iter().map(|s| s.order_count).sum(),
+            loss_order_count: summaries.iter().map(|s| s.loss_order_count).sum(),
+            total_amount: summaries.iter().map(|s| s.total_amount).sum(),
+            total_profit: summaries.iter().map(|s| s.total_profit).sum(),
+            highest_profit,
+            lowest_profit,
+            max_drawdown,
+            win_order_count: summaries.iter().map(|s| s.win_order_count).sum(),
+            losing_order_count: summaries.iter().map(|s| s.losing_order_count).sum(),
+            gross_profit: summaries.iter().map(|s| s.gross_profit).sum(),
+            gross_loss: summaries.iter().map(|s| s.gross_loss).sum(),
+            exposure_time: summaries.iter().map(|s| s.exposure_time).sum(),
+            session_time: summaries.iter().map(|s| s.session_time).sum(),
+            return_count: summaries.iter().map(|s| s.return_count).sum(),
+            return_sum: summaries.iter().map(|s| s.return_sum).sum(),
+            return_square_sum: summaries.iter().map(|s| s.return_square_sum).sum(),
+            downside_square_sum: summaries.iter().map(|s| s.downside_square_sum).sum(),
+            config_sha: shas.join(","),
+        }
+    }
+}
+
+// Settled order, flatten for report
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OrderRow {
+    pub id: String,
+    pub symbol: String,
+    pub pair: String,
+    pub date: String,
+    pub created_time: i64,
+    pub created_price: f32,
+    pub created_volume: u32,
+    // price of first post market ticker
+    pub closed_price: f32,
+    pub amount: f32,
+    pub profit: f32,
+    pub write_off_time: Option<i64>,
+    pub status: String,
+    pub audit: AuditState,
+    pub constraint_id: String,
+    // matched rule of order, ex: flash#2
+    pub rule: String,
+    // evaluation-only rules which would have fired, ex: flash#5,flash#6
+    pub evaluations: String,
+}
+
+// Profit attribution of an audit rule
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RuleSummary {
+    // ex: flash#2
+    pub rule: String,
+    pub evaluation: bool,
+    pub order_count: usize,
+    pub win_order_count: usize,
+    pub total_profit: f32,
+}
+
+impl RuleSummary {
+    // Attribute order profits to matched rules and evaluation-only rules
+    pub fn attribute(rows: &[OrderRow]) -> Vec<Self> {
+        let mut summaries: BTreeMap<(bool, String), Self> = BTreeMap::new();
+        for row in rows {
+            let rules = std::iter::once((false, row.rule.as_str()))
+                .chain(row.evaluations.split(',').map(|rule| (true, rule)))
+                .filter(|(_, rule)| !rule.is_empty());
+            for (evaluation, rule) in rules {
+                let summary = summaries
+                    .entry((evaluation, rule.to_string()))
+                    .or_insert_with(|| Self {
+                        rule: rule.to_string(),
+                        evaluation,
+                        ..Default::default()
+                    });
+                summary.order_count += 1;
+                if row.profit > 0.0 {
+                    summary.win_order_count += 1;
+                }
+                summary.total_profit += row.profit;
+            }
+        }
+        summaries.into_values().collect()
+    }
+
+    // Merge summaries of the same rule
+    pub fn merge(list: &[RuleSummary]) -> Vec<Self> {
+        let mut summaries: BTreeMap<(bool, String), Self> = BTreeMap::new();
+        for item in list {
+            let summary = summaries
+                .entry((item.evaluation, item.rule.clone()))
+                .or_insert_with(|| Self {
+                    rule: item.rule.clone(),
+                    evaluation: item.evaluation,
+                    ..Default::default()
+                });
+            summary.order_count += item.order_count;
+            summary.win_order_count += item.win_order_count;
+            summary.total_profit += item.total_profit;
+        }
+        summaries.into_values().collect()
+    }
+}
+
+// Mark-to-market equity of orders
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EquityPoint {
+    pub pair: String,
+    pub date: String,
+    pub time: i64,
+    pub equity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RiskMetrics {
+    pub pair: String,
+    pub date: String,
+    pub total_profit: f32,
+    pub max_drawdown: f32,
+    pub sharpe: Option<f64>,
+    pub sortino: Option<f64>,
+    pub win_rate: f64,
+    pub average_win: f32,
+    pub average_loss: f32,
+    pub profit_factor: Option<f64>,
+    pub exposure: f64,
+}
+
+impl From<&PairSummary> for RiskMetrics {
+    fn from(summary: &PairSummary) -> Self {
+        Self {
+            pair: summary.pair.clone(),
+            date: summary.date.clone(),
+            total_profit: summary.total_profit,
+            max_drawdown: summary.max_drawdown,
+            sharpe: summary.sharpe(),
+            sortino: summary.sortino(),
+            win_rate: summary.win_rate(),
+            average_win: summary.average_win(),
+            average_loss: summary.average_loss(),
+            profit_factor: summary.profit_factor(),
+            exposure: summary.exposure(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BacktestReport {
+    pub source: String,
+    pub config_sha: String,
+    pub config: AppConfig,
+    // ordered by created time
+    pub orders: Vec<OrderRow>,
+    pub pairs: Vec<PairSummary>,
+    pub days: Vec<PairSummary>,
+    // equity curves of pairs and days
+    pub equity: Vec<EquityPoint>,
+    pub metrics: Vec<RiskMetrics>,
+    // profit attribution of rules
+    #[serde(default)]
+    pub rules: Vec<RuleSummary>,
+    // hypothetical orders of evaluation-only rules
+    #[serde(default)]
+    pub shadow_orders: Vec<OrderRow>,
+    #[serde(default)]
+    pub shadows: Vec<RuleSummary>,
+}
+
+impl BacktestReport {
+    pub fn summary(&self) -> PairSummary {
+        PairSummary {
+            source: self.source.clone(),
+            ..PairSummary::merge(&self.source, &self.days)
+        }
+    }
+
+    // Merge reports of multiple replays, config snapshot is taken from the first one
+    pub fn merge(source: &str, reports: &[BacktestReport]) -> Option<Self> {
+        let first = reports.first()?;
+        let mut shas: Vec<String> = reports.iter().map(|r| r.config_sha.clone()).collect();
+        shas.sort();
+        shas.dedup();
+
+        Some(Self {
+            source: source.to_string(),
+            config_sha: shas.join(","),
+            config: first.config.clone(),
+            orders: reports.iter().flat_map(|r| r.orders.clone()).collect(),
+            pairs: reports.iter().flat_map(|r| r.pairs.clone()).collect(),
+            days: reports.iter().flat_map(|r| r.days.clone()).collect(),
+            equity: reports.iter().flat_map(|r| r.equity.clone()).collect(),
+            metrics: reports.iter().flat_map(|r| r.metrics.clone()).collect(),
+            rules: RuleSummary::merge(
+                &reports
+                    .iter()
+                    .flat_map(|r| r.rules.clone())
+                    .collect::<Vec<RuleSummary>>(),
+            ),
+            shadow_orders: reports
+                .iter()
+                .flat_map(|r| r.shadow_orders.clone())
+                .collect(),
+            shadows: RuleSummary::merge(
+                &reports
+                    .iter()
+                    .flat_map(|r| r.shadows.clone())
+                    .collect::<Vec<RuleSummary>>(),
+            ),
+        })
+    }
+}
@@ -1,9 +1,23 @@
 use super::biz::{
-    MarketHoursType, Order, OrderStatus, Protfolio, Ticker, TimeUnit, TradeInfo, Trend,
+    AuditState, BarType, MarketHoursType, Order, OrderEvent, OrderStatus, Protfolio, Ticker,
+    TimeUnit, TradeInfo, Trend,
 };
 use crate::{
-    analysis::{init_dispatcher, trade::prepare_trade},
-    persist::{es::ElasticTicker, mongo::get_start_time, PersistenceContext},
+    analysis::{
+        indicator::{
+            IndicatorEngine, INDICATOR_ATR, INDICATOR_BOLLINGER, INDICATOR_EMA, INDICATOR_MACD,
+            INDICATOR_RSI, INDICATOR_VWAP,
+        },
+        init_dispatcher,
+        rolling::MovingAggregator,
+        trade::prepare_trade,
+    },
+    persist::{
+        create_sinks, create_source,
+        grafana::add_order_annotation,
+        sink::{TickerSink, TickerSource},
+        PersistenceContext,
+    },
     proto::biz::TickerEvent,
     Result,
 };
@@ -12,26 +26,42 @@ use config::Config;
 use log::*;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
 use std::{
     cmp::max,
     collections::{BTreeMap, HashMap, HashSet, LinkedList},
+    env, fs,
+    path::Path,
     sync::{Arc, Mutex, RwLock},
 };
-use tokio::sync::broadcast::{channel, Receiver, Sender};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::broadcast::{channel, Receiver, Sender},
+};
 
-pub const KEY_EXTRA_CONFIG_FILE_PATH: &str = "config_file_path";
-pub const KEY_EXTRA_PRCOESS_IN_ASYNC: &str = "process_in_async";
-pub const KEY_EXTRA_ENABLE_DATA_TRUNCAT: &str = "enable_clean_data_before_operation";
-pub const KEY_EXTRA_PRINT_TRADE_META_START_TIME: &str = "print_trade_meta_start_time";
-pub const KEY_EXTRA_PRINT_TRADE_META_END_TIME: &str = "print_trade_meta_end_time";
+// Prefix of environment variables overriding config, ex: SMINER_TRADE__ENABLED=false
+pub const ENV_PREFIX: &str = "SMINER_";
+// Profile overlay when not given by command line
+pub const ENV_PROFILE: &str = "SMINER_PROFILE";
 
 pub type LockTradeInfo = Arc<RwLock<TradeInfo>>;
 pub type LockListMap<T> = BTreeMap<String, RwLock<LinkedList<T>>>;
+// (time, price) by mark
+pub type PriceMarks = BTreeMap<i64, (i64, f32)>;
+
+// Interval of kept price marks
+pub const PRICE_MARK_INTERVAL: i64 = 60_000;
 
 #[derive(Debug)]
 pub struct AppContext {
-    config: Arc<AppConfig>,
+    // swapped on reload of trade rules
+    config: RwLock<Arc<AppConfig>>,
     persistence: Arc<PersistenceContext>,
+    // destinations of tickers and order events, handled from init
+    sinks: RwLock<Vec<Arc<dyn TickerSink>>>,
+    // stored tickers of previous process
+    source: RwLock<Option<Arc<dyn TickerSource>>>,
     asset: Arc<AssetContext>,
     post_man: Arc<PostMan>,
 }
@@ -39,20 +69,112 @@ pub struct AppContext {
 impl AppContext {
     pub fn new(app_config: AppConfig) -> Self {
         let config = Arc::new(app_config);
-        let persistence = PersistenceContext::new(Arc::clone(&config));
+        let persistence = Arc::new(PersistenceContext::new(Arc::clone(&config)));
         let asset = AssetContext::new(Arc::clone(&config));
         let post_man = PostMan::new(Arc::clone(&config));
 
         Self {
-            config: Arc::clone(&config),
-            persistence: Arc::new(persistence),
+            config: RwLock::new(Arc::clone(&config)),
+            sinks: RwLock::new(create_sinks(&persistence)),
+            source: RwLock::new(create_source(&persistence)),
+            persistence,
             asset: Arc::new(asset),
             post_man: Arc::new(post_man),
         }
     }
 
     pub fn config(&self) -> Arc<AppConfig> {
-        Arc::clone(&self.config)
+        Arc::clone(&self.config.read().unwrap())
+    }
+
+    // Reload trade rules from config layers, running units, tickers and other sections are kept
+    // Returns config sha when rules swapped, None when unchanged
+    pub fn reload_trade(&self) -> Result<Option<String>> {
+        let current = self.config();
+        let switches = &current.switches;
+        let mut loaded = AppConfig::load_layered(
+            &switches.config_file,
+            switches.profile.as_deref(),
+            &switches.overrides,
+        )?;
+
+        // trade handlers are initialized on start
+        let mut next = current.as_ref().clone();
+        next.trade = TradeAudit {
+            enabled: current.trade.enabled,
+            ..loaded.trade.clone()
+        };
+        // references resolved against running units
+        next.validate()?;
+        let sha = next.sha()?;
+        if sha == current.sha()? {
+            return Ok(None);
+        }
+
+        loaded.trade = current.trade.clone();
+        if loaded.sha()? != current.sha()? {
+            warn!("Config changes other than trade rules are applied after restart");
+        }
+
+        *self.config.write().unwrap() = Arc::new(next);
+        Ok(Some(sha))
+    }
+
+    // Reload trade rules on SIGHUP or modification of config files
+    pub fn watch_config(self: &Arc<Self>) -> Result<()> {
+        let config = self.config();
+        if !config.reload.enabled {
+            return Ok(());
+        }
+
+        let files = config.switches.files();
+        let period = std::time::Duration::from_secs(config.reload.interval.max(1));
+        let mut hangup = signal(SignalKind::hangup())?;
+        let context = Arc::clone(self);
+        info!("Watch config files: {:?}", &files);
+        tokio::spawn(async move {
+            let mut modified = modified_times(&files);
+            let mut interval = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = hangup.recv() => info!("Received SIGHUP, reload config"),
+                    _ = interval.tick() => {
+                        let times = modified_times(&files);
+                        if times == modified {
+                            continue;
+                        }
+                        modified = times;
+                        info!("Config files modified, reload config");
+                    }
+                }
+
+                match context.reload_trade() {
+                    Ok(Some(sha)) => {
+                        info!("Trade rules reloaded, config sha: {}", sha);
+                        if let Err(err) = add_order_annotation(
+                            context.config(),
+                            Utc::now(),
+                            format!("Trade rules reloaded, config sha: {}", sha),
+                            vec!["reload".to_string()],
+                        ) {
+                            error!("Annotate reload error: {:?}", err);
+                        }
+                    }
+                    Ok(None) => info!(
+                        "Trade rules unchanged, config sha: {}",
+                        context.config().sha().unwrap_or_default()
+                    ),
+                    Err(err) => {
+                        error!("Config rejected, running trade rules are kept");
+                        for line in err.to_string().lines() {
+                            error!("{}", line);
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(())
     }
 
     pub fn persistence(&self) -> Arc<PersistenceContext> {
@@ -63,21 +185,46 @@ impl AppContext {
         Arc::clone(&self.asset)
     }
 
+    pub fn sinks(&self) -> Vec<Arc<dyn TickerSink>> {
+        self.sinks.read().unwrap().clone()
+    }
+
+    // Sinks registered after init are saved by dispatch_direct only
+    pub fn register_sink(&self, sink: Arc<dyn TickerSink>) {
+        info!("Register ticker sink: {}", sink.name());
+        self.sinks.write().unwrap().push(sink);
+    }
+
+    pub fn source(&self) -> Option<Arc<dyn TickerSource>> {
+        self.source.read().unwrap().clone()
+    }
+
+    pub fn set_source(&self, source: Arc<dyn TickerSource>) {
+        info!("Use ticker source: {}", source.name());
+        *self.source.write().unwrap() = Some(source);
+    }
+
     pub fn post_man(&self) -> Arc<PostMan> {
         Arc::clone(&self.post_man)
     }
 
     pub async fn init(self) -> Result<Arc<Self>> {
-        let me = Arc::new(self);
-        if me.config().async_process() {
-            init_dispatcher(&Arc::clone(&me)).await?;
-        }
+        let me = self.init_replay().await?;
         // FIXME: init mongo for temp solution
         let persistence = me.persistence();
         persistence.init_mongo().await?;
         Ok(Arc::clone(&me))
     }
 
+    // Init without MongoDB client, tickers of replay files are loaded locally
+    pub async fn init_replay(self) -> Result<Arc<Self>> {
+        let me = Arc::new(self);
+        if me.config().async_process() {
+            init_dispatcher(&Arc::clone(&me)).await?;
+        }
+        Ok(me)
+    }
+
     pub fn last_volume(&self, symbol: &str) -> i64 {
         let lock = self.asset.symbol_tickers(symbol).unwrap();
         let list = lock.read().unwrap();
@@ -94,7 +241,7 @@ impl AppContext {
         let volume_diff = max(0, ticker.day_volume - self.last_volume(&ticker.id));
 
         // send to persist
-        if config.sync_mongo_enabled() || config.sync_elasticsearch_enabled() {
+        if !self.sinks.read().unwrap().is_empty() {
             let mut event: TickerEvent = ticker.into();
             // calculate volume
             event.volume = volume_diff;
@@ -118,8 +265,16 @@ impl AppContext {
             MarketHoursType::RegularMarket => {
                 // runtime broken and restarted while regular market period
                 if asset.get_regular_start_time() == 0 {
-                    let start_time = get_start_time(self.persistence(), self.config()).await;
-                    asset.set_regular_start_time(start_time);
+                    let start_time = match self.source() {
+                        Some(source) => source.regular_start_time(ticker.time).await?,
+                        None => 0,
+                    };
+                    // no stored ticker, started from this one
+                    asset.set_regular_start_time(if start_time > 0 {
+                        start_time
+                    } else {
+                        ticker.time
+                    });
                 }
             }
             _ => {}
@@ -134,24 +289,13 @@ impl AppContext {
         ticker.volume = Some(max(0, ticker.day_volume - self.last_volume(&ticker.id)));
 
         // save data
-        if self.config.sync_mongo_enabled() {
-            ticker.save_to_mongo(self.persistence()).await?;
-        }
-        if self.config.sync_elasticsearch_enabled() {
-            let es_ticker: ElasticTicker = (*ticker).clone().into();
-            es_ticker.save_to_elasticsearch(self.persistence()).await?;
+        for sink in self.sinks() {
+            sink.save_tickers(std::slice::from_ref(ticker)).await?;
         }
 
         // Add into source list
-        if let Some(lock) = self.asset.symbol_tickers(&ticker.id) {
-            let mut list = lock.write().unwrap();
-            list.push_front(ticker.clone());
-            debug!(
-                "{} ticker size: {}, message_id: {}",
-                ticker.id,
-                list.len(),
-                &message_id
-            );
+        if self.asset.add_ticker(ticker) {
+            debug!("{} ticker added, message_id: {}", ticker.id, &message_id);
         } else {
             error!("No tickers container {} initialized", &ticker.id);
         }
@@ -200,10 +344,28 @@ pub struct AssetContext {
     tickers: Arc<LockListMap<Ticker>>,
     // aggregated trade data
     trades: Arc<LockListMap<LockTradeInfo>>,
+    // trade data by message ID
+    trade_index: Arc<RwLock<HashMap<i64, LockTradeInfo>>>,
+    // cached data kept, none for keeping all
+    retention: Option<Retention>,
+    // traded volume of cached tickers
+    ticker_volumes: Arc<HashMap<String, Mutex<i64>>>,
+    // latest price of each mark interval
+    price_marks: Arc<HashMap<String, RwLock<PriceMarks>>>,
+    // first ticker of post market
+    post_tickers: Arc<RwLock<HashMap<String, Ticker>>>,
     // computed trend info
     protfolios: Arc<HashMap<String, LockListMap<Protfolio>>>,
+    // technical indicators of moving units
+    indicators: Arc<HashMap<String, BTreeMap<String, Mutex<IndicatorEngine>>>>,
+    // rolling aggregation of moving units
+    aggregators: Arc<HashMap<String, BTreeMap<String, Mutex<MovingAggregator>>>>,
     // placed orders
     orders: Arc<RwLock<LinkedList<Order>>>,
+    // hypothetical orders of evaluation-only rules
+    shadow_orders: Arc<RwLock<LinkedList<Order>>>,
+    // Sender for order lifecycle events
+    order_events: Sender<OrderEvent>,
     // number of generating ID
     sequence: Arc<Mutex<i64>>,
     // start time of regular market
@@ -215,13 +377,35 @@ impl AssetContext {
         let tickers = Self::init_tickers(Arc::clone(&config));
         let protfolios = Self::init_protfolios(Arc::clone(&config));
         let trades = Self::init_trades(Arc::clone(&config));
+        let indicators = Self::init_indicators(Arc::clone(&config));
+        let aggregators = Self::init_aggregators(Arc::clone(&config));
+        let price_marks = config
+            .symbols()
+            .into_iter()
+            .map(|symbol| (symbol, RwLock::new(BTreeMap::new())))
+            .collect();
 
         Self {
             config: Arc::clone(&config),
             tickers: Arc::new(tickers),
             protfolios: Arc::new(protfolios),
+            indicators: Arc::new(indicators),
+            aggregators: Arc::new(aggregators),
             trades: Arc::new(trades),
+            trade_index: Arc::new(RwLock::new(HashMap::new())),
+            retention: config.retention_policy(),
+            ticker_volumes: Arc::new(
+                config
+                    .symbols()
+                    .into_iter()
+                    .map(|symbol| (symbol, Mutex::new(0)))
+                    .collect(),
+            ),
+            price_marks: Arc::new(price_marks),
+            post_tickers: Arc::new(RwLock::new(HashMap::new())),
             orders: Arc::new(RwLock::new(LinkedList::new())),
+            shadow_orders: Arc::new(RwLock::new(LinkedList::new())),
+            order_events: channel::<OrderEvent>(128).0,
             sequence: Arc::new(Mutex::new(
                 Utc::now().timestamp_millis() % Duration::days(3).num_milliseconds(),
             )),
@@ -251,6 +435,40 @@ impl AssetContext {
         map
     }
 
+    fn init_indicators(
+        config: Arc<AppConfig>,
+    ) -> HashMap<String, BTreeMap<String, Mutex<IndicatorEngine>>> {
+        let mut map: HashMap<String, BTreeMap<String, Mutex<IndicatorEngine>>> = HashMap::new();
+        if !config.indicators.enabled {
+            return map;
+        }
+        for symbol in config.symbols() {
+            let mut uniter: BTreeMap<String, Mutex<IndicatorEngine>> = BTreeMap::new();
+            for unit in config.time_units().iter().filter(|u| u.period > 0) {
+                uniter.insert(
+                    unit.name.clone(),
+                    Mutex::new(IndicatorEngine::new(&config.indicators, unit)),
+                );
+            }
+            map.insert(symbol, uniter);
+        }
+        map
+    }
+
+    fn init_aggregators(
+        config: Arc<AppConfig>,
+    ) -> HashMap<String, BTreeMap<String, Mutex<MovingAggregator>>> {
+        let mut map: HashMap<String, BTreeMap<String, Mutex<MovingAggregator>>> = HashMap::new();
+        for symbol in config.symbols() {
+            let mut uniter: BTreeMap<String, Mutex<MovingAggregator>> = BTreeMap::new();
+            for unit in config.time_units().iter().filter(|u| u.period > 0) {
+                uniter.insert(unit.name.clone(), Mutex::new(MovingAggregator::new(unit)));
+            }
+            map.insert(symbol, uniter);
+        }
+        map
+    }
+
     fn init_trades(config: Arc<AppConfig>) -> LockListMap<LockTradeInfo> {
         let symbols = config.symbols();
         let mut map: LockListMap<LockTradeInfo> = BTreeMap::new();
@@ -276,10 +494,97 @@ impl AssetContext {
         Arc::clone(&self.orders)
     }
 
+    pub fn shadow_orders(&self) -> Arc<RwLock<LinkedList<Order>>> {
+        Arc::clone(&self.shadow_orders)
+    }
+
+    pub fn subscribe_orders(&self) -> Receiver<OrderEvent> {
+        self.order_events.subscribe()
+    }
+
+    // No receiver without persistence of orders
+    fn publish_order(&self, time: i64, order: &Order) {
+        if self.order_events.receiver_count() > 0 {
+            if let Err(err) = self.order_events.send(OrderEvent::new(time, order)) {
+                error!("Publish order event error: {:?}", err);
+            }
+        }
+    }
+
     pub fn symbol_tickers(&self, symbol: &str) -> Option<&RwLock<LinkedList<Ticker>>> {
         self.tickers.get(symbol)
     }
 
+    // Add into source list, tickers out of retention are dropped
+    pub fn add_ticker(&self, ticker: &Ticker) -> bool {
+        let lock = match self.tickers.get(&ticker.id) {
+            Some(lock) => lock,
+            None => return false,
+        };
+        let mut list = lock.write().unwrap();
+        list.push_front(ticker.clone());
+
+        if let Some(lock) = self.price_marks.get(&ticker.id) {
+            // smallest mark at or after ticker time
+            let mark = (ticker.time + PRICE_MARK_INTERVAL - 1).div_euclid(PRICE_MARK_INTERVAL)
+                * PRICE_MARK_INTERVAL;
+            let mut marks = lock.write().unwrap();
+            match marks.get(&mark) {
+                Some((time, _)) if *time > ticker.time => {}
+                _ => {
+                    marks.insert(mark, (ticker.time, ticker.price));
+                }
+            }
+        }
+
+        if matches!(ticker.market_hours, MarketHoursType::PostMarket) {
+            let mut post_tickers = self.post_tickers.write().unwrap();
+            if !post_tickers.contains_key(&ticker.id) {
+                post_tickers.insert(ticker.id.clone(), ticker.clone());
+            }
+        }
+
+        if let (Some(retention), Some(lock)) = (self.retention, self.ticker_volumes.get(&ticker.id))
+        {
+            let min_time = ticker.time - retention.window;
+            let mut volume = lock.lock().unwrap();
+            *volume += ticker.volume.unwrap_or(0);
+            let mut count = 0;
+            // keep the latest tickers and traded volume required by activity bars
+            while matches!(list.back(), Some(t) if t.time < min_time
+                && list.len() as i64 > retention.ticks
+                && *volume - t.volume.unwrap_or(0) >= retention.volume)
+            {
+                let evicted = list.pop_back().unwrap();
+                *volume -= evicted.volume.unwrap_or(0);
+                count += 1;
+            }
+            if count > 0 {
+                if let Some(map) = self.aggregators.get(&ticker.id) {
+                    for aggregator in map.values() {
+                        aggregator.lock().unwrap().evict(count);
+                    }
+                }
+                debug!(
+                    "Evict {} tickers of {}, size: {}",
+                    count,
+                    &ticker.id,
+                    list.len()
+                );
+            }
+        }
+
+        true
+    }
+
+    // Latest (time, price) at or before each mark, sort by time
+    pub fn price_marks(&self, symbol: &str) -> Vec<(i64, f32)> {
+        match self.price_marks.get(symbol) {
+            Some(lock) => lock.read().unwrap().values().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn get_current_market(&self, symbol: &str) -> Option<MarketHoursType> {
         let lock = self.tickers.get(symbol).unwrap();
         let reader = lock.read().unwrap();
@@ -291,18 +596,8 @@ impl AssetContext {
     }
 
     pub fn get_first_post_ticker(&self, symbol: &str) -> Option<Ticker> {
-        let lock = self.tickers.get(symbol).unwrap();
-        let reader = lock.read().unwrap();
-        let first_trade = reader
-            .iter()
-            .filter(|ticker| matches!(ticker.market_hours, MarketHoursType::PostMarket))
-            .last();
-
-        if let Some(trade) = first_trade {
-            Some(trade.clone())
-        } else {
-            None
-        }
+        let reader = self.post_tickers.read().unwrap();
+        reader.get(symbol).cloned()
     }
 
     pub fn get_latest_ticker(&self, symbol: &str) -> Option<Ticker> {
@@ -339,6 +634,34 @@ impl AssetContext {
         }
     }
 
+    pub fn get_indicators(&self, symbol: &str, unit: &str) -> Option<&Mutex<IndicatorEngine>> {
+        if let Some(map) = self.indicators.get(symbol) {
+            map.get(unit)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_aggregator(&self, symbol: &str, unit: &str) -> Option<&Mutex<MovingAggregator>> {
+        if let Some(map) = self.aggregators.get(symbol) {
+            map.get(unit)
+        } else {
+            None
+        }
+    }
+
+    pub fn retention(&self) -> Option<Retention> {
+        self.retention
+    }
+
+    pub fn sequence(&self) -> i64 {
+        *self.sequence.lock().unwrap()
+    }
+
+    pub fn set_sequence(&self, value: i64) {
+        *self.sequence.lock().unwrap() = value;
+    }
+
     pub fn next_message_id(&self) -> i64 {
         let mut guard = self.sequence.lock().unwrap();
         *guard += 1;
@@ -381,7 +704,29 @@ impl AssetContext {
             debug!("add_trade: {} - {:?}", symbol, &trade.clone());
         }
         // TODO: message might not be sequential, make sure message are in time sort
-        trades.push_front(Arc::new(RwLock::new(trade)));
+        let time = trade.time;
+        let message_id = trade.message_id;
+        let trade = Arc::new(RwLock::new(trade));
+        trades.push_front(Arc::clone(&trade));
+
+        let mut index = self.trade_index.write().unwrap();
+        index.insert(message_id, trade);
+
+        // evict finalized trades out of retention
+        if let Some(retention) = self.retention {
+            let min_time = time - retention.window;
+            while let Some(lock) = trades.back() {
+                let (message_id, evictable) = {
+                    let trade = lock.read().unwrap();
+                    (trade.message_id, trade.time < min_time && trade.finalized())
+                };
+                if !evictable {
+                    break;
+                }
+                trades.pop_back();
+                index.remove(&message_id);
+            }
+        }
     }
 
     pub fn symbol_trades(&self, symbol: &str) -> Option<&RwLock<LinkedList<LockTradeInfo>>> {
@@ -389,28 +734,13 @@ impl AssetContext {
     }
 
     pub fn search_trade(&self, message_id: i64) -> Option<LockTradeInfo> {
-        let trades = Arc::clone(&self.trades);
-        for (_symbol, lock) in trades.as_ref() {
-            let reader = lock.read().unwrap();
-            let result = reader
-                .iter()
-                .find(|s| s.read().unwrap().message_id == message_id);
-
-            if let Some(value) = result {
-                return Some(Arc::clone(value));
-            }
-        }
-        None
+        let index = self.trade_index.read().unwrap();
+        index.get(&message_id).map(Arc::clone)
     }
 
     pub fn find_trade(&self, symbol: &str, message_id: i64) -> Option<LockTradeInfo> {
-        let trades_lock = self.symbol_trades(symbol).unwrap();
-        let trades = trades_lock.read().unwrap();
-        let trade = trades
-            .iter()
-            .find(|s| s.read().unwrap().message_id == message_id)
-            .unwrap();
-        Some(Arc::clone(trade))
+        self.search_trade(message_id)
+            .filter(|trade| trade.read().unwrap().id == symbol)
     }
 
     // check if all trades added into ticker point
@@ -454,11 +784,54 @@ impl AssetContext {
                 order.created_rival_price,
                 order.created_volume,
             );
+            self.publish_order(order.created_time, &order);
             writer.push_front(order);
             true
         }
     }
 
+    // Add hypothetical order, each rule keeps at most one running order per symbol,
+    // running order of the same rule on rival symbol is written off
+    pub fn add_shadow_order(&self, order: Order) -> bool {
+        let rule = order.decision.as_ref().and_then(|d| d.rule_name());
+        let rival_symbol = self.find_rival_symbol(&order.symbol);
+        let mut writer = self.shadow_orders.write().unwrap();
+
+        let running = |o: &Order| {
+            matches!(o.status, OrderStatus::Init | OrderStatus::Accepted)
+                && o.decision.as_ref().and_then(|d| d.rule_name()) == rule
+        };
+        if writer
+            .iter()
+            .any(|o| running(o) && o.symbol == order.symbol)
+        {
+            return false;
+        }
+
+        if let Some(rival_symbol) = rival_symbol {
+            let constraint_id = format!("S{}", &order.id);
+            for o in writer
+                .iter_mut()
+                .filter(|o| running(o) && o.symbol == rival_symbol)
+            {
+                o.write_off_time = Some(order.created_time);
+                o.status = OrderStatus::WriteOff;
+                o.constraint_id = Some(constraint_id.clone());
+            }
+        }
+
+        debug!(
+            "new shadow order: [{}] {:<12} rule: {:?} price: {:<7}, volume: {}",
+            &order.symbol,
+            format!("{:?}", &order.audit),
+            rule,
+            order.created_price,
+            order.created_volume,
+        );
+        writer.push_front(order);
+        true
+    }
+
     pub fn find_rival_symbol(&self, symbol: &str) -> Option<String> {
         let config = Arc::clone(&self.config);
         if let Some(ticker_group) = config
@@ -505,11 +878,13 @@ impl AssetContext {
                 .count();
 
             if count == 2 {
+                let time = order.accepted_time.unwrap_or(order.created_time);
                 {
                     for o in writer.iter_mut().filter(|o| o.id == rival_order.id) {
                         o.write_off_time = order.accepted_time; // FIXME: accepted time to current time
                         o.status = status.clone();
                         o.constraint_id = Some(constraint_id.clone());
+                        self.publish_order(time, o);
                     }
                 }
                 {
@@ -517,6 +892,7 @@ impl AssetContext {
                         o.write_off_time = order.accepted_time; // FIXME: accepted time to current time
                         o.status = status.clone();
                         o.constraint_id = Some(constraint_id.clone());
+                        self.publish_order(time, o);
                     }
                 }
             }
@@ -538,12 +914,13 @@ impl AssetContext {
         }
     }
 
-    pub fn find_last_flash_order(&self, symbol: &str) -> Option<Order> {
+    pub fn find_last_audit_order(&self, symbol: &str, audit: &AuditState) -> Option<Order> {
         let lock = &self.orders;
         let reader = lock.read().unwrap();
         if let Some(order) = reader
             .iter()
             .filter(|o| o.symbol == symbol)
+            .filter(|o| &o.audit == audit)
             .filter(|o| {
                 matches!(
                     o.status,
@@ -603,6 +980,29 @@ impl AssetContext {
             list_writer.clear();
             debug!("Clean up cached data for trades: {}", id)
         });
+        self.trade_index.write().unwrap().clear();
+        self.price_marks.iter().for_each(|(id, lock)| {
+            lock.write().unwrap().clear();
+            debug!("Clean up cached data for price marks: {}", id)
+        });
+        self.post_tickers.write().unwrap().clear();
+        self.ticker_volumes.values().for_each(|lock| {
+            *lock.lock().unwrap() = 0;
+        });
+        self.indicators.iter().for_each(|(id, map)| {
+            map.iter().for_each(|(unit, lock)| {
+                let mut engine = lock.lock().unwrap();
+                *engine = IndicatorEngine::new(&self.config.indicators, &engine.unit());
+                debug!("Clean up cached data for indicator: {:?} of {}", unit, id)
+            });
+        });
+        self.aggregators.iter().for_each(|(id, map)| {
+            map.iter().for_each(|(unit, lock)| {
+                let mut aggregator = lock.lock().unwrap();
+                *aggregator = MovingAggregator::new(&aggregator.unit());
+                debug!("Clean up cached data for aggregator: {:?} of {}", unit, id)
+            });
+        });
         Ok(())
     }
 }
@@ -698,26 +1098,244 @@ pub struct AppConfig {
     pub replay: ReplayBehavior,
     pub units: Vec<TimeUnit>,
     pub tickers: TickerList,
+    #[serde(default)]
+    pub indicators: IndicatorConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    // switches of running process, given by command line instead of config
+    #[serde(skip)]
+    pub switches: Switches,
     #[serde(default = "empty_map", skip_serializing, skip_deserializing)]
     runtime: Arc<RwLock<HashMap<String, String>>>,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Switches {
+    // path of loaded config file
+    pub config_file: String,
+    // persist and index data in background
+    pub async_process: bool,
+    // clean existing data before operation
+    pub truncate: bool,
+    // print meta of trades in time range, (start, end) in milliseconds
+    pub meta_window: Option<(i64, i64)>,
+    // layers of loaded config, kept for reload
+    pub profile: Option<String>,
+    pub overrides: Vec<(String, Value)>,
+}
+
+impl Switches {
+    // Files of config layers, base file and profile overlay
+    pub fn files(&self) -> Vec<String> {
+        let mut files = vec![self.config_file.clone()];
+        files.extend(
+            self.profile
+                .iter()
+                .map(|profile| profile_file(&self.config_file, profile)),
+        );
+        files
+    }
+}
+
+fn modified_times(files: &[String]) -> Vec<Option<std::time::SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
 fn empty_map() -> Arc<RwLock<HashMap<String, String>>> {
     Arc::new(RwLock::new(HashMap::new()))
 }
 
 impl AppConfig {
     pub fn load(file: &str) -> Result<Self> {
-        let settings = Config::builder()
+        Self::load_layered(file, None, &[])
+    }
+
+    // Load config in layers, later ones win: base file, profile overlay (ex: config.prod.yaml of prod),
+    // SMINER_* environment variables, then overrides by path (ex: --set trade.enabled=false)
+    pub fn load_layered(
+        file: &str,
+        profile: Option<&str>,
+        overrides: &[(String, Value)],
+    ) -> Result<Self> {
+        let mut builder = Config::builder()
             .add_source(config::File::with_name(file))
             .set_default("replay.outputs.baseFolder", "tmp")?
-            .set_default("dataSource.mongodb.target", "yahoo")?
-            .build()?;
+            .set_default("dataSource.mongodb.target", "yahoo")?;
+        let profile = profile
+            .map(|profile| profile.to_string())
+            .or_else(|| env::var(ENV_PROFILE).ok());
+        if let Some(profile) = &profile {
+            builder = builder.add_source(config::File::with_name(&profile_file(file, profile)));
+        }
+        let config: Self = builder.build()?.try_deserialize::<Self>()?;
 
-        let config: Self = settings.try_deserialize::<Self>()?;
+        let mut values = env_values(&serde_json::to_value(&config)?, env::vars());
+        values.extend_from_slice(overrides);
+        let mut config = config.replace_values(&values)?;
+        for warning in config.validate()? {
+            warn!("{}", warning);
+        }
+        config.switches.config_file = file.to_string();
+        config.switches.profile = profile;
+        config.switches.overrides = overrides.to_vec();
         Ok(config)
     }
 
+    // Resolve unit references and check values, returns warnings of rules which never fire
+    // All errors are reported at once by path of value, ex: trade.flash.rules.0.trends.1
+    pub fn validate(&mut self) -> Result<Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+        let mut warnings: Vec<String> = Vec::new();
+
+        self.resolve_references(&mut errors);
+
+        let mut names: HashSet<&str> = HashSet::new();
+        for (index, unit) in self.units.iter().enumerate() {
+            if !names.insert(&unit.name) {
+                errors.push(format!("units.{}: duplicated unit {}", index, unit.name));
+            }
+            if unit.duration <= 0 {
+                errors.push(format!("units.{}: duration must be positive", index));
+            }
+        }
+
+        let symbols = self.symbols();
+        for (index, option) in self.trade.options.iter().enumerate() {
+            let path = format!("trade.options.{}", index);
+            for symbol in option.symbols.iter().filter(|s| !symbols.contains(s)) {
+                errors.push(format!("{}.symbols: {} not found in tickers", path, symbol));
+            }
+            if option.max_order_amount == 0 {
+                errors.push(format!("{}.maxOrderAmount: must be positive", path));
+            }
+            check_rate(
+                &mut errors,
+                &path,
+                "profitTakeRate",
+                option.profit_take_rate,
+            );
+            check_rate(
+                &mut errors,
+                &path,
+                "earlyClearRate",
+                option.early_clear_rate,
+            );
+        }
+
+        for (name, mode) in self.trade.modes() {
+            let path = format!("trade.{}", mode_path(name));
+            if let Some(rate) = mode.loss_margin_rate {
+                check_rate(&mut errors, &path, "lossMarginRate", rate);
+            }
+            if mode.cooldown.iter().any(|&cooldown| cooldown < 0) {
+                errors.push(format!("{}.cooldown: must not be negative", path));
+            }
+            if mode.action(name).is_none() {
+                warnings.push(format!("{}: mode without action is never audited", path));
+            } else if mode.role(name).is_none() {
+                warnings.push(format!("{}: mode without role is never audited", path));
+            }
+            for (index, rule) in mode.rules.iter().enumerate() {
+                let path = format!("{}.rules.{}", path, index);
+                rule.check(self, &path, &mut errors, &mut warnings);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(warnings)
+        } else {
+            Err(errors.join("\n").into())
+        }
+    }
+
+    // Resolve unit references of criteria to spans of moving units
+    fn resolve_references(&mut self, errors: &mut Vec<String>) {
+        let units = self.units.clone();
+        let mut modes: Vec<(&str, &mut AuditMode)> = vec![
+            ("flash", &mut self.trade.flash),
+            ("slug", &mut self.trade.slug),
+            ("revert", &mut self.trade.revert),
+        ];
+        modes.extend(
+            self.trade
+                .modes
+                .iter_mut()
+                .map(|(name, mode)| (name.as_str(), mode)),
+        );
+
+        for (name, mode) in modes {
+            for (index, rule) in mode.rules.iter_mut().enumerate() {
+                let path = format!("trade.{}.rules.{}", mode_path(name), index);
+                let mut resolve = |kind: &str,
+                                   from: Option<&str>,
+                                   to: &str,
+                                   pick: &dyn Fn(Vec<UnitRef>) -> Option<UnitRef>|
+                 -> UnitRef {
+                    match UnitRef::candidates(&units, from, to) {
+                        Ok(spans) => pick(spans).unwrap_or_else(|| {
+                            errors.push(format!(
+                                "{}.{}: no moving unit of the same prefix divides and covers {}",
+                                path, kind, to
+                            ));
+                            UnitRef::default()
+                        }),
+                        Err(e) => {
+                            errors.push(format!("{}.{}: {}", path, kind, e));
+                            UnitRef::default()
+                        }
+                    }
+                };
+
+                let widest = |spans: Vec<UnitRef>| spans.into_iter().last();
+                let finest = |spans: Vec<UnitRef>| spans.into_iter().next();
+                for (i, trend) in rule.trends.iter_mut().enumerate() {
+                    let kind = format!("trends.{}", i);
+                    trend.span = resolve(&kind, trend.from.as_deref(), &trend.to, &widest);
+                }
+                for (i, deviation) in rule.deviations.iter_mut().enumerate() {
+                    let kind = format!("deviations.{}", i);
+                    deviation.span =
+                        resolve(&kind, deviation.from.as_deref(), &deviation.to, &finest);
+                }
+                for (i, oscillation) in rule.oscillations.iter_mut().enumerate() {
+                    let kind = format!("oscillations.{}", i);
+                    oscillation.span =
+                        resolve(&kind, oscillation.from.as_deref(), &oscillation.to, &finest);
+                }
+                for (i, lower) in rule.lowers.iter_mut().enumerate() {
+                    // spans on unit of configured duration
+                    let duration = lower.duration;
+                    let on_unit = |spans: Vec<UnitRef>| {
+                        spans.into_iter().find(|span| {
+                            units
+                                .iter()
+                                .any(|u| u.name == span.unit && u.duration as u32 == duration)
+                        })
+                    };
+                    let kind = format!("lowers.{}", i);
+                    lower.span = resolve(&kind, lower.from.as_deref(), &lower.to, &on_unit);
+                    lower.compare_span = resolve(&kind, None, &lower.compare_to, &on_unit);
+                }
+            }
+        }
+    }
+
+    // Copy with its own runtime values, changes are not shared with the original
+    pub fn isolate(&self) -> Self {
+        let runtime = self.runtime.read().unwrap().clone();
+        Self {
+            runtime: Arc::new(RwLock::new(runtime)),
+            ..self.clone()
+        }
+    }
+
     pub fn extra_put(&self, key: &str, value: &str) {
         if let Ok(mut lock) = self.runtime.write() {
             lock.insert(key.to_string(), value.to_string());
@@ -758,8 +1376,31 @@ impl AppConfig {
         self.time_units().into_iter().find(|u| u.name == name)
     }
 
+    // Cached data kept, longest window of time units with margin, and activity of tick/volume bars
+    pub fn retention_policy(&self) -> Option<Retention> {
+        if !self.retention.enabled {
+            return None;
+        }
+        let window = self.units.iter().map(|u| u.window()).max().unwrap_or(0);
+        Some(Retention {
+            window: window + self.retention.margin * 1000,
+            ticks: self
+                .units
+                .iter()
+                .map(|u| u.activity(BarType::Tick))
+                .max()
+                .unwrap_or(0),
+            volume: self
+                .units
+                .iter()
+                .map(|u| u.activity(BarType::Volume))
+                .max()
+                .unwrap_or(0),
+        })
+    }
+
     pub fn async_process(&self) -> bool {
-        self.extra_present(KEY_EXTRA_PRCOESS_IN_ASYNC)
+        self.switches.async_process
     }
 
     pub fn sync_mongo_enabled(&self) -> bool {
@@ -771,7 +1412,132 @@ impl AppConfig {
     }
 
     pub fn truncat_enabled(&self) -> bool {
-        self.extra_present(KEY_EXTRA_ENABLE_DATA_TRUNCAT)
+        self.switches.truncate
+    }
+
+    // Create a copy with values replaced by path, ex: trade.options.*.profitTakeRate
+    // the copy comes with empty runtime values
+    pub fn with_values(&self, values: &[(String, Value)]) -> Result<Self> {
+        let mut config = self.replace_values(values)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn replace_values(&self, values: &[(String, Value)]) -> Result<Self> {
+        let mut root = serde_json::to_value(self)?;
+        for (path, value) in values {
+            let keys: Vec<&str> = path.split('.').collect();
+            if replace_value(&mut root, &keys, value) == 0 {
+                return Err(format!("No config value found by path: {}", path).into());
+            }
+        }
+        let config: Self = serde_json::from_value(root).map_err(|e| {
+            let paths: Vec<&str> = values.iter().map(|(path, _)| path.as_str()).collect();
+            format!("Invalid config values of {:?}, {}", paths, e)
+        })?;
+        Ok(config)
+    }
+
+    // SHA1 of serialized content, runtime values excluded
+    pub fn sha(&self) -> Result<String> {
+        let mut hasher = Sha1::new();
+        hasher.update(serde_json::to_vec(self)?);
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+// Override of config value, ex: trade.options.*.profitTakeRate=0.01
+// Value is taken as JSON, or as string when not valid JSON
+pub fn parse_override(text: &str) -> Result<(String, Value)> {
+    match text.split_once('=') {
+        Some((path, value)) if !path.is_empty() => Ok((path.to_string(), parse_value(value))),
+        _ => Err(format!("Invalid override: {}, expected path=value", text).into()),
+    }
+}
+
+fn parse_value(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+// Overlay next to the base file, ex: config.prod.yaml of config.yaml
+fn profile_file(file: &str, profile: &str) -> String {
+    let path = Path::new(file);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(file);
+    let name = match path.extension().and_then(|s| s.to_str()) {
+        Some(extension) => format!("{}.{}.{}", stem, profile, extension),
+        None => format!("{}.{}", stem, profile),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+// Overrides of SMINER_* environment variables, path segments are separated by double underscores
+// and matched ignoring case and underscores, ex: SMINER_TRADE__FLASH__LOSS_MARGIN_RATE
+// Variables not matching any config value are skipped, ex: SMINER_HOME
+pub fn env_values(
+    root: &Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Vec<(String, Value)> {
+    let mut values: Vec<(String, Value)> = Vec::new();
+    for (name, value) in vars {
+        if name == ENV_PROFILE {
+            continue;
+        }
+        if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+            let segments: Vec<&str> = key.split("__").collect();
+            match env_path(root, &segments) {
+                Some(path) => values.push((path.join("."), parse_value(&value))),
+                None => warn!("Skip environment without config value: {}", name),
+            }
+        }
+    }
+    // applied in stable order
+    values.sort_by(|a, b| a.0.cmp(&b.0));
+    values
+}
+
+fn env_path(node: &Value, segments: &[&str]) -> Option<Vec<String>> {
+    let normalize = |s: &str| s.replace('_', "").to_lowercase();
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => return Some(Vec::new()),
+    };
+    let (key, child) = match node {
+        Value::Object(map) => map
+            .iter()
+            .find(|(key, _)| normalize(key) == normalize(segment))
+            .map(|(key, child)| (key.to_string(), child))?,
+        Value::Array(list) => {
+            let index = segment.parse::<usize>().ok()?;
+            (index.to_string(), list.get(index)?)
+        }
+        _ => return None,
+    };
+    let mut path = vec![key];
+    path.extend(env_path(child, rest)?);
+    Some(path)
+}
+
+fn replace_value(node: &mut Value, keys: &[&str], value: &Value) -> usize {
+    if keys.is_empty() {
+        *node = value.clone();
+        return 1;
+    }
+
+    let (key, rest) = (keys[0], &keys[1..]);
+    match node {
+        Value::Object(map) => match map.get_mut(key) {
+            Some(child) => replace_value(child, rest, value),
+            None => 0,
+        },
+        Value::Array(list) if key == "*" => list
+            .iter_mut()
+            .map(|child| replace_value(child, rest, value))
+            .sum(),
+        Value::Array(list) => match key.parse::<usize>().ok().and_then(|i| list.get_mut(i)) {
+            Some(child) => replace_value(child, rest, value),
+            None => 0,
+        },
+        _ => 0,
     }
 }
 
@@ -780,6 +1546,18 @@ pub struct DataSource {
     pub mongodb: DataSourceInfo,
     pub elasticsearch: DataSourceInfo,
     pub grafana: DataSourceInfo,
+    // embedded database file, no service required
+    #[serde(default = "default_sqlite")]
+    pub sqlite: DataSourceInfo,
+}
+
+fn default_sqlite() -> DataSourceInfo {
+    DataSourceInfo {
+        uri: "tmp/sminer.db".to_string(),
+        enabled: false,
+        target: None,
+        auth: None,
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -800,6 +1578,112 @@ pub struct YahooFinance {
     pub uri: String,
 }
 
+// Cached data kept for aggregation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    // milliseconds of tickers and trades
+    pub window: i64,
+    // latest tickers of tick bars
+    pub ticks: i64,
+    // traded volume of volume bars
+    pub volume: i64,
+}
+
+// Snapshot of consume session, restored on restart in the same day
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    pub enabled: bool,
+    pub file: String,
+    // seconds between snapshots
+    pub interval: u64,
+    // fill tickers after snapshot from ticker source
+    pub backfill: bool,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: "tmp/session.json".to_string(),
+            interval: 60,
+            backfill: false,
+        }
+    }
+}
+
+// Reload of trade rules while consuming, on SIGHUP or modification of config files
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ReloadConfig {
+    pub enabled: bool,
+    // seconds between checks of config file modification
+    pub interval: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: 5,
+        }
+    }
+}
+
+// Retention of cached tickers and trades, disabled unless configured
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    // seconds kept beyond the longest window of time units
+    pub margin: i64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            margin: 60,
+        }
+    }
+}
+
+// Parameters of technical indicators, in bars of time unit duration
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct IndicatorConfig {
+    pub enabled: bool,
+    pub ema: usize,
+    pub rsi: usize,
+    #[serde(rename = "macdFast")]
+    pub macd_fast: usize,
+    #[serde(rename = "macdSlow")]
+    pub macd_slow: usize,
+    #[serde(rename = "macdSignal")]
+    pub macd_signal: usize,
+    pub atr: usize,
+    pub bollinger: usize,
+    // multiplier of standard deviation
+    #[serde(rename = "bollingerWidth")]
+    pub bollinger_width: f64,
+}
+
+impl Default for IndicatorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ema: 20,
+            rsi: 14,
+            macd_fast: 12,
+            macd_slow: 26,
+            macd_signal: 9,
+            atr: 14,
+            bollinger: 20,
+            bollinger_width: 2.0,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TradeAudit {
     pub enabled: bool,
@@ -808,9 +1692,34 @@ pub struct TradeAudit {
     pub slug: AuditMode,
     // used to prevent loss, check downward trend while profit still positive
     pub revert: AuditMode,
+    // additional modes, ex: a dedicated exit mode with `role: Exit`
+    #[serde(default)]
+    pub modes: BTreeMap<String, AuditMode>,
 }
 
 impl TradeAudit {
+    // All audit modes ordered by priority (higher first), later declared mode first on tie
+    pub fn modes(&self) -> Vec<(&str, &AuditMode)> {
+        let mut modes: Vec<(&str, &AuditMode)> = vec![
+            ("flash", &self.flash),
+            ("slug", &self.slug),
+            ("revert", &self.revert),
+        ];
+        modes.extend(self.modes.iter().map(|(name, mode)| (name.as_str(), mode)));
+        modes.reverse();
+        modes.sort_by_key(|(_, mode)| -mode.priority);
+        modes
+    }
+
+    pub fn find_mode(&self, name: &str) -> Option<&AuditMode> {
+        match name {
+            "flash" => Some(&self.flash),
+            "slug" => Some(&self.slug),
+            "revert" => Some(&self.revert),
+            _ => self.modes.get(name),
+        }
+    }
+
     pub fn get_option(&self, symbol: &str) -> AuditOption {
         let option = self
             .options
@@ -851,9 +1760,49 @@ pub struct AuditMode {
     // loss margin on trend downward
     #[serde(rename = "lossMarginRate")]
     pub loss_margin_rate: Option<f32>,
+    // state of order placed when accepted, Flash/Slug place orders, EarlyClear clears running ones
+    #[serde(default)]
+    pub action: Option<AuditState>,
+    // Entry modes audit every trade, Exit modes audit trades with running rival order
+    #[serde(default)]
+    pub role: Option<AuditRole>,
+    // modes with higher priority are audited first, the first accepted one wins
+    #[serde(default)]
+    pub priority: i32,
+    // seconds to wait since last order of the symbol
+    #[serde(default)]
+    pub cooldown: Option<i64>,
     pub rules: Vec<AuditRule>,
 }
 
+impl AuditMode {
+    // Action of mode, builtin modes have default action, additional modes without action are ignored
+    pub fn action(&self, name: &str) -> Option<AuditState> {
+        self.action.clone().or(match name {
+            "flash" => Some(AuditState::Flash),
+            "slug" => Some(AuditState::Slug),
+            "revert" => Some(AuditState::EarlyClear),
+            _ => None,
+        })
+    }
+
+    // Role of mode, defaults by action: Flash/Slug enter, EarlyClear exits
+    pub fn role(&self, name: &str) -> Option<AuditRole> {
+        self.role.clone().or(match self.action(name) {
+            Some(AuditState::Flash | AuditState::Slug) => Some(AuditRole::Entry),
+            Some(AuditState::EarlyClear) => Some(AuditRole::Exit),
+            _ => None,
+        })
+    }
+
+    pub fn cooldown(&self, name: &str) -> Option<i64> {
+        self.cooldown.or(match name {
+            "flash" => Some(30),
+            _ => None,
+        })
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AuditRule {
     #[serde(default = "default_trends")]
@@ -864,11 +1813,124 @@ pub struct AuditRule {
     pub oscillations: Vec<OscillationCriteria>,
     #[serde(default = "default_lowers")]
     pub lowers: Vec<LowerCriteria>,
+    #[serde(default = "default_indicators")]
+    pub indicators: Vec<IndicatorCriteria>,
     #[serde(default = "default_evaluation")]
     pub evaluation: bool,
     pub mode: AuditRuleType,
     #[serde(default = "default_symbols")]
     pub symbols: Vec<String>,
+    // rules with higher priority are validated first
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl AuditRule {
+    fn check(
+        &self,
+        config: &AppConfig,
+        path: &str,
+        errors: &mut Vec<String>,
+        warnings: &mut Vec<String>,
+    ) {
+        let symbols = config.symbols();
+        if !self.symbols.is_empty() && self.symbols.iter().all(|s| !symbols.contains(s)) {
+            warnings.push(format!(
+                "{}: never fires, symbols {:?} not found in tickers",
+                path, self.symbols
+            ));
+        }
+
+        for (index, trend) in self.trends.iter().enumerate() {
+            for (name, value) in [("up", &trend.up), ("down", &trend.down)] {
+                if let Some(value) = value.as_deref().filter(|v| !valid_compare(v)) {
+                    errors.push(format!(
+                        "{}.trends.{}.{}: invalid count {}, ex: 1, 1+ or 3-",
+                        path, index, name, value
+                    ));
+                }
+            }
+            // slopes of the same span in both directions
+            if let Some(other) = self.trends[..index]
+                .iter()
+                .find(|t| t.span == trend.span && t.trend != trend.trend)
+            {
+                warnings.push(format!(
+                    "{}: never fires, {} required both {:?} and {:?}",
+                    path, trend.to, other.trend, trend.trend
+                ));
+            }
+        }
+        for (index, deviation) in self.deviations.iter().enumerate() {
+            let path = format!("{}.deviations.{}", path, index);
+            check_rate(errors, &path, "value", deviation.value);
+        }
+        for (index, oscillation) in self.oscillations.iter().enumerate() {
+            let path = format!("{}.oscillations.{}", path, index);
+            check_rate(errors, &path, "value", oscillation.value);
+        }
+
+        for (index, indicator) in self.indicators.iter().enumerate() {
+            let path = format!("{}.indicators.{}", path, index);
+            if !config.units.iter().any(|u| u.name == indicator.unit) {
+                errors.push(format!("{}.unit: unit {} not found", path, indicator.unit));
+            }
+            match indicator_size(&indicator.name) {
+                Some(size) if indicator.index >= size => errors.push(format!(
+                    "{}.index: {} has {} values",
+                    path, indicator.name, size
+                )),
+                Some(_) => {}
+                None => errors.push(format!(
+                    "{}.name: unknown indicator {}",
+                    path, indicator.name
+                )),
+            }
+            if !config.indicators.enabled {
+                warnings.push(format!("{}: never fires, indicators disabled", path));
+            }
+            if let (Some(min), Some(max)) = (indicator.min, indicator.max) {
+                if min > max {
+                    warnings.push(format!("{}: never fires, min {} > max {}", path, min, max));
+                }
+            }
+        }
+    }
+}
+
+fn mode_path(name: &str) -> String {
+    match name {
+        "flash" | "slug" | "revert" => name.to_string(),
+        _ => format!("modes.{}", name),
+    }
+}
+
+// Rates are ratio of price, ex: 0.005 for 0.5%
+fn check_rate(errors: &mut Vec<String>, path: &str, name: &str, rate: f32) {
+    if !(0.0..1.0).contains(&rate) {
+        errors.push(format!(
+            "{}.{}: rate {} out of range [0, 1)",
+            path, name, rate
+        ));
+    }
+}
+
+// Count of value_compare, ex: 10, 10+ or 10-
+fn valid_compare(value: &str) -> bool {
+    value
+        .strip_suffix(|c| c == '+' || c == '-')
+        .unwrap_or(value)
+        .parse::<u32>()
+        .is_ok()
+}
+
+// Value size of indicator
+fn indicator_size(name: &str) -> Option<usize> {
+    match name {
+        INDICATOR_EMA | INDICATOR_RSI | INDICATOR_ATR | INDICATOR_VWAP => Some(1),
+        INDICATOR_MACD | INDICATOR_BOLLINGER => Some(3),
+        _ => None,
+    }
 }
 
 fn default_trends() -> Vec<TrendCriteria> {
@@ -883,6 +1945,9 @@ fn default_oscillations() -> Vec<OscillationCriteria> {
 fn default_lowers() -> Vec<LowerCriteria> {
     Vec::new()
 }
+fn default_indicators() -> Vec<IndicatorCriteria> {
+    Vec::new()
+}
 fn default_evaluation() -> bool {
     false
 }
@@ -890,6 +1955,12 @@ fn default_symbols() -> Vec<String> {
     Vec::new()
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum AuditRole {
+    Entry,
+    Exit,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum AuditRuleType {
     Permit,
@@ -903,6 +1974,9 @@ pub struct TrendCriteria {
     pub trend: Trend,
     pub up: Option<String>,
     pub down: Option<String>,
+    // widest unit dividing the span, resolved on config load
+    #[serde(skip)]
+    pub span: UnitRef,
 }
 
 fn value_compare(config: Option<String>, target: u32) -> bool {
@@ -951,6 +2025,9 @@ pub struct DeviationCriteria {
     pub from: Option<String>,
     pub to: String,
     pub value: f32,
+    // finest unit dividing the span, resolved on config load
+    #[serde(skip)]
+    pub span: UnitRef,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -958,6 +2035,9 @@ pub struct OscillationCriteria {
     pub from: Option<String>,
     pub to: String,
     pub value: f32,
+    // finest unit dividing the span, resolved on config load
+    #[serde(skip)]
+    pub span: UnitRef,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -967,6 +2047,97 @@ pub struct LowerCriteria {
     #[serde(rename = "compareTo")]
     pub compare_to: String,
     pub duration: u32,
+    // spans on unit of duration, resolved on config load
+    #[serde(skip)]
+    pub span: UnitRef,
+    #[serde(skip)]
+    pub compare_span: UnitRef,
+}
+
+// Span of periods on a moving unit referenced by criteria, ex: m0070 => [0, 7) of m0010
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnitRef {
+    pub unit: String,
+    // period offsets, from inclusive, to exclusive
+    pub from: usize,
+    pub to: usize,
+}
+
+impl UnitRef {
+    // Spans on moving units with the same prefix which divide both ends and cover the span, finest unit first
+    pub fn candidates(units: &[TimeUnit], from: Option<&str>, to: &str) -> Result<Vec<UnitRef>> {
+        let (prefix, span_to) = parse_reference(to)?;
+        let span_from = match from {
+            Some(from) => {
+                let (from_prefix, span_from) = parse_reference(from)?;
+                if from_prefix != prefix {
+                    return Err(format!(
+                        "Unit reference from: {} and to: {} must have the same prefix",
+                        from, to
+                    )
+                    .into());
+                }
+                span_from
+            }
+            None => 0,
+        };
+        if span_from >= span_to {
+            return Err(format!(
+                "Unit reference from: {} must be less than to: {}",
+                from.unwrap_or_default(),
+                to
+            )
+            .into());
+        }
+
+        let mut candidates: Vec<(i64, UnitRef)> = units
+            .iter()
+            .filter(|u| u.is_moving() && u.duration > 0)
+            .filter(|u| u.name.trim_end_matches(|c: char| c.is_ascii_digit()) == prefix)
+            .filter(|u| span_from % u.duration as i64 == 0 && span_to % u.duration as i64 == 0)
+            .filter(|u| span_to / u.duration as i64 <= u.period as i64)
+            .map(|u| {
+                (
+                    u.duration as i64,
+                    UnitRef {
+                        unit: u.name.clone(),
+                        from: (span_from / u.duration as i64) as usize,
+                        to: (span_to / u.duration as i64) as usize,
+                    },
+                )
+            })
+            .collect();
+        candidates.sort_by_key(|(duration, _)| *duration);
+        Ok(candidates.into_iter().map(|(_, span)| span).collect())
+    }
+}
+
+// Split reference to unit prefix and span, ex: m0070 => (m, 70)
+fn parse_reference(name: &str) -> Result<(&str, i64)> {
+    let span = name.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    let prefix = &name[..name.len() - span.len()];
+    match span.parse::<i64>() {
+        Ok(value) if !prefix.is_empty() && span.chars().all(|c| c.is_ascii_digit()) => {
+            Ok((prefix, value))
+        }
+        _ => Err(format!(
+            "Invalid unit reference: {}, expected unit prefix and span, ex: m0070",
+            name
+        )
+        .into()),
+    }
+}
+
+// Range of technical indicator, ex: { unit: m0060, name: rsi, max: 30 }
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IndicatorCriteria {
+    pub unit: String,
+    pub name: String,
+    // value index of indicator, ex: 1 for upper band of bollinger
+    #[serde(default)]
+    pub index: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -996,9 +2167,11 @@ pub struct Outputs {
     pub base_folder: String,
     pub file: OutputType,
     pub elasticsearch: OutputType,
+    #[serde(default)]
+    pub parquet: OutputType,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct OutputType {
     pub enabled: bool,
 }
@@ -1018,3 +2191,83 @@ pub struct TickerGroup {
 pub struct Symbol {
     pub id: String,
 }
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SweepSpec {
+    pub parameters: Vec<SweepParameter>,
+}
+
+impl SweepSpec {
+    pub fn load(file: &str) -> Result<Self> {
+        let settings = Config::builder()
+            .add_source(config::File::with_name(file))
+            .build()?;
+
+        let spec: Self = settings.try_deserialize::<Self>()?;
+        Ok(spec)
+    }
+
+    // Cartesian product of all parameter candidates
+    pub fn variants(&self) -> Result<Vec<Vec<(String, Value)>>> {
+        let mut variants: Vec<Vec<(String, Value)>> = vec![Vec::new()];
+        for parameter in &self.parameters {
+            let candidates = parameter.candidates()?;
+            variants = variants
+                .iter()
+                .flat_map(|variant| {
+                    candidates.iter().map(move |candidate| {
+                        let mut next = variant.clone();
+                        next.push((parameter.path.clone(), candidate.clone()));
+                        next
+                    })
+                })
+                .collect();
+        }
+        Ok(variants)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SweepParameter {
+    // dot separated path of config, "*" for all items of list, ex: trade.options.*.profitTakeRate
+    pub path: String,
+    #[serde(default = "default_sweep_values")]
+    pub values: Vec<Value>,
+    pub range: Option<SweepRange>,
+}
+
+fn default_sweep_values() -> Vec<Value> {
+    Vec::new()
+}
+
+impl SweepParameter {
+    pub fn candidates(&self) -> Result<Vec<Value>> {
+        let mut candidates = self.values.clone();
+        if let Some(range) = &self.range {
+            if range.step <= 0.0 || range.from > range.to {
+                return Err(format!("Invalid sweep range of {}: {:?}", self.path, range).into());
+            }
+            let mut index = 0;
+            loop {
+                // round to avoid accumulated float error, ex: 0.30000000000000004
+                let value = ((range.from + range.step * index as f64) * 1e8).round() / 1e8;
+                if value > range.to + range.step * 1e-6 {
+                    break;
+                }
+                candidates.push(Value::from(value));
+                index += 1;
+            }
+        }
+        if candidates.is_empty() {
+            return Err(format!("No sweep value defined for {}", self.path).into());
+        }
+        Ok(candidates)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SweepRange {
+    pub from: f64,
+    pub to: f64,
+    pub step: f64,
+}
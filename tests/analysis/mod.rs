@@ -1,18 +1,28 @@
 use log::{error, info, warn};
+use proptest::prelude::*;
 use sminer::{
-    analysis::{replay, trade::rebound_at, ReplayMode},
+    analysis::{
+        indicator::IndicatorEngine,
+        optimize::{take_file_date, walk_forward_windows},
+        replay,
+        rolling::{calculate_slope, MovingAggregator},
+        trade::rebound_at,
+        ReplayMode,
+    },
     init_log,
     persist::es::{take_index_time, ticker_index_name},
     vo::{
-        biz::{MarketHoursType, Protfolio, QuoteType, TimeUnit, TradeInfo},
-        core::{AppConfig, AppContext, KEY_EXTRA_PRCOESS_IN_ASYNC},
+        biz::{BarType, MarketHoursType, Protfolio, QuoteType, Ticker, TimeUnit, TradeInfo},
+        core::{AppConfig, AppContext, IndicatorConfig, Retention},
     },
     Result,
 };
 use std::{
+    collections::LinkedList,
     fs::File,
     io::{BufRead, BufReader},
     path::Path,
+    sync::{Arc, Mutex, RwLock},
 };
 use tokio::runtime::Runtime;
 
@@ -46,7 +56,7 @@ fn test_replay() -> Result<()> {
                 let index_name = ticker_index_name(&index_time);
                 persistence.delete_index(&index_name).await?;
             }
-            replay(&context, file, ReplayMode::Sync).await?
+            replay(&context, file, ReplayMode::Sync).await?;
         }
         Ok(())
     });
@@ -63,8 +73,8 @@ fn test_replay_async() -> Result<()> {
     let rt = Runtime::new()?;
     let result: Result<()> = rt.block_on(async {
         init_log("INFO").await?;
-        let config = AppConfig::load("config.yaml")?;
-        config.extra_put(KEY_EXTRA_PRCOESS_IN_ASYNC, "async_mode"); // enable for save data
+        let mut config = AppConfig::load("config.yaml")?;
+        config.switches.async_process = true; // enable for save data
         let context = AppContext::new(config).init().await?;
         let config = context.config();
         let persistence = context.persistence();
@@ -91,7 +101,7 @@ fn test_replay_async() -> Result<()> {
                 let index_name = ticker_index_name(&index_time);
                 persistence.delete_index(&index_name).await?;
             }
-            replay(&context, &file, ReplayMode::Async { delay: 50 }).await?
+            replay(&context, &file, ReplayMode::Async { delay: 50 }).await?;
         }
         Ok(())
     });
@@ -150,6 +160,354 @@ fn test_sort() {
     println!("desc: {:?}", &protfolios);
 }
 
+#[test]
+fn test_take_file_date() -> Result<()> {
+    assert_eq!("2022-03-09", take_file_date("tmp/tickers20220309")?);
+    assert_eq!(
+        "2022-03-09",
+        take_file_date("tmp/json/split.tickers20220309.TQQQ-SQQQ.json")?
+    );
+    // digits of version and symbol are not part of date
+    assert_eq!("2022-03-09", take_file_date("tickers20220309.v2")?);
+    assert_eq!(
+        "2022-03-10",
+        take_file_date("tmp2/SPXL3.tickers20220310.gz")?
+    );
+    assert!(take_file_date("tickers2022030").is_err());
+    assert!(take_file_date("tickers202203091").is_err());
+    assert!(take_file_date("tickers20221309").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_walk_forward_windows() -> Result<()> {
+    let dates: Vec<String> = (1..=7).map(|d| format!("2022-03-0{}", d)).collect();
+
+    let windows = walk_forward_windows(&dates, 3, 2)?;
+    assert_eq!(2, windows.len());
+    assert_eq!(&dates[0..3], windows[0].0);
+    assert_eq!(&dates[3..5], windows[0].1);
+    assert_eq!(&dates[2..5], windows[1].0);
+    assert_eq!(&dates[5..7], windows[1].1);
+
+    // the last partial window is dropped
+    let windows = walk_forward_windows(&dates[..6], 3, 2)?;
+    assert_eq!(1, windows.len());
+
+    // exactly one window
+    assert_eq!(1, walk_forward_windows(&dates[..5], 3, 2)?.len());
+
+    // too few files
+    assert!(walk_forward_windows(&dates[..4], 3, 2).is_err());
+    assert!(walk_forward_windows(&dates, 0, 2).is_err());
+    assert!(walk_forward_windows(&dates, 3, 0).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_indicator_engine() {
+    let config = IndicatorConfig {
+        enabled: true,
+        ema: 3,
+        rsi: 3,
+        macd_fast: 2,
+        macd_slow: 3,
+        macd_signal: 2,
+        atr: 3,
+        bollinger: 3,
+        bollinger_width: 2.0,
+    };
+    let unit = TimeUnit::new("m0010", 10, 10);
+    let ticker = |index: i64, price: f32| Ticker {
+        id: "TQQQ".to_string(),
+        price,
+        time: index * 10_000,
+        quote_type: QuoteType::Etf,
+        market_hours: MarketHoursType::RegularMarket,
+        day_volume: index * 100,
+        volume: None,
+        change: 0.0,
+        time_diff: 0,
+    };
+
+    // rising price, one ticker per bar
+    let mut engine = IndicatorEngine::new(&config, &unit);
+    let values = engine.update(&ticker(1, 1.0));
+    assert!(values["ema"][0].is_nan());
+    assert!(values["vwap"][0].is_nan());
+    let mut values = values;
+    for index in 2..=10 {
+        values = engine.update(&ticker(index, index as f32));
+    }
+    assert_eq!(100.0, values["rsi"][0]);
+    assert!(values["ema"][0] > 8.0 && values["ema"][0] < 10.0);
+    assert!(values["macd"][0] > 0.0);
+    assert!(values["atr"][0] > 0.95 && values["atr"][0] < 1.0);
+    assert_eq!(9.0, values["bollinger"][0]);
+    assert!(values["bollinger"][1] > 9.0 && values["bollinger"][2] < 9.0);
+    assert_eq!(6.0, values["vwap"][0]);
+
+    // older ticker is ignored
+    assert_eq!(values, engine.update(&ticker(3, 100.0)));
+    let rising = values.clone();
+
+    // flat price
+    let mut engine = IndicatorEngine::new(&config, &unit);
+    for index in 1..=10 {
+        values = engine.update(&ticker(index, 5.0));
+    }
+    assert_eq!(50.0, values["rsi"][0]);
+    assert_eq!(5.0, values["ema"][0]);
+    assert_eq!(0.0, values["atr"][0]);
+    assert_eq!(vec![5.0, 5.0, 5.0], values["bollinger"]);
+
+    // tick bars of 2 tickers, rising price by bar
+    let unit = TimeUnit {
+        bar: BarType::Tick,
+        ..TimeUnit::new("t0002", 2, 10)
+    };
+    let mut engine = IndicatorEngine::new(&config, &unit);
+    for index in 1..=10 {
+        engine.update(&ticker(index, index as f32));
+        values = engine.update(&ticker(index, index as f32));
+    }
+    assert_eq!(rising, values);
+}
+
+// Slope of nearest line, the former full recomputation kept as reference
+fn baseline_slope(samples: &Vec<(f64, f64)>) -> (f64, f64) {
+    match samples.len() {
+        0 => (f64::NAN, f64::NAN),
+        1 => (0.0, samples[0].1),
+        2 => {
+            let (x_1, y_1) = samples[0];
+            let (x_2, y_2) = samples[1];
+            if x_1 == x_2 {
+                return (0.0, if y_1 == y_2 { y_1 } else { (y_1 + y_2) / 2.0 });
+            }
+            let a = (y_1 - y_2) / (x_1 - x_2);
+            (a, y_1 - a * x_1)
+        }
+        _ => {
+            let count = samples.len() as f64;
+            let x_avg: f64 = samples.iter().map(|(x, _)| *x / count).sum();
+            let y_avg: f64 = samples.iter().map(|(_, y)| *y / count).sum();
+            let xy: f64 = samples
+                .iter()
+                .map(|(x, y)| (*x - x_avg) * (*y - y_avg))
+                .sum();
+            let x_x: f64 = samples
+                .iter()
+                .map(|(x, _)| (*x - x_avg) * (*x - x_avg))
+                .sum();
+            let a = xy / x_x;
+            (a, y_avg - a * x_avg)
+        }
+    }
+}
+
+fn same_value(left: f64, right: f64) -> bool {
+    left.to_bits() == right.to_bits() || (left.is_nan() && right.is_nan())
+}
+
+// cargo test --package sminer --test tests -- analysis::test_fixed_unit_retention --exact
+#[test]
+fn test_fixed_unit_retention() -> Result<()> {
+    let unit = TimeUnit::new("m0060", 60, 0);
+    let retention = Retention {
+        window: 5 * 60 * 1000,
+        ticks: 0,
+        volume: 0,
+    };
+    let mut tickers: LinkedList<Ticker> = LinkedList::new();
+    let mut protfolios: LinkedList<Protfolio> = LinkedList::new();
+    let start = 1646830800000;
+    for index in 0..60 {
+        tickers.push_front(Ticker {
+            id: "TQQQ".to_string(),
+            price: 50.0 + index as f32,
+            time: start + index * 30 * 1000,
+            quote_type: QuoteType::Etf,
+            market_hours: MarketHoursType::RegularMarket,
+            day_volume: index,
+            volume: Some(1),
+            change: 0.0,
+            time_diff: 0,
+        });
+        let ticker = tickers.front().unwrap();
+        let trade = Arc::new(RwLock::new(TradeInfo::from(ticker, index, 1, true)));
+        unit.rebalance(
+            "TQQQ",
+            &tickers,
+            &mut protfolios,
+            None,
+            Some(retention),
+            trade,
+        )?;
+    }
+
+    // 30 minutes of tickers, only protfolios in 5 minutes are kept
+    let latest = tickers.front().unwrap().time;
+    assert_eq!(5, protfolios.len());
+    assert!(protfolios
+        .iter()
+        .all(|p| p.unit_time >= latest - retention.window));
+    Ok(())
+}
+
+// Feed tickers one by one, protfolios and trade states of rolling aggregation
+// must be identical to full recomputation
+fn check_rolling_aggregation(
+    unit: &TimeUnit,
+    ticks: &[(i64, f32, i64)],
+) -> std::result::Result<(), TestCaseError> {
+    let aggregator = Mutex::new(MovingAggregator::new(unit));
+    let mut tickers: LinkedList<Ticker> = LinkedList::new();
+    let mut full: LinkedList<Protfolio> = LinkedList::new();
+    let mut rolling: LinkedList<Protfolio> = LinkedList::new();
+    let mut time = 1646830800000;
+    let mut day_volume = 0;
+
+    for (message_id, (time_diff, price, volume)) in ticks.iter().enumerate() {
+        time += time_diff;
+        day_volume += volume;
+        tickers.push_front(Ticker {
+            id: "TQQQ".to_string(),
+            price: *price,
+            time,
+            quote_type: QuoteType::Etf,
+            market_hours: MarketHoursType::RegularMarket,
+            day_volume,
+            volume: Some(*volume),
+            change: 0.0,
+            time_diff: 0,
+        });
+
+        let ticker = tickers.front().unwrap();
+        let message_id = message_id as i64;
+        let full_trade = Arc::new(RwLock::new(TradeInfo::from(ticker, message_id, 1, true)));
+        let rolling_trade = Arc::new(RwLock::new(TradeInfo::from(ticker, message_id, 1, true)));
+        unit.rebalance(
+            "TQQQ",
+            &tickers,
+            &mut full,
+            None,
+            None,
+            Arc::clone(&full_trade),
+        )
+        .unwrap();
+        unit.rebalance(
+            "TQQQ",
+            &tickers,
+            &mut rolling,
+            Some(&aggregator),
+            None,
+            Arc::clone(&rolling_trade),
+        )
+        .unwrap();
+
+        prop_assert_eq!(&full, &rolling);
+        prop_assert_eq!(
+            &full_trade.read().unwrap().states,
+            &rolling_trade.read().unwrap().states
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    // cargo test --package sminer --test tests -- analysis::test_rolling_aggregation
+    #[test]
+    fn test_rolling_aggregation(
+        duration in 1..30i32,
+        period in 1..12u32,
+        ticks in prop::collection::vec((0..3000i64, 0.01..500f32, 0..1000i64), 1..300),
+    ) {
+        check_rolling_aggregation(&TimeUnit::new("m0000", duration, period), &ticks)?;
+    }
+
+    // cargo test --package sminer --test tests -- analysis::test_rolling_aggregation_out_of_order
+    #[test]
+    fn test_rolling_aggregation_out_of_order(
+        duration in 1..30i32,
+        period in 1..12u32,
+        ticks in prop::collection::vec(
+            (prop_oneof![9 => 0..3000i64, 1 => -2000..0i64], 0.01..500f32, 0..1000i64),
+            1..300,
+        ),
+    ) {
+        check_rolling_aggregation(&TimeUnit::new("m0000", duration, period), &ticks)?;
+    }
+
+    // cargo test --package sminer --test tests -- analysis::test_rolling_aggregation_tick_bar
+    #[test]
+    fn test_rolling_aggregation_tick_bar(
+        size in 1..30i32,
+        period in 1..12u32,
+        ticks in prop::collection::vec((-2000..3000i64, 0.01..500f32, 0..1000i64), 1..300),
+    ) {
+        let unit = TimeUnit {
+            bar: BarType::Tick,
+            ..TimeUnit::new("t0000", size, period)
+        };
+        check_rolling_aggregation(&unit, &ticks)?;
+    }
+
+    // cargo test --package sminer --test tests -- analysis::test_rolling_aggregation_volume_bar
+    #[test]
+    fn test_rolling_aggregation_volume_bar(
+        size in 1..3000i32,
+        period in 1..12u32,
+        ticks in prop::collection::vec((-2000..3000i64, 0.01..500f32, 0..1000i64), 1..300),
+    ) {
+        let unit = TimeUnit {
+            bar: BarType::Volume,
+            ..TimeUnit::new("v0000", size, period)
+        };
+        check_rolling_aggregation(&unit, &ticks)?;
+    }
+
+    // cargo test --package sminer --test tests -- analysis::test_rolling_aggregation_flat
+    #[test]
+    fn test_rolling_aggregation_flat(
+        duration in 1..30i32,
+        period in 1..12u32,
+        price in 0.01..500f32,
+        ticks in prop::collection::vec((0..3000i64, 0..1000i64), 1..300),
+    ) {
+        let ticks: Vec<(i64, f32, i64)> = ticks
+            .into_iter()
+            .map(|(time_diff, volume)| (time_diff, price, volume))
+            .collect();
+        check_rolling_aggregation(&TimeUnit::new("m0000", duration, period), &ticks)?;
+    }
+
+    // cargo test --package sminer --test tests -- analysis::test_calculate_slope
+    #[test]
+    fn test_calculate_slope(
+        samples in prop::collection::vec((1646830800000..1646854200000i64, 1.0..500f32), 0..300),
+        flat in 0..2usize,
+    ) {
+        // newest first, as ticker list
+        let mut samples: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|(time, price)| (*time as f64, *price as f64))
+            .collect();
+        samples.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        // flat prices give tiny slopes of either sign, must be kept as is
+        if flat == 1 {
+            if let Some((_, price)) = samples.first().cloned() {
+                samples.iter_mut().for_each(|sample| sample.1 = price);
+            }
+        }
+
+        let (slope, b_num) = calculate_slope(&samples);
+        let (expected_slope, expected_b_num) = baseline_slope(&samples);
+        prop_assert!(same_value(slope, expected_slope), "{} != {}", slope, expected_slope);
+        prop_assert!(same_value(b_num, expected_b_num), "{} != {}", b_num, expected_b_num);
+    }
+}
+
 // cargo test --package sminer --test tests -- analysis::test_slope_check --exact --nocapture
 #[test]
 #[ignore = "manually run only"]
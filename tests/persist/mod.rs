@@ -20,7 +20,7 @@ mod mongo {
     use sminer::{
         init_log,
         persist::{
-            mongo::{get_reglar_market_start_time, query_ticker},
+            mongo::{get_reglar_market_start_time, query_ticker, TickerFilter},
             DataSource, PersistenceContext,
         },
         vo::{biz::Ticker, core::AppConfig},
@@ -32,6 +32,43 @@ mod mongo {
         sync::Arc,
     };
 
+    #[test]
+    fn test_ticker_filter() -> Result<()> {
+        let filter = TickerFilter::parse(&[], None, None)?;
+        assert!(filter.is_empty());
+        assert_eq!(Document::new(), filter.to_document());
+        assert_eq!("", filter.suffix());
+
+        let filter = TickerFilter::parse(
+            &["tqqq,SQQQ"],
+            Some("2022-03-09T14:30:00Z"),
+            Some("1646838000000"),
+        )?;
+        assert_eq!(vec!["TQQQ", "SQQQ"], filter.symbols);
+        assert_eq!(
+            mongodb::bson::doc! {
+                "id": { "$in": ["TQQQ", "SQQQ"] },
+                "time": { "$gte": 1646836200000_i64, "$lt": 1646838000000_i64 },
+            },
+            filter.to_document()
+        );
+        assert_eq!(".TQQQ-SQQQ.1430-1500", filter.suffix());
+
+        // end date included
+        let filter = TickerFilter::parse(&[], None, Some("20220309"))?;
+        assert_eq!(Some(1646870400000), filter.to);
+        assert_eq!(".-202203100000", filter.suffix());
+
+        // ranges across days are dated
+        let filter = TickerFilter::parse(&[], Some("20220309"), Some("20220310"))?;
+        assert_eq!(".202203090000-202203110000", filter.suffix());
+        let filter = TickerFilter::parse(&[], Some("20220309"), Some("20220309"))?;
+        assert_eq!(".0000-0000", filter.suffix());
+
+        assert!(TickerFilter::parse(&[], Some("20220310"), Some("20220309")).is_err());
+        Ok(())
+    }
+
     #[tokio::test]
     #[ignore = "used for import data"]
     async fn test_import_into_mongo() -> Result<()> {
@@ -91,7 +128,13 @@ mod mongo {
         let db_name = config.data_source.mongodb.target.as_ref().unwrap();
         let client: Client = context.get_connection()?;
 
-        let mut cursor = query_ticker(&client, db_name, "tickers20220311").await?;
+        let mut cursor = query_ticker(
+            &client,
+            db_name,
+            "tickers20220311",
+            &TickerFilter::default(),
+        )
+        .await?;
         while let Some(ticker) = cursor.try_next().await? {
             info!("{:?}", ticker);
         }
@@ -139,7 +182,8 @@ mod mongo {
         ];
 
         for collection in collections {
-            let mut cursor = query_ticker(&client, db_name, collection).await?;
+            let mut cursor =
+                query_ticker(&client, db_name, collection, &TickerFilter::default()).await?;
             std::fs::create_dir_all("tmp")?;
             let file = OpenOptions::new()
                 .write(true)
@@ -173,13 +217,118 @@ mod elastic {
     use sminer::{
         init_log,
         persist::{
-            es::{take_index_time, ticker_index_name, ElasticTicker},
+            es::{
+                index_templates, take_index_time, ticker_index_name, ElasticOrder, ElasticTicker,
+                ElasticTradeStates, LIFECYCLE_POLICY,
+            },
             DataSource, PersistenceContext,
         },
-        vo::{biz::Ticker, core::AppConfig},
+        vo::{
+            biz::{
+                AuditState, BarType, MarketHoursType, Order, OrderEvent, Protfolio, QuoteType,
+                Ticker, TimeUnit, TradeInfo,
+            },
+            core::AppConfig,
+        },
         Result,
     };
-    use std::{cmp::max, sync::Arc};
+    use std::{cmp::max, collections::BTreeMap, sync::Arc};
+
+    // Every field of document is mapped explicitly
+    fn assert_mapped<T: serde::Serialize>(templates: &[(String, Value)], name: &str, doc: &T) {
+        let (_, body) = templates.iter().find(|(n, _)| n == name).unwrap();
+        assert_eq!(json!([format!("{}-*", name)]), body["index_patterns"]);
+        let properties = &body["template"]["mappings"]["properties"];
+        for field in json!(doc).as_object().unwrap().keys() {
+            assert!(
+                properties.get(field).is_some(),
+                "{} of {} not mapped",
+                field,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_index_templates() -> Result<()> {
+        let templates = index_templates(false);
+        assert_eq!(6, templates.len());
+        assert!(templates
+            .iter()
+            .all(|(_, body)| body["template"]["settings"] == json!({})));
+
+        let ticker = Ticker {
+            id: "TQQQ".to_string(),
+            price: 50.5,
+            time: 1646836200000,
+            quote_type: QuoteType::Etf,
+            market_hours: MarketHoursType::RegularMarket,
+            day_volume: 100,
+            volume: Some(100),
+            change: 0.5,
+            time_diff: 0,
+        };
+        assert_mapped(
+            &templates,
+            "sminer-ticker",
+            &ElasticTicker::from(ticker.clone()),
+        );
+
+        let mut trade = TradeInfo::from(&ticker, 1, 1, false);
+        trade.states.insert("m0060".to_string(), vec![0.5, 0.3]);
+        trade.update_indicators("m0060", BTreeMap::from([("rsi".to_string(), vec![55.0])]));
+        assert!(trade.finalized());
+        assert_eq!(Some(&vec![55.0]), trade.indicator("m0060", "rsi"));
+        // indicators are kept out of slope states
+        let states = ElasticTradeStates::from(&trade);
+        assert_eq!(2, states.states.len());
+        assert!(states.states.iter().all(|state| state.unit == "m0060"));
+        assert_eq!(1, states.states[1].index);
+        assert_mapped(&templates, "sminer-trade", &states);
+        let (_, body) = templates.iter().find(|(n, _)| n == "sminer-trade").unwrap();
+        let nested = &body["template"]["mappings"]["properties"]["states"];
+        assert_eq!(json!("nested"), nested["type"]);
+        for field in json!(states.states[0]).as_object().unwrap().keys() {
+            assert!(nested["properties"].get(field).is_some());
+        }
+
+        let order = Order::new("TQQQ", 50.0, 40.0, 5, ticker.time, AuditState::Flash, None);
+        let event = OrderEvent::new(ticker.time, &order);
+        assert_mapped(&templates, "sminer-order", &ElasticOrder::from(&event));
+
+        let protfolio = Protfolio {
+            id: "TQQQ".to_string(),
+            time: ticker.time,
+            price: 50.5,
+            kind: 'p',
+            quote_type: QuoteType::Etf,
+            market_hours: MarketHoursType::RegularMarket,
+            volume: 100,
+            unit: TimeUnit {
+                name: "m0060".to_string(),
+                duration: 60,
+                period: 0,
+                bar: BarType::Time,
+            },
+            unit_time: ticker.time,
+            period_type: 0,
+            max_price: 50.5,
+            min_price: 50.5,
+            open_price: 50.5,
+            close_price: 50.5,
+            sample_size: 1,
+            slope: Some(0.1),
+            b_num: None,
+        };
+        assert_mapped(&templates, "sminer-protfolio", &protfolio);
+
+        // daily indices follow the lifecycle policy
+        let templates = index_templates(true);
+        assert!(templates.iter().all(|(_, body)| {
+            body["template"]["settings"]["index.lifecycle.name"] == json!(LIFECYCLE_POLICY)
+        }));
+        Ok(())
+    }
 
     #[tokio::test]
     #[ignore = "used for test imported data"]
@@ -458,3 +607,413 @@ mod grafana {
         Ok(())
     }
 }
+
+mod sink {
+    use sminer::{
+        persist::sink::MemorySink,
+        vo::{
+            biz::{AuditState, MarketHoursType, Order, OrderStatus, QuoteType, Ticker},
+            core::{AppConfig, AppContext},
+        },
+        Result,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    #[tokio::test]
+    async fn test_memory_sink() -> Result<()> {
+        let mut config = AppConfig::load("config.yaml")?.with_values(&[
+            (
+                "dataSource.mongodb.enabled".to_string(),
+                serde_json::json!(false),
+            ),
+            (
+                "dataSource.elasticsearch.enabled".to_string(),
+                serde_json::json!(false),
+            ),
+            ("trade.enabled".to_string(), serde_json::json!(false)),
+        ])?;
+        config.switches.async_process = true;
+        let symbol = config.symbols().into_iter().next().unwrap();
+
+        // no sink of disabled data sources
+        let context = AppContext::new(config);
+        assert!(context.sinks().is_empty());
+        assert!(context.source().is_none());
+        let sink = Arc::new(MemorySink::default());
+        context.register_sink(sink.clone());
+        context.set_source(sink.clone());
+        let context = context.init().await?;
+
+        let start = 1646836200000;
+        for index in 0..10 {
+            let ticker = Ticker {
+                id: symbol.clone(),
+                price: 10.0 + index as f32,
+                time: start + index * 1000,
+                quote_type: QuoteType::Etf,
+                market_hours: MarketHoursType::RegularMarket,
+                day_volume: index * 100,
+                volume: None,
+                change: 0.0,
+                time_diff: 0,
+            };
+            context.dispatch(&ticker).await?;
+        }
+        let order = Order::new(&symbol, 10.0, 20.0, 5, start, AuditState::Flash, None);
+        assert!(context.asset().add_order(order.clone()));
+
+        // wait until processors flushed, bounded for loaded machines
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sink.tickers().len() < 10 || sink.orders().is_empty() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .map_err(|_| "Sink not flushed in 10 seconds")?;
+
+        let tickers = sink.tickers();
+        assert_eq!(10, tickers.len());
+        assert_eq!(start, context.asset().get_regular_start_time());
+        let orders = sink.orders();
+        assert_eq!(1, orders.len());
+        assert_eq!(order.id, orders[0].order.id);
+        assert_eq!(OrderStatus::Init, orders[0].status);
+
+        // stored tickers as source
+        let source = context.source().unwrap();
+        assert_eq!(start, source.regular_start_time(start + 5000).await?);
+        let after = source
+            .tickers_after(start + 4500, &[symbol.clone()])
+            .await?;
+        assert_eq!(
+            (5..10).map(|i| start + i * 1000).collect::<Vec<i64>>(),
+            after.iter().map(|t| t.time).collect::<Vec<i64>>()
+        );
+        Ok(())
+    }
+}
+
+mod sqlite {
+    use sminer::{
+        persist::{
+            sink::{TickerSink, TickerSource},
+            sqlite::{SqliteStore, TickerQuery},
+        },
+        vo::{
+            biz::{
+                AuditState, BacktestReport, MarketHoursType, Order, OrderEvent, QuoteType, Ticker,
+            },
+            core::AppConfig,
+        },
+        Result,
+    };
+
+    #[test]
+    fn test_ticker_query() -> Result<()> {
+        assert_eq!(None, TickerQuery::parse("tmp/tickers20220309")?);
+
+        let query = TickerQuery::parse("sqlite:tqqq,SQQQ@20220309")?.unwrap();
+        assert_eq!(vec!["TQQQ", "SQQQ"], query.symbols);
+        assert_eq!(1646784000000, query.from);
+        assert_eq!(1646870400000, query.to);
+        assert_eq!("tickers20220309.TQQQ-SQQQ", query.name());
+
+        // end date included
+        let query = TickerQuery::parse("sqlite:2022-03-09..2022-03-10")?.unwrap();
+        assert!(query.symbols.is_empty());
+        assert_eq!(1646956800000, query.to);
+        assert_eq!("tickers20220309", query.name());
+
+        let query = TickerQuery::parse("sqlite:TQQQ@2022-03-09T14:30:00Z..1646838000000")?.unwrap();
+        assert_eq!((1646836200000, 1646838000000), (query.from, query.to));
+
+        assert!(TickerQuery::parse("sqlite:20220310..20220309").is_err());
+        assert!(TickerQuery::parse("sqlite:TQQQ@yesterday").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() -> Result<()> {
+        let file = std::env::temp_dir().join("sminer-store.db");
+        let _ = std::fs::remove_file(&file);
+        let store = SqliteStore::open(file.to_str().unwrap())?;
+
+        let start = 1646836200000;
+        let tickers: Vec<Ticker> = (0..20)
+            .map(|index| Ticker {
+                id: if index % 2 == 0 { "TQQQ" } else { "SQQQ" }.to_string(),
+                price: 50.0459 + index as f32,
+                // received out of time order
+                time: start + (index / 2) * 1000 - if index == 4 { 1500 } else { 0 },
+                quote_type: QuoteType::Etf,
+                market_hours: if index < 2 {
+                    MarketHoursType::PreMarket
+                } else {
+                    MarketHoursType::RegularMarket
+                },
+                day_volume: index * 100,
+                volume: if index < 2 { None } else { Some(200) },
+                change: 0.5,
+                time_diff: 3,
+            })
+            .collect();
+        store.save_tickers(&tickers).await?;
+
+        // received order kept
+        let query = TickerQuery::parse(&format!("sqlite:TQQQ@{}..{}", start, start + 5000))?;
+        let loaded = store.query_tickers(&query.unwrap())?;
+        let expected: Vec<&Ticker> = tickers
+            .iter()
+            .filter(|t| t.id == "TQQQ" && t.time < start + 5000)
+            .collect();
+        assert_eq!(
+            serde_json::to_string(&expected)?,
+            serde_json::to_string(&loaded)?
+        );
+        assert_eq!(expected[2].volume, loaded[2].volume);
+
+        // source of regular market
+        assert_eq!(start + 500, store.regular_start_time(start).await?);
+        let after = store
+            .tickers_after(start + 7000, &["SQQQ".to_string()])
+            .await?;
+        assert_eq!(
+            vec![start + 8000, start + 9000],
+            after.iter().map(|t| t.time).collect::<Vec<i64>>()
+        );
+
+        let order = Order::new("TQQQ", 50.0, 40.0, 5, start, AuditState::Flash, None);
+        store.save_order(&OrderEvent::new(start, &order)).await?;
+        let orders = store.query_orders(start, start + 1)?;
+        assert_eq!(1, orders.len());
+        assert_eq!(order.id, orders[0].order.id);
+
+        let config = AppConfig::load("config.yaml")?;
+        let report = BacktestReport {
+            source: "tickers20220309.sqlite".to_string(),
+            config_sha: config.sha()?,
+            config,
+            orders: Vec::new(),
+            pairs: Vec::new(),
+            days: Vec::new(),
+            equity: Vec::new(),
+            metrics: Vec::new(),
+            rules: Vec::new(),
+            shadow_orders: Vec::new(),
+            shadows: Vec::new(),
+        };
+        store.insert_report(&report)?;
+        assert_eq!(1, store.query_reports(Some(&report.config_sha))?.len());
+        assert!(store.query_reports(Some("other"))?.is_empty());
+
+        let removed = store.delete_tickers(&TickerQuery {
+            symbols: vec!["SQQQ".to_string()],
+            from: start,
+            to: start + 10000,
+        })?;
+        assert_eq!(10, removed);
+
+        // imported tickers replace the range, failed import stores nothing
+        let query = TickerQuery {
+            symbols: vec!["TQQQ".to_string()],
+            from: start - 1000,
+            to: start + 10000,
+        };
+        let imported: Vec<Ticker> = tickers.iter().filter(|t| t.id == "TQQQ").cloned().collect();
+        let count = store.import_tickers(imported.iter().cloned().map(Ok), Some(&query))?;
+        assert_eq!(10, count);
+        assert_eq!(10, store.query_tickers(&query)?.len());
+        let failed = imported
+            .iter()
+            .cloned()
+            .map(Ok)
+            .chain([Err("broken".into())]);
+        assert!(store.import_tickers(failed, None).is_err());
+        assert_eq!(10, store.query_tickers(&query)?.len());
+
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+}
+
+mod parquet {
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::RowAccessor,
+    };
+    use sminer::{
+        persist::parquet::write_parquet,
+        vo::biz::{AuditState, MarketHoursType, Order, QuoteType, Ticker},
+        Result,
+    };
+    use std::fs::File;
+
+    #[test]
+    fn test_write_parquet() -> Result<()> {
+        let folder = std::env::temp_dir().join("sminer-parquet");
+        let start = 1646836200000;
+        let tickers: Vec<Ticker> = (0..10)
+            .map(|index| Ticker {
+                id: "TQQQ".to_string(),
+                price: 50.5 + index as f32,
+                time: start + index * 1000,
+                quote_type: QuoteType::Etf,
+                market_hours: MarketHoursType::RegularMarket,
+                day_volume: index * 100,
+                volume: None,
+                change: 0.5,
+                time_diff: 3,
+            })
+            .collect();
+        let file = folder.join("tickers.parquet");
+        write_parquet(file.to_str().unwrap(), &tickers)?;
+
+        let reader = SerializedFileReader::new(File::open(&file)?)?;
+        assert_eq!(10, reader.metadata().file_metadata().num_rows());
+        let rows = reader
+            .get_row_iter(None)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let row = &rows[3];
+        assert_eq!("TQQQ", row.get_string(0)?);
+        assert_eq!(53.5, row.get_double(1)?);
+        assert_eq!(start + 3000, row.get_long(2)?);
+        assert_eq!("RegularMarket", row.get_string(4)?);
+
+        // optional fields written as null
+        let mut accepted = Order::new("TQQQ", 50.0, 40.0, 5, start, AuditState::Flash, None);
+        accepted.accepted_time = Some(start + 1000);
+        let created = Order::new("SQQQ", 40.0, 50.0, 5, start, AuditState::Flash, None);
+        let file = folder.join("orders.parquet");
+        write_parquet(file.to_str().unwrap(), &[accepted, created])?;
+
+        let reader = SerializedFileReader::new(File::open(&file)?)?;
+        let rows = reader
+            .get_row_iter(None)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(start + 1000, rows[0].get_long(6)?);
+        assert!(rows[1].get_long(6).is_err());
+        assert_eq!("Flash", rows[1].get_string(11)?);
+
+        std::fs::remove_dir_all(&folder)?;
+        Ok(())
+    }
+}
+
+mod reader {
+    use flate2::{write::GzEncoder, Compression};
+    use sminer::{
+        persist::reader::{
+            convert_file, is_protobuf, source_name, source_path, JsonLines, TickerFile,
+            TickerWriter,
+        },
+        vo::biz::Ticker,
+        Result,
+    };
+    use std::{fs::File, io::Write};
+
+    const LINES: &str = r#"{"id":"TQQQ","price":50.5,"time":1646836200000,"quote_type":"Etf","market_hours":"RegularMarket","day_volume":100,"change":0.5}
+{"id":"TQQQ","price":
+{"id":"TQQQ","price":51.5,"time":1646836201000,"quote_type":"Etf","market_hours":"RegularMarket","day_volume":200,"change":0.5}
+
+{"id":"SQQQ","price":40.5,"time":1646836202000,"quote_type":"Etf","market_hours":"RegularMarket","day_volume":300,"change":0.5}
+"#;
+
+    fn assert_tickers(path: &str) -> Result<()> {
+        let mut lines = JsonLines::<Ticker>::open(path)?;
+        let tickers = lines.by_ref().collect::<Result<Vec<Ticker>>>()?;
+        assert_eq!(
+            vec![1646836200000, 1646836201000, 1646836202000],
+            tickers.iter().map(|t| t.time).collect::<Vec<i64>>()
+        );
+        // blank lines are not counted
+        assert_eq!(1, lines.skipped());
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_lines() -> Result<()> {
+        let folder = std::env::temp_dir().join("sminer-reader");
+        std::fs::create_dir_all(&folder)?;
+
+        let plain = folder.join("tickers20220309");
+        std::fs::write(&plain, LINES)?;
+        assert_tickers(plain.to_str().unwrap())?;
+
+        let gzip = folder.join("tickers20220309.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzip)?, Compression::default());
+        encoder.write_all(LINES.as_bytes())?;
+        encoder.finish()?;
+        assert_tickers(gzip.to_str().unwrap())?;
+
+        let zstd = folder.join("tickers20220309.zst");
+        std::fs::write(&zstd, zstd::encode_all(LINES.as_bytes(), 0)?)?;
+        assert_tickers(zstd.to_str().unwrap())?;
+
+        // broken archive stops with error
+        let broken = folder.join("broken.gz");
+        std::fs::write(&broken, LINES)?;
+        assert!(JsonLines::<Ticker>::open(broken.to_str().unwrap())?
+            .collect::<Result<Vec<Ticker>>>()
+            .is_err());
+
+        assert_eq!("tickers20220309", source_name("tmp/tickers20220309.zst"));
+        assert_eq!("tmp/tickers20220309", source_path("tmp/tickers20220309.gz"));
+        assert_eq!("tmp/tickers20220309", source_path("tmp/tickers20220309"));
+        assert_eq!("tickers20220309", source_name("tmp/tickers20220309.pb.gz"));
+
+        std::fs::remove_dir_all(&folder)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_protobuf_tickers() -> Result<()> {
+        let folder = std::env::temp_dir().join("sminer-protobuf");
+        std::fs::create_dir_all(&folder)?;
+        let json = folder.join("tickers20220309");
+        std::fs::write(&json, LINES)?;
+        let json = json.to_str().unwrap();
+        let expected =
+            serde_json::to_string(&TickerFile::open(json)?.collect::<Result<Vec<Ticker>>>()?)?;
+
+        for target in ["tickers20220309.pb", "tickers20220309.pb.zst"] {
+            let target = folder.join(target);
+            let target = target.to_str().unwrap();
+            assert!(is_protobuf(target));
+            assert_eq!(3, convert_file(json, target)?);
+            let tickers = TickerFile::open(target)?.collect::<Result<Vec<Ticker>>>()?;
+            assert_eq!(expected, serde_json::to_string(&tickers)?);
+        }
+        assert!(!is_protobuf(json));
+
+        // back to json lines
+        let back = folder.join("back.gz");
+        let back = back.to_str().unwrap();
+        convert_file(folder.join("tickers20220309.pb").to_str().unwrap(), back)?;
+        let tickers = TickerFile::open(back)?.collect::<Result<Vec<Ticker>>>()?;
+        assert_eq!(expected, serde_json::to_string(&tickers)?);
+        assert!(convert_file(back, back).is_err());
+
+        // truncated record stops with error
+        let truncated = folder.join("truncated.pb");
+        let mut writer = TickerWriter::create(truncated.to_str().unwrap())?;
+        for ticker in TickerFile::open(json)? {
+            writer.write(&ticker?)?;
+        }
+        writer.finish()?;
+        let content = std::fs::read(&truncated)?;
+        std::fs::write(&truncated, &content[..content.len() - 3])?;
+        let result =
+            TickerFile::open(truncated.to_str().unwrap())?.collect::<Result<Vec<Ticker>>>();
+        assert!(result.is_err());
+
+        // corrupted length prefix is rejected before allocation
+        let oversized = folder.join("oversized.pb");
+        std::fs::write(&oversized, [0xff, 0xff, 0xff, 0xff, 0xff, 0x1f])?;
+        let result =
+            TickerFile::open(oversized.to_str().unwrap())?.collect::<Result<Vec<Ticker>>>();
+        assert!(result.unwrap_err().to_string().contains("exceeds limit"));
+
+        std::fs::remove_dir_all(&folder)?;
+        Ok(())
+    }
+}
@@ -30,7 +30,7 @@ fn test_consume_yahoo_tickers() -> Result<()> {
         let end_time = Utc::now().add(Duration::minutes(2)).timestamp();
         info!(
             "Start consuming yahoo tickers, expected to stop at {}",
-            Utc.timestamp_millis(end_time),
+            Utc.timestamp_millis_opt(end_time).unwrap(),
         );
 
         let symbols = config.symbols();
@@ -3,12 +3,26 @@ use config::Config;
 use log::info;
 use sminer::{
     init_log,
-    vo::{biz::Ticker, core::AppConfig},
+    persist::{
+        sink::{MemorySink, TickerSink},
+        snapshot::SessionSnapshot,
+    },
+    vo::{
+        biz::{
+            AuditState, BacktestReport, MarketHoursType, Order, OrderEvent, OrderRow, OrderStatus,
+            PairSummary, QuoteType, RuleSummary, Ticker, TradeInfo,
+        },
+        core::{
+            env_values, parse_override, AppConfig, AppContext, AssetContext, AuditRole, Retention,
+            RetentionConfig, SweepParameter, SweepRange, SweepSpec, UnitRef,
+        },
+    },
     Result,
 };
 use std::{
     fs::File,
     io::{BufRead, BufReader},
+    sync::Arc,
 };
 
 #[tokio::test]
@@ -46,7 +60,7 @@ fn print_data() -> Result<()> {
         println!(
             "id: {}, time: {}, market: {:?}, price: {}, volume: {}",
             ticker.id,
-            Utc.timestamp_millis(ticker.time),
+            Utc.timestamp_millis_opt(ticker.time).unwrap(),
             ticker.market_hours,
             ticker.price,
             ticker.day_volume
@@ -74,3 +88,851 @@ fn parse_bool() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_merge_pair_summary() -> Result<()> {
+    let summaries = vec![
+        PairSummary {
+            pair: "SQQQ-TQQQ".to_string(),
+            date: "2022-03-09".to_string(),
+            order_count: 4,
+            loss_order_count: 1,
+            total_amount: 1000.0,
+            total_profit: 20.0,
+            config_sha: "sha".to_string(),
+            ..Default::default()
+        },
+        PairSummary {
+            pair: "SQQQ-TQQQ".to_string(),
+            date: "2022-03-10".to_string(),
+            order_count: 6,
+            loss_order_count: 4,
+            total_amount: 1000.0,
+            total_profit: -10.0,
+            config_sha: "sha".to_string(),
+            ..Default::default()
+        },
+    ];
+
+    let total = PairSummary::merge("Total", &summaries);
+    assert_eq!("SQQQ-TQQQ", total.pair);
+    assert_eq!("sha", total.config_sha);
+    assert_eq!(10, total.order_count);
+    assert_eq!(5, total.loss_order_count);
+    assert_eq!(50.0, total.loss_order_rate());
+    assert_eq!(0.5, total.profit_rate());
+
+    Ok(())
+}
+
+#[test]
+fn test_config_with_values() -> Result<()> {
+    let config = AppConfig::load("config.yaml")?;
+    let variant = config.with_values(&[
+        (
+            "trade.options.*.profitTakeRate".to_string(),
+            serde_json::json!(0.0123),
+        ),
+        (
+            "trade.flash.rules.0.deviations.0.value".to_string(),
+            serde_json::json!(0.0456),
+        ),
+    ])?;
+
+    assert!(variant
+        .trade
+        .options
+        .iter()
+        .all(|o| o.profit_take_rate == 0.0123));
+    assert_eq!(0.0456, variant.trade.flash.rules[0].deviations[0].value);
+    assert_ne!(config.sha()?, variant.sha()?);
+    assert_eq!(config.sha()?, config.with_values(&[])?.sha()?);
+    assert!(config
+        .with_values(&[("trade.unknown".to_string(), serde_json::json!(1))])
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_config_isolate() -> Result<()> {
+    let config = AppConfig::load("config.yaml")?;
+    config.extra_put("grafana_dashboard_id", "1");
+
+    // runtime values copied, not shared
+    let isolated = config.isolate();
+    assert_eq!(
+        Some("1".to_string()),
+        isolated.extra_get("grafana_dashboard_id")
+    );
+    isolated.extra_put("grafana_dashboard_id", "2");
+    assert_eq!(
+        Some("1".to_string()),
+        config.extra_get("grafana_dashboard_id")
+    );
+    // shallow clone shares runtime values
+    config.clone().extra_put("grafana_dashboard_id", "3");
+    assert_eq!(
+        Some("3".to_string()),
+        config.extra_get("grafana_dashboard_id")
+    );
+    assert_eq!(config.sha()?, isolated.sha()?);
+
+    Ok(())
+}
+
+#[test]
+fn test_unit_references() -> Result<()> {
+    let span = |unit: &str, from: usize, to: usize| UnitRef {
+        unit: unit.to_string(),
+        from,
+        to,
+    };
+
+    let config = AppConfig::load("config.yaml")?;
+    let rules = &config.trade.flash.rules;
+    // finest unit for price ranges, widest unit for trends
+    assert_eq!(span("m0010", 0, 7), rules[0].deviations[0].span);
+    assert_eq!(span("m0030", 0, 1), rules[0].trends[1].span);
+    assert_eq!(span("m0010", 0, 12), rules[1].lowers[0].span);
+    assert_eq!(span("m0010", 0, 7), rules[1].lowers[0].compare_span);
+
+    // re-resolved on variants
+    let variant = config.with_values(&[(
+        "trade.flash.rules.0.trends.2.from".to_string(),
+        serde_json::json!("m0020"),
+    )])?;
+    assert_eq!(
+        span("m0010", 2, 6),
+        variant.trade.flash.rules[0].trends[2].span
+    );
+
+    // typo, no unit divides, unknown prefix, reversed, out of unit periods
+    for (path, value) in [
+        ("trade.flash.rules.0.deviations.0.to", "mOO70"),
+        ("trade.flash.rules.0.deviations.0.to", "m0O70"),
+        ("trade.flash.rules.0.deviations.0.to", "m0075"),
+        ("trade.flash.rules.0.oscillations.0.to", "x0060"),
+        ("trade.flash.rules.0.trends.2.from", "m0120"),
+        ("trade.flash.rules.1.lowers.0.compareTo", "m7200"),
+    ] {
+        let result = config.with_values(&[(path.to_string(), serde_json::json!(value))]);
+        assert!(result.is_err(), "{} = {} should be rejected", path, value);
+    }
+    let result = config.with_values(&[(
+        "trade.flash.rules.0.indicators".to_string(),
+        serde_json::json!([{ "unit": "m0070", "name": "rsi", "max": 30 }]),
+    )]);
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_sweep_variants() -> Result<()> {
+    let spec = SweepSpec {
+        parameters: vec![
+            SweepParameter {
+                path: "trade.options.*.enableProfitTake".to_string(),
+                values: vec![serde_json::json!(true), serde_json::json!(false)],
+                range: None,
+            },
+            SweepParameter {
+                path: "trade.flash.lossMarginRate".to_string(),
+                values: Vec::new(),
+                range: Some(SweepRange {
+                    from: 0.001,
+                    to: 0.003,
+                    step: 0.001,
+                }),
+            },
+        ],
+    };
+
+    let variants = spec.variants()?;
+    assert_eq!(6, variants.len());
+    assert_eq!(
+        vec![
+            serde_json::json!(0.001),
+            serde_json::json!(0.002),
+            serde_json::json!(0.003)
+        ],
+        spec.parameters[1].candidates()?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_backtest_report() -> Result<()> {
+    let config = AppConfig::load("config.yaml")?;
+    let report = |date: &str, profit: f32| BacktestReport {
+        source: format!("split.tickers{}.json", date.replace("-", "")),
+        config_sha: "sha".to_string(),
+        config: config.clone(),
+        orders: vec![OrderRow {
+            pair: "SQQQ-TQQQ".to_string(),
+            date: date.to_string(),
+            profit,
+            ..Default::default()
+        }],
+        pairs: vec![PairSummary {
+            pair: "SQQQ-TQQQ".to_string(),
+            date: date.to_string(),
+            order_count: 1,
+            total_profit: profit,
+            ..Default::default()
+        }],
+        days: vec![PairSummary {
+            pair: "SQQQ-TQQQ".to_string(),
+            date: date.to_string(),
+            order_count: 1,
+            total_profit: profit,
+            ..Default::default()
+        }],
+        equity: Vec::new(),
+        metrics: Vec::new(),
+        rules: Vec::new(),
+        shadow_orders: Vec::new(),
+        shadows: Vec::new(),
+    };
+
+    let reports = vec![report("2022-03-09", 10.0), report("2022-03-10", -4.0)];
+    assert_eq!("2022-03-09", reports[0].summary().date);
+
+    let merged = BacktestReport::merge("total", &reports).unwrap();
+    assert_eq!("sha", merged.config_sha);
+    assert_eq!(2, merged.orders.len());
+    assert_eq!(2, merged.days.len());
+
+    let summary = merged.summary();
+    assert_eq!("", summary.date);
+    assert_eq!(2, summary.order_count);
+    assert_eq!(6.0, summary.total_profit);
+    assert!(BacktestReport::merge("total", &[]).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_pair_summary_metrics() -> Result<()> {
+    // one break-even order is neither win nor loss
+    let summary = PairSummary {
+        order_count: 5,
+        win_order_count: 3,
+        losing_order_count: 1,
+        gross_profit: 30.0,
+        gross_loss: 10.0,
+        exposure_time: 30,
+        session_time: 120,
+        ..Default::default()
+    };
+    assert_eq!(60.0, summary.win_rate());
+    assert_eq!(10.0, summary.average_win());
+    assert_eq!(10.0, summary.average_loss());
+    assert_eq!(Some(3.0), summary.profit_factor());
+    assert_eq!(25.0, summary.exposure());
+    assert_eq!(None, summary.sharpe());
+
+    // returns: 0.02, -0.01, 0.02
+    let summary = PairSummary {
+        return_count: 3,
+        return_sum: 0.03,
+        return_square_sum: 0.0009,
+        downside_square_sum: 0.0001,
+        ..Default::default()
+    };
+    assert!((summary.sharpe().unwrap() - 0.57735).abs() < 1e-4);
+    assert!((summary.sortino().unwrap() - 1.73205).abs() < 1e-4);
+
+    Ok(())
+}
+
+#[test]
+fn test_order_row_audit() -> Result<()> {
+    let row = OrderRow {
+        audit: AuditState::LossClear,
+        ..Default::default()
+    };
+    // audit written by variant name
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.serialize(&row)?;
+    let content = String::from_utf8(writer.into_inner().map_err(|e| e.to_string())?)?;
+    assert!(content.lines().nth(1).unwrap().contains(",LossClear,"));
+    assert_eq!(AuditState::Decline, OrderRow::default().audit);
+
+    Ok(())
+}
+
+#[test]
+fn test_rule_summary_attribute() -> Result<()> {
+    let row = |rule: &str, evaluations: &str, profit: f32| OrderRow {
+        rule: rule.to_string(),
+        evaluations: evaluations.to_string(),
+        profit,
+        ..Default::default()
+    };
+    let rows = vec![
+        row("flash#0", "flash#3", 10.0),
+        row("flash#0", "", -4.0),
+        row("slug#1", "flash#3,slug#2", 2.0),
+        row("", "", 5.0),
+    ];
+
+    let rules = RuleSummary::attribute(&rows);
+    assert_eq!(4, rules.len());
+    assert_eq!("flash#0", rules[0].rule);
+    assert!(!rules[0].evaluation);
+    assert_eq!(2, rules[0].order_count);
+    assert_eq!(1, rules[0].win_order_count);
+    assert_eq!(6.0, rules[0].total_profit);
+    assert_eq!("flash#3", rules[2].rule);
+    assert!(rules[2].evaluation);
+    assert_eq!(12.0, rules[2].total_profit);
+
+    let merged = RuleSummary::merge(&[rules.clone(), rules].concat());
+    assert_eq!(4, merged.len());
+    assert_eq!(4, merged[0].order_count);
+    assert_eq!(12.0, merged[0].total_profit);
+
+    Ok(())
+}
+
+#[test]
+fn test_trade_audit_modes() -> Result<()> {
+    let config = AppConfig::load("config.yaml")?;
+    let names: Vec<&str> = config.trade.modes().iter().map(|(name, _)| *name).collect();
+    assert_eq!(vec!["revert", "slug", "flash"], names);
+    assert_eq!(Some(AuditState::Flash), config.trade.flash.action("flash"));
+    assert_eq!(Some(30), config.trade.flash.cooldown("flash"));
+    assert_eq!(None, config.trade.slug.cooldown("slug"));
+
+    let variant = config.with_values(&[(
+        "trade.modes".to_string(),
+        serde_json::json!({
+            "exit": { "action": "EarlyClear", "priority": 1, "rules": [] },
+            "hold": { "action": "CloseTrade", "role": "Exit", "rules": [] },
+            "draft": { "rules": [] }
+        }),
+    )])?;
+    let names: Vec<&str> = variant
+        .trade
+        .modes()
+        .iter()
+        .map(|(name, _)| *name)
+        .collect();
+    assert_eq!(
+        vec!["exit", "hold", "draft", "revert", "slug", "flash"],
+        names
+    );
+    assert_eq!(
+        Some(AuditState::EarlyClear),
+        variant.trade.find_mode("exit").unwrap().action("exit")
+    );
+    assert_eq!(
+        None,
+        variant.trade.find_mode("draft").unwrap().action("draft")
+    );
+    assert_eq!(Some(AuditRole::Entry), config.trade.slug.role("slug"));
+    assert_eq!(
+        Some(AuditRole::Exit),
+        variant.trade.find_mode("exit").unwrap().role("exit")
+    );
+    assert_eq!(
+        Some(AuditRole::Exit),
+        variant.trade.find_mode("hold").unwrap().role("hold")
+    );
+    assert_eq!(
+        None,
+        variant.trade.find_mode("draft").unwrap().role("draft")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_asset_retention() -> Result<()> {
+    let config = with_units(serde_json::json!([
+        { "name": "m0010", "duration": 10, "period": 3 }
+    ]))?;
+    assert_eq!(
+        Some(Retention {
+            window: 30_000,
+            ticks: 0,
+            volume: 0
+        }),
+        config.retention_policy()
+    );
+    // configs without retention keep everything
+    assert!(!RetentionConfig::default().enabled);
+    let symbol = config.symbols().into_iter().next().unwrap();
+    let asset = AssetContext::new(Arc::new(config));
+
+    // one ticker per 5 seconds, turns to post market at last
+    for index in 0..100 {
+        let ticker = Ticker {
+            id: symbol.clone(),
+            price: index as f32,
+            time: 1646830800000 + index * 5_000,
+            quote_type: QuoteType::Etf,
+            market_hours: if index < 95 {
+                MarketHoursType::RegularMarket
+            } else {
+                MarketHoursType::PostMarket
+            },
+            day_volume: index * 100,
+            volume: None,
+            change: 0.0,
+            time_diff: 0,
+        };
+        assert!(asset.add_ticker(&ticker));
+
+        // finalized trade
+        let mut trade = TradeInfo::from(&ticker, index, 1, true);
+        trade.update_state("m0010", vec![0.0]);
+        asset.add_trade(&symbol, trade);
+    }
+
+    // tickers in 30 seconds kept
+    let tickers = asset.symbol_tickers(&symbol).unwrap().read().unwrap();
+    assert_eq!(7, tickers.len());
+    assert_eq!(1646830800000 + 93 * 5_000, tickers.back().unwrap().time);
+
+    // finalized trades evicted, indexed by message ID
+    assert_eq!(
+        7,
+        asset.symbol_trades(&symbol).unwrap().read().unwrap().len()
+    );
+    assert!(asset.search_trade(92).is_none());
+    assert_eq!(
+        99,
+        asset
+            .find_trade(&symbol, 99)
+            .unwrap()
+            .read()
+            .unwrap()
+            .message_id
+    );
+    assert!(asset.find_trade("NONE", 99).is_none());
+
+    // latest price of each minute kept
+    let marks = asset.price_marks(&symbol);
+    assert_eq!(10, marks.len());
+    assert_eq!((1646830800000, 0.0), marks[0]);
+    assert_eq!((1646830800000 + 12 * 5_000, 12.0), marks[1]);
+    assert_eq!(95.0, asset.get_first_post_ticker(&symbol).unwrap().price);
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_config() -> Result<()> {
+    let config = AppConfig::load("config.yaml")?;
+    assert!(config.clone().validate()?.is_empty());
+
+    // all errors reported at once, by path of value
+    let values: Vec<(String, serde_json::Value)> = vec![
+        ("units.1.name", serde_json::json!("m0010")),
+        ("trade.options.0.symbols", serde_json::json!(["QQQ"])),
+        ("trade.options.0.profitTakeRate", serde_json::json!(1.5)),
+        ("trade.flash.lossMarginRate", serde_json::json!(-0.1)),
+        ("trade.flash.rules.0.trends.0.down", serde_json::json!("1x")),
+        (
+            "trade.flash.rules.0.deviations.0.value",
+            serde_json::json!(2),
+        ),
+        (
+            "trade.flash.rules.0.indicators",
+            serde_json::json!([{ "unit": "m0060", "name": "macd", "index": 3 }]),
+        ),
+    ]
+    .into_iter()
+    .map(|(path, value)| (path.to_string(), value))
+    .collect();
+    let error = config.with_values(&values).unwrap_err().to_string();
+    for path in [
+        "units.1:",
+        "trade.options.0.symbols:",
+        "trade.options.0.profitTakeRate:",
+        "trade.flash.lossMarginRate:",
+        "trade.flash.rules.0.trends.0.down:",
+        "trade.flash.rules.0.deviations.0.value:",
+        "trade.flash.rules.0.indicators.0.index:",
+    ] {
+        assert!(error.contains(path), "{} not reported in {}", path, error);
+    }
+
+    // rules never fire
+    let variant = config.with_values(&[
+        (
+            "trade.flash.rules.0.trends.1".to_string(),
+            serde_json::json!({ "to": "m0010", "trend": "Downward" }),
+        ),
+        (
+            "trade.slug.rules.0.symbols".to_string(),
+            serde_json::json!(["QQQ"]),
+        ),
+    ])?;
+    let warnings = variant.clone().validate()?;
+    assert_eq!(2, warnings.len(), "{:?}", warnings);
+    for path in ["trade.flash.rules.0:", "trade.slug.rules.0:"] {
+        assert!(
+            warnings.iter().any(|w| w.starts_with(path)),
+            "{:?}",
+            warnings
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_layered_config() -> Result<()> {
+    let folder = std::env::temp_dir().join("sminer-layered-config");
+    std::fs::create_dir_all(&folder)?;
+    std::fs::copy("config.yaml", folder.join("config.yaml"))?;
+    std::fs::write(
+        folder.join("config.test.yaml"),
+        "trade:\n  flash:\n    lossMarginRate: 0.004\nretention:\n  margin: 30\n",
+    )?;
+    let file = folder.join("config.yaml");
+    let file = file.to_str().unwrap();
+
+    // overlay merged into base, overrides win
+    let overrides = vec![parse_override("retention.margin=45")?];
+    let config = AppConfig::load_layered(file, Some("test"), &overrides)?;
+    assert_eq!(Some(0.004), config.trade.flash.loss_margin_rate);
+    assert!(!config.trade.flash.rules.is_empty());
+    assert_eq!(45, config.retention.margin);
+    assert_eq!(file, config.switches.config_file);
+    assert!(AppConfig::load_layered(file, Some("unknown"), &[]).is_err());
+
+    // environment variables matched ignoring case and underscores
+    let root = serde_json::to_value(&config)?;
+    let vars = [
+        ("SMINER_TRADE__FLASH__LOSS_MARGIN_RATE", "0.006"),
+        ("SMINER_DATA_SOURCE__MONGODB__ENABLED", "true"),
+        ("SMINER_UNITS__0__PERIOD", "30"),
+        ("SMINER_PROFILE", "test"),
+        ("SMINER_HOME", "/opt/sminer"),
+        ("HOME", "/root"),
+    ];
+    // unknown variables skipped
+    let values = env_values(
+        &root,
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+    );
+    assert_eq!(
+        vec![
+            (
+                "dataSource.mongodb.enabled".to_string(),
+                serde_json::json!(true)
+            ),
+            (
+                "trade.flash.lossMarginRate".to_string(),
+                serde_json::json!(0.006)
+            ),
+            ("units.0.period".to_string(), serde_json::json!(30)),
+        ],
+        values
+    );
+    let unknown = [("SMINER_TRADE__UNKNOWN".to_string(), "1".to_string())];
+    assert!(env_values(&root, unknown.into_iter()).is_empty());
+
+    // value taken as string when not JSON
+    assert_eq!(
+        (
+            "tickers.symbols.0.bull.id".to_string(),
+            serde_json::json!("TQQQ=X")
+        ),
+        parse_override("tickers.symbols.0.bull.id=TQQQ=X")?
+    );
+    assert!(parse_override("trade.enabled").is_err());
+
+    std::fs::remove_dir_all(&folder)?;
+    Ok(())
+}
+
+#[test]
+fn test_reload_trade() -> Result<()> {
+    let folder = std::env::temp_dir().join("sminer-reload-trade");
+    std::fs::create_dir_all(&folder)?;
+    let file = folder.join("config.yaml");
+    let content = std::fs::read_to_string("config.yaml")?;
+    std::fs::write(&file, &content)?;
+
+    let context = AppContext::new(AppConfig::load(file.to_str().unwrap())?);
+    let sha = context.config().sha()?;
+    assert_eq!(None, context.reload_trade()?);
+
+    // rules swapped, units kept until restart
+    let changed = content
+        .replacen("lossMarginRate: 0.005", "lossMarginRate: 0.004", 1)
+        .replacen("period: 360", "period: 180", 1);
+    std::fs::write(&file, &changed)?;
+    let reloaded = context.reload_trade()?;
+    assert!(reloaded.is_some());
+    assert_ne!(Some(sha), reloaded);
+    let config = context.config();
+    assert_eq!(Some(0.004), config.trade.flash.loss_margin_rate);
+    assert_eq!(360, config.units[0].period);
+    assert_eq!(reloaded, Some(config.sha()?));
+
+    // invalid rules rejected, running ones kept
+    std::fs::write(&file, changed.replacen("to: m0070", "to: m0075", 1))?;
+    assert!(context.reload_trade().is_err());
+    assert_eq!(reloaded, Some(context.config().sha()?));
+
+    std::fs::remove_dir_all(&folder)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_restore_session() -> Result<()> {
+    let file = std::env::temp_dir().join("sminer-session.json");
+    let file = file.to_str().unwrap();
+    let _ = std::fs::remove_file(file);
+    let config = AppConfig::load("config.yaml")?.with_values(&[
+        ("snapshot.enabled".to_string(), serde_json::json!(true)),
+        ("snapshot.file".to_string(), serde_json::json!(file)),
+    ])?;
+    let symbols: Vec<String> = config.symbols().into_iter().take(2).collect();
+
+    // nothing to restore on first start
+    let context = AppContext::new(config.clone());
+    assert!(!context.restore_session().await?);
+
+    let start = Utc::now().timestamp_millis() - 600_000;
+    for index in 0..120 {
+        for (offset, symbol) in symbols.iter().enumerate() {
+            let mut ticker = Ticker {
+                id: symbol.clone(),
+                price: 10.0 + (index % 17) as f32 * 0.1 - offset as f32,
+                time: start + index * 3_000,
+                quote_type: QuoteType::Etf,
+                market_hours: MarketHoursType::RegularMarket,
+                day_volume: index * (100 + offset as i64),
+                volume: None,
+                change: 0.0,
+                time_diff: 0,
+            };
+            let message_id = context.asset().next_message_id();
+            context.dispatch_direct(&mut ticker, message_id).await?;
+        }
+    }
+    let asset = context.asset();
+    asset.set_regular_start_time(start);
+    SessionSnapshot::take(&asset).write(file)?;
+
+    // state rebuilt by new process
+    let restored = AppContext::new(config);
+    assert!(restored.restore_session().await?);
+    let target = restored.asset();
+    assert_eq!(start, target.get_regular_start_time());
+    assert!(target.sequence() >= asset.sequence());
+    assert_eq!(
+        serde_json::to_string(&*asset.orders().read().unwrap())?,
+        serde_json::to_string(&*target.orders().read().unwrap())?
+    );
+    for symbol in symbols.iter() {
+        let tickers: Vec<(i64, Option<i64>)> = asset
+            .symbol_tickers(symbol)
+            .unwrap()
+            .read()
+            .unwrap()
+            .iter()
+            .map(|t| (t.time, t.volume))
+            .collect();
+        let restored_tickers: Vec<(i64, Option<i64>)> = target
+            .symbol_tickers(symbol)
+            .unwrap()
+            .read()
+            .unwrap()
+            .iter()
+            .map(|t| (t.time, t.volume))
+            .collect();
+        assert_eq!(tickers, restored_tickers);
+        for unit in context
+            .config()
+            .time_units()
+            .iter()
+            .filter(|u| u.period > 0)
+        {
+            let protfolios = asset.get_protfolios(symbol, &unit.name).unwrap();
+            let restored_protfolios = target.get_protfolios(symbol, &unit.name).unwrap();
+            assert_eq!(
+                *protfolios.read().unwrap(),
+                *restored_protfolios.read().unwrap()
+            );
+        }
+    }
+
+    std::fs::remove_file(file)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_backfill_session() -> Result<()> {
+    let file = std::env::temp_dir().join("sminer-backfill.json");
+    let file = file.to_str().unwrap();
+    let _ = std::fs::remove_file(file);
+    let config = AppConfig::load("config.yaml")?.with_values(&[
+        ("snapshot.enabled".to_string(), serde_json::json!(true)),
+        ("snapshot.file".to_string(), serde_json::json!(file)),
+        ("snapshot.backfill".to_string(), serde_json::json!(true)),
+    ])?;
+    let symbols: Vec<String> = config.symbols().into_iter().take(2).collect();
+    let ticker = |symbol: &str, time: i64, index: i64| Ticker {
+        id: symbol.to_string(),
+        price: 10.0 + index as f32 * 0.1,
+        time,
+        quote_type: QuoteType::Etf,
+        market_hours: MarketHoursType::RegularMarket,
+        day_volume: index * 100,
+        volume: None,
+        change: 0.0,
+        time_diff: 0,
+    };
+
+    // only the first symbol has tickers before snapshot
+    let start = Utc::now().timestamp_millis() - 600_000;
+    let context = AppContext::new(config.clone());
+    for index in 0..10 {
+        let mut ticker = ticker(&symbols[0], start + index * 3_000, index);
+        let message_id = context.asset().next_message_id();
+        context.dispatch_direct(&mut ticker, message_id).await?;
+    }
+    SessionSnapshot::take(&context.asset()).write(file)?;
+
+    // stored by previous process after snapshot taken
+    let source = Arc::new(MemorySink::default());
+    let stored: Vec<Ticker> = (10..20)
+        .flat_map(|index| {
+            symbols
+                .iter()
+                .map(|s| ticker(s, start + index * 3_000, index))
+                .collect::<Vec<Ticker>>()
+        })
+        .collect();
+    source.save_tickers(&stored).await?;
+
+    let restored = AppContext::new(config);
+    restored.set_source(source);
+    assert!(restored.restore_session().await?);
+    let target = restored.asset();
+    assert_eq!(
+        20,
+        target
+            .symbol_tickers(&symbols[0])
+            .unwrap()
+            .read()
+            .unwrap()
+            .len()
+    );
+    assert_eq!(
+        10,
+        target
+            .symbol_tickers(&symbols[1])
+            .unwrap()
+            .read()
+            .unwrap()
+            .len()
+    );
+    assert_eq!(
+        start + 19 * 3_000,
+        target.get_latest_ticker(&symbols[1]).unwrap().time
+    );
+
+    std::fs::remove_file(file)?;
+    Ok(())
+}
+
+#[test]
+fn test_order_events() -> Result<()> {
+    let config = AppConfig::load("config.yaml")?;
+    let symbol = config.symbols().into_iter().next().unwrap();
+    let asset = AssetContext::new(Arc::new(config));
+    let rival_symbol = asset.find_rival_symbol(&symbol).unwrap();
+    let mut rx = asset.subscribe_orders();
+
+    let order = Order::new(&symbol, 10.0, 20.0, 5, 1000, AuditState::Flash, None);
+    assert!(asset.add_order(order.clone()));
+    // running order of symbol exists
+    assert!(!asset.add_order(order.clone()));
+    let rival_order = Order::new(&rival_symbol, 20.0, 10.0, 3, 2000, AuditState::Flash, None);
+    assert!(asset.add_order(rival_order.clone()));
+    asset.write_off(&rival_order);
+
+    let events: Vec<OrderEvent> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+    let summary: Vec<(String, OrderStatus, i64)> = events
+        .iter()
+        .map(|e| (e.order.id.clone(), e.status.clone(), e.time))
+        .collect();
+    assert_eq!(
+        vec![
+            (order.id.clone(), OrderStatus::Init, 1000),
+            (rival_order.id.clone(), OrderStatus::Init, 2000),
+            (order.id.clone(), OrderStatus::WriteOff, 2000),
+            (rival_order.id.clone(), OrderStatus::WriteOff, 2000),
+        ],
+        summary
+    );
+    // paired orders share constraint ID
+    assert!(events[2].order.constraint_id.is_some());
+    assert_eq!(events[2].order.constraint_id, events[3].order.constraint_id);
+    Ok(())
+}
+
+// Config of units without margin, audit rules referencing default units are removed
+fn with_units(units: serde_json::Value) -> Result<AppConfig> {
+    let mut values = vec![
+        ("units".to_string(), units),
+        ("retention.margin".to_string(), serde_json::json!(0)),
+    ];
+    for mode in ["flash", "slug", "revert"] {
+        values.push((format!("trade.{}.rules", mode), serde_json::json!([])));
+    }
+    AppConfig::load("config.yaml")?.with_values(&values)
+}
+
+// Count of tickers kept after 100 tickers, one per 5 seconds with 100 traded volume
+fn count_retained_tickers(units: serde_json::Value) -> Result<usize> {
+    let config = with_units(units)?;
+    let symbol = config.symbols().into_iter().next().unwrap();
+    let asset = AssetContext::new(Arc::new(config));
+    for index in 0..100 {
+        asset.add_ticker(&Ticker {
+            id: symbol.clone(),
+            price: index as f32,
+            time: 1646830800000 + index * 5_000,
+            quote_type: QuoteType::Etf,
+            market_hours: MarketHoursType::RegularMarket,
+            day_volume: index * 100,
+            volume: Some(100),
+            change: 0.0,
+            time_diff: 0,
+        });
+    }
+    let count = asset.symbol_tickers(&symbol).unwrap().read().unwrap().len();
+    Ok(count)
+}
+
+#[test]
+fn test_asset_retention_activity() -> Result<()> {
+    let time = serde_json::json!({ "name": "m0010", "duration": 10, "period": 3 });
+    assert_eq!(7, count_retained_tickers(serde_json::json!([time]))?);
+
+    // 3 bars of 4 tickers
+    let tick = serde_json::json!({ "name": "t0004", "duration": 4, "period": 3, "bar": "Tick" });
+    assert_eq!(12, count_retained_tickers(serde_json::json!([time, tick]))?);
+
+    // 3 bars of 500 traded volume, volume of the oldest ticker is not counted
+    let volume =
+        serde_json::json!({ "name": "v0500", "duration": 500, "period": 3, "bar": "Volume" });
+    assert_eq!(
+        15,
+        count_retained_tickers(serde_json::json!([time, tick, volume]))?
+    );
+
+    Ok(())
+}